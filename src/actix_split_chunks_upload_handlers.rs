@@ -1,62 +1,357 @@
-use futures::{ StreamExt };
+use bytes::{Buf, BytesMut};
+use futures::{ stream, StreamExt };
+use serde::Serialize;
 
 use crate::split_chunks_upload_operations_raw::{
+    create_upload_session_raw,
     split_chunks_upload_raw,
     file_chunks_merge_raw,
+    CreateUploadSession,
+    ConflictPolicy,
     get_uploaded_chunks_hashes_raw,
-    MAX_SIZE,
+    get_upload_session_status_raw,
+    reuse_stored_chunks_raw,
+    start_merge_job_raw,
+    get_merge_job_status_raw,
+    max_chunk_size,
+    remove_upload_session_raw,
+    session_key,
+    MergeChunksRequest,
+    ReuseChunksRequest,
+    UploadChunkRequest,
+};
+
+use crate::instant_upload::{
+    create_instant_upload_challenge_raw,
+    instant_upload_raw,
+    stored_file_scope,
+    InstantUploadAnswer,
+    InstantUploadRequest,
 };
 
 use actix_web::{web, HttpRequest};
+use urlencoding::decode;
+
+use crate::chunk_hash::{normalize_hash, HashAlgorithm, SUPPORTED_HASH_ALGORITHMS};
+
+use crate::actix_utils::{get_header, get_required_header};
+
+use crate::post_merge::get_post_merge_status_raw;
+
+use crate::upload_policy::{prepare_merge_target, UploadPolicy};
+
+// 请求头中的 identify 加上服务名
+fn identify_header<P: UploadPolicy>(req: &HttpRequest) -> Result<String, String> {
+    Ok(session_key(P::SERVICE, get_required_header(req, "identify")?))
+}
 
-use crate::actix_utils::{get_header, get_headers};
+// 可选, 默认 md5
+fn hash_algorithm_header(req: &HttpRequest) -> Result<HashAlgorithm, String> {
+    get_header(req, "hashAlgorithm").map_or(Ok(HashAlgorithm::default()), HashAlgorithm::from_name)
+}
+
+// 可选, 保存路径已有文件时的处理方式
+fn conflict_policy_header(req: &HttpRequest) -> Result<ConflictPolicy, String> {
+    get_header(req, "conflictPolicy").map_or(Ok(ConflictPolicy::default()), ConflictPolicy::from_name)
+}
+
+fn merge_chunks_request<P: UploadPolicy>(
+    req: &HttpRequest,
+    owner: Option<String>,
+    max_file_size: Option<u64>,
+) -> Result<MergeChunksRequest, Box<dyn std::error::Error>> {
+    Ok(MergeChunksRequest {
+        identify: identify_header::<P>(req)?,
+        owner,
+        // 通过创建会话接口声明了文件名时可省略
+        full_path: match get_header(req, "fullPath").filter(|full_path| !full_path.is_empty()) {
+            Some(full_path) => Some(decode(full_path)?.into_owned()),
+            None => None,
+        },
+        // 可选, 整个文件的 hash
+        file_hash: get_header(req, "fileHash").filter(|file_hash| !file_hash.is_empty()).map(String::from),
+        conflict_policy: conflict_policy_header(req)?,
+        max_file_size,
+    })
+}
+
+// 服务端接受的 chunk hash 算法列表
+pub fn get_chunk_hash_algorithms() -> Result<String, Box<dyn std::error::Error>> {
+    Ok(serde_json::to_string(&SUPPORTED_HASH_ALGORITHMS)?)
+}
 
-pub async fn get_uploaded_chunks_hashes(
-    req: HttpRequest,
+// 创建上传会话, 返回服务端生成的会话 id, 之后作为 identify 使用
+// 会话创建者之后上传, 查询, 合并和中止时需要一致
+pub async fn create_upload_session_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+    request: CreateUploadSession,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    let identify = get_header(&req, "identify")
-        .ok_or_else(|| String::from("request header identify not found or invalid"))?;
-    Ok(get_uploaded_chunks_hashes_raw(identify).await)
+    let grant = policy.authorize(req).await?;
+    if let Some(max_file_size) = policy.max_file_size(&grant) {
+        if request.file_size > max_file_size {
+            return Err(format!("file is larger than {} bytes", max_file_size).into());
+        }
+    }
+    let session_id = format!("{:032x}", rand::random::<u128>());
+    let owner = policy.owner(&grant);
+    create_upload_session_raw(&session_key(P::SERVICE, &session_id), request, owner.as_deref()).await?;
+    Ok(session_id)
 }
 
-pub async fn split_chunks_upload_handler(
-    req: HttpRequest,
+pub async fn get_uploaded_chunks_hashes<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let grant = policy.authorize(req).await?;
+    let owner = policy.owner(&grant);
+    Ok(get_uploaded_chunks_hashes_raw(&identify_header::<P>(req)?, owner.as_deref()).await)
+}
+
+// 查询会话已上传的 chunk 和过期时间
+pub async fn get_upload_session_status_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let grant = policy.authorize(req).await?;
+    let owner = policy.owner(&grant);
+    let status = get_upload_session_status_raw(&identify_header::<P>(req)?, owner.as_deref())
+        .await
+        .ok_or_else(|| String::from("upload session not found"))?;
+    Ok(serde_json::to_string(&status)?)
+}
+
+pub async fn split_chunks_upload_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+    payload: web::Payload,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let grant = policy.authorize(req).await?;
+    let request = UploadChunkRequest {
+        identify: identify_header::<P>(req)?,
+        chunk_hash: String::from(get_required_header(req, "chunkHash")?),
+        chunk_index: get_required_header(req, "chunkIndex")?.parse::<usize>()?,
+        chunks_number: get_required_header(req, "chunksNumber")?.parse::<usize>()?,
+        hash_algorithm: hash_algorithm_header(req)?,
+    };
+    // 文件内容以流的形式交给下层写入
+    let chunk_content = payload.map(|chunk| chunk.map_err(|err| err.into()));
+    split_chunks_upload_raw(request, Box::pin(chunk_content), policy.owner(&grant).as_deref()).await
+}
+
+// 批量上传中单个 chunk 的结果, error 为 null 表示已校验并保存
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChunkUploadResult {
+    chunk_index: usize,
+    chunk_hash: String,
+    error: Option<String>,
+}
+
+// 读取请求体直到缓冲区至少有 length 字节, 请求体提前结束时返回 false
+async fn fill_buffer(
+    payload: &mut web::Payload,
+    buffer: &mut BytesMut,
+    length: usize,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    while buffer.len() < length {
+        match payload.next().await {
+            Some(bytes) => buffer.extend_from_slice(&bytes?),
+            None => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+// 跳过 length 字节, 不读入内存
+async fn skip_payload(
+    payload: &mut web::Payload,
+    buffer: &mut BytesMut,
+    mut length: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let skipped = length.min(buffer.len());
+        buffer.advance(skipped);
+        length -= skipped;
+        if length == 0 {
+            return Ok(());
+        }
+        match payload.next().await {
+            Some(bytes) => buffer.extend_from_slice(&bytes?),
+            None => return Err("batch body is truncated".into()),
+        }
+    }
+}
+
+// 一个请求上传多个 chunk, 请求体为连续的 chunk:
+// 4 字节大端序号, 1 字节 hash 长度, hash, 4 字节大端内容长度, 内容
+// 每个 chunk 单独校验, 失败不影响其他 chunk, 响应每个 chunk 的结果
+pub async fn split_chunks_batch_upload_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
     mut payload: web::Payload,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    // parse header
-    let headers = get_headers(
-        &req,
-        vec![
-            String::from("identify"),
-            String::from("chunkHash"),
-            String::from("chunkIndex"),
-            String::from("chunksNumber"),
-        ],
-    )?;
-    let p2 = Box::pin(async move {
-        // 获得文件内容
-        let mut chunk_content = web::BytesMut::new();
-
-        while let Some(chunk) = payload.next().await {
-            let chunk = chunk?;
-            if (chunk_content.len() + chunk.len()) > MAX_SIZE {
-                return Err("overflow".into());
-            }
-            chunk_content.extend_from_slice(&chunk);
+    let grant = policy.authorize(req).await?;
+    let owner = policy.owner(&grant);
+    let identify = identify_header::<P>(req)?;
+    let chunks_number = get_required_header(req, "chunksNumber")?.parse::<usize>()?;
+    let hash_algorithm = hash_algorithm_header(req)?;
+
+    let mut buffer = BytesMut::new();
+    let mut results = vec![];
+    while fill_buffer(&mut payload, &mut buffer, 1).await? {
+        let hash_length = match fill_buffer(&mut payload, &mut buffer, 5).await? {
+            true => buffer[4] as usize,
+            false => return Err("batch body is truncated".into()),
+        };
+        if !fill_buffer(&mut payload, &mut buffer, 5 + hash_length + 4).await? {
+            return Err("batch body is truncated".into());
         }
-        Ok(chunk_content)
-    });
-    split_chunks_upload_raw(headers, p2).await
+        let chunk_index = buffer.get_u32() as usize;
+        buffer.advance(1);
+        let chunk_hash = String::from_utf8(buffer.split_to(hash_length).to_vec())?;
+        let content_length = buffer.get_u32() as usize;
+
+        // 超过单个 chunk 的最大尺寸时不读取内容
+        let max_chunk_size = max_chunk_size();
+        if content_length > max_chunk_size {
+            skip_payload(&mut payload, &mut buffer, content_length).await?;
+            results.push(ChunkUploadResult {
+                chunk_index,
+                chunk_hash: normalize_hash(&chunk_hash),
+                error: Some(format!("chunk is larger than {} bytes", max_chunk_size)),
+            });
+            continue;
+        }
+        if !fill_buffer(&mut payload, &mut buffer, content_length).await? {
+            return Err("batch body is truncated".into());
+        }
+        let content = buffer.split_to(content_length).freeze();
+
+        let request = UploadChunkRequest {
+            identify: identify.clone(),
+            chunk_hash: chunk_hash.clone(),
+            chunk_index,
+            chunks_number,
+            hash_algorithm,
+        };
+        let chunk_content = stream::once(async move { Ok(content) });
+        let result = split_chunks_upload_raw(request, Box::pin(chunk_content), owner.as_deref()).await;
+        results.push(ChunkUploadResult {
+            chunk_index,
+            chunk_hash: normalize_hash(&chunk_hash),
+            error: result.err().map(|err| err.to_string()),
+        });
+    }
+    Ok(serde_json::to_string(&results)?)
+}
+
+// 引用服务端已存储的 chunk, 返回会话当前的 chunk hash 列表
+pub async fn reuse_stored_chunks_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+    chunks_hash: Vec<String>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let grant = policy.authorize(req).await?;
+    let request = ReuseChunksRequest {
+        identify: identify_header::<P>(req)?,
+        chunks_number: get_required_header(req, "chunksNumber")?.parse::<usize>()?,
+        hash_algorithm: hash_algorithm_header(req)?,
+        chunks_hash,
+    };
+    reuse_stored_chunks_raw(request, policy.owner(&grant).as_deref()).await
+}
+
+// 中止上传, 立即删除会话的所有 chunk 并取消定时清理
+pub async fn abort_upload_session_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let grant = policy.authorize(req).await?;
+    remove_upload_session_raw(&identify_header::<P>(req)?, policy.owner(&grant).as_deref()).await?;
+    Ok(String::from("true"))
+}
+
+// 合并后响应合并目标的结果
+pub async fn file_chunks_merge_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let grant = policy.authorize(req).await?;
+    let request = merge_chunks_request::<P>(req, policy.owner(&grant), policy.max_file_size(&grant))?;
+    let target = prepare_merge_target(policy, grant, request.identify.clone()).await;
+    file_chunks_merge_raw(request, &target).await
+}
+
+// 后台合并, 立即返回任务 id, 任务成功后的结果为合并目标的结果
+pub async fn file_chunks_merge_job_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let grant = policy.authorize(req).await?;
+    let request = merge_chunks_request::<P>(req, policy.owner(&grant), policy.max_file_size(&grant))?;
+    let target = prepare_merge_target(policy, grant, request.identify.clone()).await;
+
+    let job_id = format!("{:032x}", rand::random::<u128>());
+    start_merge_job_raw(session_key(P::SERVICE, &job_id), request, Box::new(target)).await?;
+    Ok(job_id)
+}
+
+// 查询合并任务的进度和结果
+pub async fn get_merge_job_status_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let grant = policy.authorize(req).await?;
+    let owner = policy.owner(&grant);
+    let job_id = get_required_header(req, "jobId")?;
+    let status = get_merge_job_status_raw(&session_key(P::SERVICE, job_id), owner.as_deref())
+        .ok_or_else(|| String::from("merge job not found"))?;
+    Ok(serde_json::to_string(&status)?)
+}
+
+// 查询合并后处理步骤的进度
+pub async fn get_post_merge_status_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let grant = policy.authorize(req).await?;
+    let owner = policy.owner(&grant);
+    let status = get_post_merge_status_raw(&identify_header::<P>(req)?, owner.as_deref())
+        .ok_or_else(|| String::from("post-merge pipeline not found"))?;
+    Ok(serde_json::to_string(&status)?)
+}
+
+// 秒传检查, 文件已存在时返回 challenge
+pub async fn create_instant_upload_challenge_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+    request: InstantUploadRequest,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let grant = policy.authorize(req).await?;
+    let challenge_id = format!("{:032x}", rand::random::<u128>());
+    let scope = stored_file_scope(P::SERVICE, policy.owner(&grant).as_deref());
+    let mut challenge =
+        create_instant_upload_challenge_raw(&session_key(P::SERVICE, &challenge_id), &scope, request).await?;
+    // 响应中不带服务名
+    challenge.challenge_id = challenge.challenge_id.map(|_| challenge_id);
+    Ok(serde_json::to_string(&challenge)?)
 }
 
-pub async fn file_chunks_merge_handler(
-    req: HttpRequest,
-    // 覆盖保存地址的函数
-    rewrite_save_path_fn: Option<Box<dyn Fn(&str, String) -> String>>,
+// 请求体为 challenge 要求的文件内容, 通过后响应合并目标的结果
+pub async fn instant_upload_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+    answer: web::Bytes,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    let headers = get_headers(
-        &req,
-        vec![String::from("identify"), String::from("fullPath")],
-    )?;
-    file_chunks_merge_raw(headers, rewrite_save_path_fn).await
+    let grant = policy.authorize(req).await?;
+    let request = InstantUploadAnswer {
+        challenge_id: session_key(P::SERVICE, get_required_header(req, "challengeId")?),
+        scope: stored_file_scope(P::SERVICE, policy.owner(&grant).as_deref()),
+        full_path: decode(get_required_header(req, "fullPath")?)?.into_owned(),
+        conflict_policy: conflict_policy_header(req)?,
+        max_file_size: policy.max_file_size(&grant),
+    };
+    let target = prepare_merge_target(policy, grant, request.challenge_id.clone()).await;
+    instant_upload_raw(request, &answer, &target).await
 }
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::time::{Duration, UNIX_EPOCH};
+
+use actix_web::{
+    http::{header::HttpDate, StatusCode},
+    web, HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use base64::{engine::general_purpose, Engine as _};
+use futures::StreamExt;
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+use crate::actix_utils::get_header;
+use crate::chunk_hash::{HashAlgorithm, SUPPORTED_HASH_ALGORITHMS};
+use crate::split_chunks_upload_operations_raw::{
+    append_chunk_raw,
+    create_append_session_raw,
+    file_chunks_merge_raw,
+    get_append_status_raw,
+    remove_upload_session_raw,
+    session_key,
+    AppendError,
+    AppendStatus,
+    ConflictPolicy,
+    MergeChunksRequest,
+    MergeTarget,
+    MAX_SIZE,
+};
+use crate::upload_policy::{prepare_merge_target, UploadPolicy};
+
+use lazy_static::lazy_static;
+
+// 支持的 tus 协议版本, 除 OPTIONS 外的请求都必须带上
+const TUS_VERSION: &str = "1.0.0";
+
+const TUS_EXTENSIONS: &str = "creation,termination,checksum,expiration";
+
+// 上传完成后保留结果1小时, 客户端重试 HEAD / PATCH 时仍能拿到
+const TUS_RESULT_SURVIVAL_TIME: Duration = Duration::from_secs(3600);
+
+struct TusResult {
+    // 上传的创建者, 只有创建者可以查询和删除结果
+    owner: Option<String>,
+    length: u64,
+    result: String,
+    expires_at: Instant,
+}
+
+lazy_static! {
+    static ref TUS_RESULTS: Mutex<HashMap<String, TusResult>> = Mutex::new(HashMap::new());
+}
+
+// tus 上传在 chunk 会话中的 identify, 与自定义协议的 identify 区分开
+fn tus_identify(service: &str, upload_id: &str) -> String {
+    session_key(service, &format!("tus-{}", upload_id))
+}
+
+fn tus_response(status: StatusCode) -> HttpResponseBuilder {
+    let mut builder = HttpResponse::build(status);
+    builder.insert_header(("Tus-Resumable", TUS_VERSION));
+    builder
+}
+
+// 带有 tus 版本头的错误响应
+fn tus_error(status: StatusCode, message: impl ToString) -> HttpResponse {
+    tus_response(status).body(message.to_string())
+}
+
+fn upload_expires(expires_at: u64) -> String {
+    HttpDate::from(UNIX_EPOCH + Duration::from_secs(expires_at)).to_string()
+}
+
+fn completed_result(identify: &str, owner: Option<&str>) -> Option<(u64, String)> {
+    let results = TUS_RESULTS.lock();
+    let result = results
+        .get(identify)
+        .filter(|result| result.expires_at > Instant::now() && is_result_owner(result, owner))?;
+    Some((result.length, result.result.clone()))
+}
+
+fn is_result_owner(result: &TusResult, owner: Option<&str>) -> bool {
+    result.owner.is_none() || result.owner.as_deref() == owner
+}
+
+fn save_result(identify: &str, owner: Option<&str>, length: u64, result: String) {
+    let now = Instant::now();
+    let mut results = TUS_RESULTS.lock();
+    // 顺便清除过期的结果
+    results.retain(|_, result| result.expires_at > now);
+    results.insert(
+        String::from(identify),
+        TusResult {
+            owner: owner.map(String::from),
+            length,
+            result,
+            expires_at: now + TUS_RESULT_SURVIVAL_TIME,
+        },
+    );
+}
+
+// 版本不匹配时返回 412
+fn check_version(req: &HttpRequest) -> Result<(), HttpResponse> {
+    match get_header(req, "Tus-Resumable") {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(tus_response(StatusCode::PRECONDITION_FAILED)
+            .insert_header(("Tus-Version", TUS_VERSION))
+            .body("unsupported tus version")),
+    }
+}
+
+// Upload-Metadata 为逗号分隔的 "key base64(value)", 取 filename 作为文件名
+fn parse_file_name(metadata: &str) -> Option<String> {
+    metadata.split(',').find_map(|pair| {
+        let mut parts = pair.trim().splitn(2, ' ');
+        let key = parts.next()?;
+        if key != "filename" && key != "name" {
+            return None;
+        }
+        let value = general_purpose::STANDARD.decode(parts.next()?.trim()).ok()?;
+        let file_name = String::from_utf8(value).ok()?;
+        // 只保留文件名部分
+        let file_name = file_name.rsplit(['/', '\\']).next()?.trim();
+        (!file_name.is_empty() && file_name != "." && file_name != "..").then(|| String::from(file_name))
+    })
+}
+
+// Upload-Checksum 为 "算法 base64(hash)"
+fn parse_checksum(checksum: &str) -> Result<(HashAlgorithm, String), String> {
+    let (name, hash) = checksum
+        .split_once(' ')
+        .ok_or_else(|| String::from("invalid Upload-Checksum"))?;
+    let hash_algorithm = HashAlgorithm::from_name(name)?;
+    let hash = general_purpose::STANDARD
+        .decode(hash.trim())
+        .map_err(|_| String::from("invalid Upload-Checksum"))?;
+    let hash = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok((hash_algorithm, hash))
+}
+
+// 服务端支持的协议版本和扩展
+pub fn tus_options_handler() -> HttpResponse {
+    let checksum_algorithms: Vec<&str> = SUPPORTED_HASH_ALGORITHMS.iter().map(|algorithm| algorithm.name()).collect();
+    HttpResponse::NoContent()
+        .insert_header(("Tus-Resumable", TUS_VERSION))
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", TUS_EXTENSIONS))
+        .insert_header(("Tus-Max-Size", MAX_SIZE.to_string()))
+        .insert_header(("Tus-Checksum-Algorithm", checksum_algorithms.join(",")))
+        .finish()
+}
+
+// 合并完成的上传, 返回编码后的合并目标结果, 以便放在响应头中
+async fn finish_upload(
+    identify: &str,
+    owner: Option<&str>,
+    length: u64,
+    target: &dyn MergeTarget,
+) -> Result<String, String> {
+    let request = MergeChunksRequest {
+        identify: String::from(identify),
+        owner: owner.map(String::from),
+        full_path: None,
+        file_hash: None,
+        conflict_policy: ConflictPolicy::default(),
+        // 创建时已检查
+        max_file_size: None,
+    };
+    let result = match file_chunks_merge_raw(request, target).await {
+        Ok(result) => urlencoding::encode(&result).into_owned(),
+        // 并发的请求已经合并完成
+        Err(err) => {
+            return completed_result(identify, owner)
+                .map(|(_, result)| result)
+                .ok_or_else(|| err.to_string())
+        }
+    };
+    save_result(identify, owner, length, result.clone());
+    Ok(result)
+}
+
+fn completed_response(status: StatusCode, length: u64, result: String) -> HttpResponse {
+    tus_response(status)
+        .insert_header(("Upload-Offset", length.to_string()))
+        .insert_header(("Upload-Length", length.to_string()))
+        .insert_header(("Upload-Result", result))
+        .insert_header(("Cache-Control", "no-store"))
+        .finish()
+}
+
+// 版本正确后鉴权, 失败时返回 400
+async fn authorize<P: UploadPolicy>(policy: &P, req: &HttpRequest) -> Result<P::Grant, HttpResponse> {
+    check_version(req)?;
+    policy.authorize(req).await.map_err(|err| tus_error(StatusCode::BAD_REQUEST, err))
+}
+
+// creation 扩展, Location 为之后 HEAD / PATCH / DELETE 的地址
+// 上传的创建者之后终止上传时需要一致
+pub async fn tus_create_handler<P: UploadPolicy>(policy: &P, req: &HttpRequest) -> HttpResponse {
+    let grant = match authorize(policy, req).await {
+        Ok(grant) => grant,
+        Err(response) => return response,
+    };
+    let length = match get_header(req, "Upload-Length").map(|length| length.parse::<u64>()) {
+        Some(Ok(length)) => length,
+        Some(Err(_)) => return tus_error(StatusCode::BAD_REQUEST, "invalid Upload-Length"),
+        None => return tus_error(StatusCode::BAD_REQUEST, "Upload-Length is required, deferred length is not supported"),
+    };
+    let max_size = policy.max_file_size(&grant).map_or(MAX_SIZE as u64, |max_size| max_size.min(MAX_SIZE as u64));
+    if length > max_size {
+        return tus_error(StatusCode::PAYLOAD_TOO_LARGE, format!("file is larger than {} bytes", max_size));
+    }
+
+    let upload_id = format!("{:032x}", rand::random::<u128>());
+    let identify = tus_identify(P::SERVICE, &upload_id);
+    let owner = policy.owner(&grant);
+    let file_name = get_header(req, "Upload-Metadata")
+        .and_then(parse_file_name)
+        .unwrap_or_else(|| upload_id.clone());
+    let status = match create_append_session_raw(&identify, file_name, length, owner.as_deref()).await {
+        Ok(status) => status,
+        Err(err) => return tus_error(StatusCode::BAD_REQUEST, err),
+    };
+
+    let mut response = tus_response(StatusCode::CREATED);
+    response
+        .insert_header(("Location", format!("{}/{}", req.path().trim_end_matches('/'), upload_id)))
+        .insert_header(("Upload-Expires", upload_expires(status.expires_at)));
+    // 空文件创建即完成
+    if length == 0 {
+        let target = prepare_merge_target(policy, grant, session_key(P::SERVICE, &upload_id)).await;
+        match finish_upload(&identify, owner.as_deref(), length, &target).await {
+            Ok(result) => {
+                response.insert_header(("Upload-Result", result));
+            }
+            Err(err) => return tus_error(StatusCode::INTERNAL_SERVER_ERROR, err),
+        }
+    }
+    response.finish()
+}
+
+// 查询已写入的字节数, 不是创建者时与不存在相同
+pub async fn tus_head_handler<P: UploadPolicy>(policy: &P, req: &HttpRequest, upload_id: &str) -> HttpResponse {
+    let grant = match authorize(policy, req).await {
+        Ok(grant) => grant,
+        Err(response) => return response,
+    };
+    let owner = policy.owner(&grant);
+    let identify = tus_identify(P::SERVICE, upload_id);
+    match get_append_status_raw(&identify, owner.as_deref()).await {
+        Some(AppendStatus { offset, length, expires_at }) => tus_response(StatusCode::OK)
+            .insert_header(("Upload-Offset", offset.to_string()))
+            .insert_header(("Upload-Length", length.to_string()))
+            .insert_header(("Upload-Expires", upload_expires(expires_at)))
+            .insert_header(("Cache-Control", "no-store"))
+            .finish(),
+        None => match completed_result(&identify, owner.as_deref()) {
+            Some((length, result)) => completed_response(StatusCode::OK, length, result),
+            None => tus_error(StatusCode::NOT_FOUND, "upload not found"),
+        },
+    }
+}
+
+// 从 Upload-Offset 开始追加内容, 全部写入后合并文件, 结果在 Upload-Result 中
+pub async fn tus_patch_handler<P: UploadPolicy>(
+    policy: &P,
+    req: &HttpRequest,
+    upload_id: &str,
+    payload: web::Payload,
+) -> HttpResponse {
+    let grant = match authorize(policy, req).await {
+        Ok(grant) => grant,
+        Err(response) => return response,
+    };
+    if get_header(req, "Content-Type") != Some("application/offset+octet-stream") {
+        return tus_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Content-Type must be application/offset+octet-stream");
+    }
+    let offset = match get_header(req, "Upload-Offset").map(|offset| offset.parse::<u64>()) {
+        Some(Ok(offset)) => offset,
+        _ => return tus_error(StatusCode::BAD_REQUEST, "Upload-Offset not found or invalid"),
+    };
+    let checksum = match get_header(req, "Upload-Checksum").map(parse_checksum).transpose() {
+        Ok(checksum) => checksum,
+        Err(err) => return tus_error(StatusCode::BAD_REQUEST, err),
+    };
+
+    let owner = policy.owner(&grant);
+    let identify = tus_identify(P::SERVICE, upload_id);
+    let chunk_content = payload.map(|chunk| chunk.map_err(|err| err.into()));
+    let status = match append_chunk_raw(&identify, owner.as_deref(), offset, Box::pin(chunk_content), checksum).await {
+        Ok(status) => status,
+        Err(AppendError::NotFound) => {
+            return match completed_result(&identify, owner.as_deref()) {
+                Some((length, result)) if offset == length => completed_response(StatusCode::NO_CONTENT, length, result),
+                Some((length, _)) => tus_response(StatusCode::CONFLICT)
+                    .insert_header(("Upload-Offset", length.to_string()))
+                    .body(AppendError::OffsetMismatch(length).to_string()),
+                None => tus_error(StatusCode::NOT_FOUND, AppendError::NotFound),
+            };
+        }
+        Err(err @ AppendError::OffsetMismatch(current_offset)) => {
+            return tus_response(StatusCode::CONFLICT)
+                .insert_header(("Upload-Offset", current_offset.to_string()))
+                .body(err.to_string());
+        }
+        Err(err @ AppendError::Locked) => return tus_error(StatusCode::LOCKED, err),
+        // tus checksum 扩展约定的状态码
+        Err(err @ AppendError::ChecksumMismatch) => {
+            return tus_error(StatusCode::from_u16(460).unwrap_or(StatusCode::BAD_REQUEST), err)
+        }
+        Err(err @ AppendError::TooLarge) => return tus_error(StatusCode::PAYLOAD_TOO_LARGE, err),
+        Err(err @ AppendError::Failed(_)) => return tus_error(StatusCode::INTERNAL_SERVER_ERROR, err),
+    };
+
+    let mut response = tus_response(StatusCode::NO_CONTENT);
+    response
+        .insert_header(("Upload-Offset", status.offset.to_string()))
+        .insert_header(("Upload-Expires", upload_expires(status.expires_at)));
+    if status.offset == status.length {
+        // 合并失败时会话保留, 客户端可以用空的 PATCH 重试
+        let target = prepare_merge_target(policy, grant, session_key(P::SERVICE, upload_id)).await;
+        match finish_upload(&identify, owner.as_deref(), status.length, &target).await {
+            Ok(result) => {
+                response.insert_header(("Upload-Result", result));
+            }
+            Err(err) => return tus_error(StatusCode::INTERNAL_SERVER_ERROR, err),
+        }
+    }
+    response.finish()
+}
+
+// termination 扩展, 删除未完成的上传
+pub async fn tus_delete_handler<P: UploadPolicy>(policy: &P, req: &HttpRequest, upload_id: &str) -> HttpResponse {
+    let grant = match authorize(policy, req).await {
+        Ok(grant) => grant,
+        Err(response) => return response,
+    };
+    let owner = policy.owner(&grant);
+    let identify = tus_identify(P::SERVICE, upload_id);
+    // 不是创建者时与不存在相同
+    let removed = match get_append_status_raw(&identify, owner.as_deref()).await {
+        Some(_) => remove_upload_session_raw(&identify, owner.as_deref()).await.is_ok(),
+        None => {
+            let mut results = TUS_RESULTS.lock();
+            let is_owner = results.get(&identify).is_some_and(|result| is_result_owner(result, owner.as_deref()));
+            is_owner && results.remove(&identify).is_some()
+        }
+    };
+    if removed {
+        tus_response(StatusCode::NO_CONTENT).finish()
+    } else {
+        tus_error(StatusCode::NOT_FOUND, "upload not found")
+    }
+}
+
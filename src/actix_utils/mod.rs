@@ -1,2 +1,2 @@
 mod request_operations;
-pub use request_operations::{get_header, get_headers};
\ No newline at end of file
+pub use request_operations::{get_header, get_required_header};
\ No newline at end of file
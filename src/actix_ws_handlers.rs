@@ -0,0 +1,235 @@
+use actix_web::{error, rt, web, Error, HttpRequest, HttpResponse};
+use actix_ws::{AggregatedMessage, Session};
+use bytes::{Buf, Bytes};
+use futures::stream;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk_hash::{normalize_hash, HashAlgorithm};
+use crate::split_chunks_upload_operations_raw::{
+    file_chunks_merge_raw,
+    get_uploaded_chunks_hashes_raw,
+    max_chunk_size,
+    session_key,
+    split_chunks_upload_raw,
+    ConflictPolicy,
+    MergeChunksRequest,
+    UploadChunkRequest,
+};
+use crate::upload_policy::{prepare_merge_target, UploadPolicy};
+
+// chunk 帧头部: 4 字节大端序号, 1 字节 hash 长度, hash
+const CHUNK_FRAME_HEADER_SIZE: usize = 5 + u8::MAX as usize;
+
+// 客户端的文本消息
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    // 之后的 chunk 帧都属于该会话
+    #[serde(rename_all = "camelCase")]
+    Open {
+        identify: String,
+        chunks_number: usize,
+        hash_algorithm: Option<String>,
+    },
+    // 合并当前会话, 参数与 /merge_chunks 的请求头相同, fullPath 不需要编码
+    #[serde(rename_all = "camelCase")]
+    Merge {
+        full_path: Option<String>,
+        file_hash: Option<String>,
+        conflict_policy: Option<String>,
+    },
+}
+
+// 服务端的文本消息
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ServerMessage {
+    // 会话已上传的 chunk hash, 与 /fetch_uploaded_chunks_hashes 相同
+    #[serde(rename_all = "camelCase")]
+    Opened { identify: String, chunks_hash: Vec<String> },
+    // chunk 已校验并保存
+    #[serde(rename_all = "camelCase")]
+    Ack { chunk_index: usize, chunk_hash: String },
+    // 合并目标的结果
+    Merged { result: String },
+    // 出错后连接保持, 客户端可以重试
+    #[serde(rename_all = "camelCase")]
+    Error {
+        chunk_index: Option<usize>,
+        error: String,
+    },
+}
+
+// 当前连接正在上传的会话
+struct OpenedSession {
+    identify: String,
+    chunks_number: usize,
+    hash_algorithm: HashAlgorithm,
+}
+
+// 解析 chunk 帧, 返回序号, hash 和内容
+fn parse_chunk_frame(mut frame: Bytes) -> Result<(usize, String, Bytes), String> {
+    if frame.len() < 5 {
+        return Err(String::from("chunk frame is too short"));
+    }
+    let chunk_index = frame.get_u32() as usize;
+    let hash_length = frame.get_u8() as usize;
+    if frame.len() < hash_length {
+        return Err(String::from("chunk frame is too short"));
+    }
+    let chunk_hash = String::from_utf8(frame.split_to(hash_length).to_vec())
+        .map_err(|_| String::from("chunk hash is not valid utf-8"))?;
+    Ok((chunk_index, chunk_hash, frame))
+}
+
+async fn send(session: &mut Session, message: ServerMessage) -> bool {
+    match serde_json::to_string(&message) {
+        Ok(message) => session.text(message).await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+async fn open_session<P: UploadPolicy>(
+    identify: String,
+    owner: Option<&str>,
+    chunks_number: usize,
+    hash_algorithm: Option<String>,
+) -> Result<(OpenedSession, ServerMessage), String> {
+    let hash_algorithm = hash_algorithm.map_or(Ok(HashAlgorithm::default()), |name| HashAlgorithm::from_name(&name))?;
+    let chunks_hash =
+        serde_json::from_str(&get_uploaded_chunks_hashes_raw(&session_key(P::SERVICE, &identify), owner).await)
+            .map_err(|err| err.to_string())?;
+    let opened = OpenedSession {
+        identify: session_key(P::SERVICE, &identify),
+        chunks_number,
+        hash_algorithm,
+    };
+    Ok((opened, ServerMessage::Opened { identify, chunks_hash }))
+}
+
+// 与 /upload_chunk 相同的校验和保存
+async fn upload_chunk(opened: &OpenedSession, owner: Option<&str>, frame: Bytes) -> ServerMessage {
+    let (chunk_index, chunk_hash, content) = match parse_chunk_frame(frame) {
+        Ok(chunk) => chunk,
+        Err(error) => return ServerMessage::Error { chunk_index: None, error },
+    };
+    let request = UploadChunkRequest {
+        identify: opened.identify.clone(),
+        chunk_hash: chunk_hash.clone(),
+        chunk_index,
+        chunks_number: opened.chunks_number,
+        hash_algorithm: opened.hash_algorithm,
+    };
+    let chunk_content = stream::once(async move { Ok(content) });
+    match split_chunks_upload_raw(request, Box::pin(chunk_content), owner).await {
+        Ok(_) => ServerMessage::Ack { chunk_index, chunk_hash: normalize_hash(&chunk_hash) },
+        Err(err) => ServerMessage::Error {
+            chunk_index: Some(chunk_index),
+            error: err.to_string(),
+        },
+    }
+}
+
+// 与 /merge_chunks 相同的合并
+async fn merge<P: UploadPolicy>(
+    policy: &P,
+    grant: P::Grant,
+    opened: &OpenedSession,
+    full_path: Option<String>,
+    file_hash: Option<String>,
+    conflict_policy: Option<String>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let request = MergeChunksRequest {
+        identify: opened.identify.clone(),
+        owner: policy.owner(&grant),
+        full_path: full_path.filter(|full_path| !full_path.is_empty()),
+        file_hash: file_hash.filter(|file_hash| !file_hash.is_empty()),
+        conflict_policy: conflict_policy.map_or(Ok(ConflictPolicy::default()), |name| ConflictPolicy::from_name(&name))?,
+        max_file_size: policy.max_file_size(&grant),
+    };
+    let target = prepare_merge_target(policy, grant, opened.identify.clone()).await;
+    file_chunks_merge_raw(request, &target).await
+}
+
+async fn handle_messages<P: UploadPolicy>(
+    policy: &'static P,
+    grant: P::Grant,
+    mut session: Session,
+    mut messages: actix_ws::AggregatedMessageStream,
+) {
+    let owner = policy.owner(&grant);
+    let mut opened: Option<OpenedSession> = None;
+    while let Some(Ok(message)) = messages.recv().await {
+        let reply = match message {
+            AggregatedMessage::Binary(frame) => match &opened {
+                Some(opened) => upload_chunk(opened, owner.as_deref(), frame).await,
+                None => ServerMessage::Error {
+                    chunk_index: None,
+                    error: String::from("open an upload session first"),
+                },
+            },
+            AggregatedMessage::Text(text) => match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(ClientMessage::Open { identify, chunks_number, hash_algorithm }) => {
+                    match open_session::<P>(identify, owner.as_deref(), chunks_number, hash_algorithm).await {
+                        Ok((opened_session, reply)) => {
+                            opened = Some(opened_session);
+                            reply
+                        }
+                        Err(error) => ServerMessage::Error { chunk_index: None, error },
+                    }
+                }
+                Ok(ClientMessage::Merge { full_path, file_hash, conflict_policy }) => match &opened {
+                    Some(opened_session) => {
+                        match merge(policy, grant.clone(), opened_session, full_path, file_hash, conflict_policy).await {
+                            Ok(result) => {
+                                opened = None;
+                                ServerMessage::Merged { result }
+                            }
+                            Err(err) => ServerMessage::Error {
+                                chunk_index: None,
+                                error: err.to_string(),
+                            },
+                        }
+                    }
+                    None => ServerMessage::Error {
+                        chunk_index: None,
+                        error: String::from("open an upload session first"),
+                    },
+                },
+                Err(err) => ServerMessage::Error {
+                    chunk_index: None,
+                    error: err.to_string(),
+                },
+            },
+            AggregatedMessage::Ping(bytes) => {
+                if session.pong(&bytes).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            AggregatedMessage::Pong(_) => continue,
+            AggregatedMessage::Close(_) => break,
+        };
+        if !send(&mut session, reply).await {
+            return;
+        }
+    }
+    let _ = session.close(None).await;
+}
+
+// 升级前鉴权, 之后连接中的所有操作都使用同一鉴权结果
+pub async fn ws_upload_handler<P: UploadPolicy>(
+    policy: &'static P,
+    req: &HttpRequest,
+    payload: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let grant = policy.authorize(req).await.map_err(error::ErrorBadRequest)?;
+    let (response, session, messages) = actix_ws::handle(req, payload)?;
+    let max_frame_size = max_chunk_size() + CHUNK_FRAME_HEADER_SIZE;
+    let messages = messages
+        .max_frame_size(max_frame_size)
+        .aggregate_continuations()
+        .max_continuation_size(max_frame_size);
+    rt::spawn(handle_messages(policy, grant, session, messages));
+    Ok(response)
+}
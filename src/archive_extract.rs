@@ -0,0 +1,225 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tar::EntryType;
+use zip::ZipArchive;
+
+use crate::split_chunks_upload_operations_raw::relative_path;
+
+// 解压的限制, 防止压缩炸弹
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExtractLimits {
+    // 解压后所有文件的总字节数, 按实际写入计算, 不信任压缩包中记录的大小
+    pub max_total_size: u64,
+    // 文件和目录的总数
+    pub max_entries: usize,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        ExtractLimits {
+            max_total_size: 2 * 1024 * 1024 * 1024,
+            max_entries: 10000,
+        }
+    }
+}
+
+lazy_static! {
+    static ref EXTRACT_LIMITS: RwLock<ExtractLimits> = RwLock::new(ExtractLimits::default());
+}
+
+pub fn set_extract_limits(limits: ExtractLimits) {
+    *EXTRACT_LIMITS.write() = limits;
+}
+
+// 解压出的一项, 路径相对于目标目录
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractedEntry {
+    pub path: String,
+    pub size: u64,
+    pub directory: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtractReport {
+    pub entries: Vec<ExtractedEntry>,
+    pub total_size: u64,
+}
+
+enum ArchiveFormat {
+    Zip,
+    TarGz,
+    Tar,
+}
+
+// 支持的压缩包扩展名
+const ARCHIVE_EXTENSIONS: [&str; 4] = [".tar.gz", ".tgz", ".zip", ".tar"];
+
+// 去掉压缩包扩展名后的路径, 作为默认的解压目录, 不是压缩包时为 None
+pub(crate) fn default_extract_dir(archive_path: &Path) -> Option<PathBuf> {
+    let name = archive_path.file_name()?.to_string_lossy().to_string();
+    let lowercase_name = name.to_ascii_lowercase();
+    let extension = ARCHIVE_EXTENSIONS
+        .iter()
+        .find(|extension| lowercase_name.ends_with(*extension))?;
+    let stem = &name[..name.len() - extension.len()];
+    (!stem.is_empty()).then(|| archive_path.with_file_name(stem))
+}
+
+// 按扩展名判断格式
+fn archive_format(archive_path: &Path) -> Result<ArchiveFormat, String> {
+    let name = archive_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    if name.ends_with(".zip") {
+        Ok(ArchiveFormat::Zip)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Ok(ArchiveFormat::TarGz)
+    } else if name.ends_with(".tar") {
+        Ok(ArchiveFormat::Tar)
+    } else {
+        Err(String::from("only .zip, .tar.gz, .tgz and .tar archives can be extracted"))
+    }
+}
+
+// 写入临时目录并记录解压的内容
+struct Extraction<'a> {
+    target_dir: &'a Path,
+    limits: ExtractLimits,
+    report: ExtractReport,
+}
+
+impl Extraction<'_> {
+    fn count_entry(&self) -> Result<(), String> {
+        if self.report.entries.len() >= self.limits.max_entries {
+            return Err(format!("archive has more than {} entries", self.limits.max_entries));
+        }
+        Ok(())
+    }
+
+    fn directory(&mut self, name: &Path) -> Result<(), String> {
+        self.count_entry()?;
+        let name = relative_path(name)?;
+        fs::create_dir_all(self.target_dir.join(&name)).map_err(|err| err.to_string())?;
+        self.report.entries.push(ExtractedEntry {
+            path: name.to_string_lossy().to_string(),
+            size: 0,
+            directory: true,
+        });
+        Ok(())
+    }
+
+    fn file(&mut self, name: &Path, content: &mut dyn Read) -> Result<(), String> {
+        self.count_entry()?;
+        let name = relative_path(name)?;
+        let file_path = self.target_dir.join(&name);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+        }
+        // 多读一个字节, 超过剩余额度时停止
+        let remaining = self.limits.max_total_size - self.report.total_size;
+        let mut file = File::create(&file_path).map_err(|err| err.to_string())?;
+        let written = io::copy(&mut content.take(remaining.saturating_add(1)), &mut file).map_err(|err| err.to_string())?;
+        if written > remaining {
+            return Err(format!(
+                "archive is larger than {} bytes after extraction",
+                self.limits.max_total_size
+            ));
+        }
+        self.report.total_size += written;
+        self.report.entries.push(ExtractedEntry {
+            path: name.to_string_lossy().to_string(),
+            size: written,
+            directory: false,
+        });
+        Ok(())
+    }
+}
+
+fn extract_zip(archive_path: &Path, extraction: &mut Extraction) -> Result<(), String> {
+    let file = File::open(archive_path).map_err(|err| err.to_string())?;
+    let mut archive = ZipArchive::new(file).map_err(|err| err.to_string())?;
+    if archive.len() > extraction.limits.max_entries {
+        return Err(format!("archive has more than {} entries", extraction.limits.max_entries));
+    }
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(|err| err.to_string())?;
+        let name = PathBuf::from(entry.name());
+        // 不创建符号链接
+        if entry.unix_mode().is_some_and(|mode| mode & 0o170000 == 0o120000) {
+            return Err(format!("entry {} is a symbolic link", name.display()));
+        }
+        if entry.is_dir() {
+            extraction.directory(&name)?;
+        } else {
+            extraction.file(&name, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+fn extract_tar(reader: impl Read, extraction: &mut Extraction) -> Result<(), String> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(|err| err.to_string())? {
+        let mut entry = entry.map_err(|err| err.to_string())?;
+        let name = entry.path().map_err(|err| err.to_string())?.into_owned();
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous => extraction.file(&name, &mut entry)?,
+            EntryType::Directory => extraction.directory(&name)?,
+            EntryType::XGlobalHeader => {}
+            // 链接和设备文件可能指向目标目录之外
+            _ => return Err(format!("entry {} is not a regular file or directory", name.display())),
+        }
+    }
+    Ok(())
+}
+
+// 把压缩包解压到目标目录, 目标目录不能已存在
+// 先解压到临时目录, 成功后再改名, 失败时不留下部分文件
+pub(crate) fn extract_archive(archive_path: &Path, target_dir: &Path) -> Result<ExtractReport, String> {
+    let format = archive_format(archive_path)?;
+    if !archive_path.is_file() {
+        return Err(String::from("archive not found"));
+    }
+    if target_dir.exists() {
+        return Err(String::from("target folder already exists"));
+    }
+    let staging_dir = PathBuf::from(format!("{}.{}.extracting", target_dir.display(), rand::random::<u32>()));
+    fs::create_dir_all(&staging_dir).map_err(|err| err.to_string())?;
+
+    let mut extraction = Extraction {
+        target_dir: &staging_dir,
+        limits: EXTRACT_LIMITS.read().clone(),
+        report: ExtractReport {
+            entries: Vec::new(),
+            total_size: 0,
+        },
+    };
+    let extracted = match format {
+        ArchiveFormat::Zip => extract_zip(archive_path, &mut extraction),
+        ArchiveFormat::TarGz => File::open(archive_path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| extract_tar(GzDecoder::new(file), &mut extraction)),
+        ArchiveFormat::Tar => File::open(archive_path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| extract_tar(file, &mut extraction)),
+    }
+    .and_then(|_| fs::rename(&staging_dir, target_dir).map_err(|err| err.to_string()));
+
+    match extracted {
+        Ok(_) => Ok(extraction.report),
+        Err(err) => {
+            let _ = fs::remove_dir_all(&staging_dir);
+            Err(err)
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+// chunk 校验支持的 hash 算法, 结果统一为小写 hex
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    // 兼容旧客户端的默认算法
+    #[default]
+    Md5,
+    Sha256,
+    Blake3,
+    // 速度优先, 只用于校验传输错误
+    Crc32c,
+    // tus checksum 扩展要求支持
+    Sha1,
+}
+
+// 服务端接受的算法
+pub const SUPPORTED_HASH_ALGORITHMS: [HashAlgorithm; 5] = [
+    HashAlgorithm::Md5,
+    HashAlgorithm::Sha256,
+    HashAlgorithm::Blake3,
+    HashAlgorithm::Crc32c,
+    HashAlgorithm::Sha1,
+];
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Crc32c => "crc32c",
+            HashAlgorithm::Sha1 => "sha1",
+        }
+    }
+
+    // 解析请求中的算法名, 不区分大小写, 允许 sha-256 写法
+    pub fn from_name(name: &str) -> Result<HashAlgorithm, String> {
+        let normalized_name = name.to_ascii_lowercase().replace('-', "");
+        SUPPORTED_HASH_ALGORITHMS
+            .iter()
+            .find(|algorithm| algorithm.name() == normalized_name)
+            .copied()
+            .ok_or_else(|| format!("unsupported hash algorithm: {}", name))
+    }
+
+    // md5, sha1 和 crc32c 可以构造碰撞, 相同 hash 不能当作相同内容在会话之间共享
+    pub fn supports_deduplication(&self) -> bool {
+        matches!(self, HashAlgorithm::Sha256 | HashAlgorithm::Blake3)
+    }
+
+    // 秒传通过 challenge 核对文件内容, 只排除 crc32c
+    pub fn supports_instant_upload(&self) -> bool {
+        !matches!(self, HashAlgorithm::Crc32c)
+    }
+
+    pub fn hasher(&self) -> ChunkHasher {
+        match self {
+            HashAlgorithm::Md5 => ChunkHasher::Md5(md5::Context::new()),
+            HashAlgorithm::Sha256 => ChunkHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => ChunkHasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Crc32c => ChunkHasher::Crc32c(0),
+            HashAlgorithm::Sha1 => ChunkHasher::Sha1(Sha1::new()),
+        }
+    }
+
+    pub fn hash(&self, data: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+// 增量计算 hash
+#[derive(Clone)]
+pub enum ChunkHasher {
+    Md5(md5::Context),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Crc32c(u32),
+    Sha1(Sha1),
+}
+
+impl ChunkHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ChunkHasher::Md5(context) => context.consume(data),
+            ChunkHasher::Sha256(hasher) => hasher.update(data),
+            ChunkHasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            ChunkHasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+            ChunkHasher::Sha1(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> String {
+        match self {
+            ChunkHasher::Md5(context) => format!("{:x}", context.compute()),
+            ChunkHasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            ChunkHasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            ChunkHasher::Crc32c(crc) => format!("{:08x}", crc),
+            ChunkHasher::Sha1(hasher) => format!("{:x}", hasher.finalize()),
+        }
+    }
+}
+
+// 客户端提交的 hash 统一转换为小写 hex 再比较
+pub fn normalize_hash(hash: &str) -> String {
+    hash.trim().to_ascii_lowercase()
+}
@@ -25,10 +25,10 @@ async fn cloud_text_add(
   };
   // 写入内容
   let file_path = format!("./cloud_text/{}.txt", uid);
-  if let Err(_) = fs::File::create(&file_path).await {
+  if fs::File::create(&file_path).await.is_err() {
     return Err(error::ErrorBadRequest("failed to create file"));
   };
-  if let Err(_) = fs::write(&file_path, &text_content).await {
+  if fs::write(&file_path, &text_content).await.is_err() {
     return Err(error::ErrorBadRequest("failed to write file"));
   };
   Ok(HttpResponse::Ok().body(""))
@@ -39,7 +39,7 @@ async fn cloud_text_get(
   extract::Path(uid): extract::Path<String>
 ) -> Result<String, Error> {
   let file_path = format!("./cloud_text/{}.txt", uid);
-  fs::read_to_string(file_path).await.or_else(|_| Err(error::ErrorBadRequest("this cloud_text file is not found")))
+  fs::read_to_string(file_path).await.map_err(|_| error::ErrorBadRequest("this cloud_text file is not found"))
 }
 
 pub fn actix_configure(config: &mut web::ServiceConfig) {
@@ -0,0 +1,32 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use hotwatch::{Event, Hotwatch};
+
+// 文件被修改或整体替换后调用 on_change
+// 观察所在目录而不是文件本身, 文件被替换(写入临时文件后改名)后仍能收到之后的变化
+pub(crate) fn watch_file<F>(hot_watch: &mut Hotwatch, path: &str, mut on_change: F) -> io::Result<()>
+where
+    F: 'static + FnMut() + Send,
+{
+    let path = Path::new(path);
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file", path.display())))?;
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let watched: PathBuf = directory.canonicalize()?.join(file_name);
+    hot_watch
+        .watch(directory, move |event: Event| {
+            let changed = match &event {
+                Event::Create(changed) | Event::Write(changed) | Event::Rename(_, changed) => changed,
+                _ => return,
+            };
+            if *changed == watched {
+                on_change();
+            }
+        })
+        .map_err(|err| io::Error::other(err.to_string()))
+}
@@ -0,0 +1,137 @@
+use serde::Deserialize;
+use tokio::{
+    fs,
+    task,
+    time::{sleep, Duration},
+};
+
+use std::{fs::Metadata, path::Path, time::SystemTime};
+
+// 孤立文件清理, 启动时执行一次, 之后定期执行
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GcConfig {
+    pub enabled: bool,
+    pub interval: u64, // 两次清理的间隔, 秒
+    // 修改时间距今超过宽限期才会被清理, 秒, 避免删除正在写入的文件
+    pub grace_period: u64,
+    // 只报告会被清理的文件, 不删除
+    pub dry_run: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            enabled: true,
+            interval: 6 * 3600,
+            grace_period: 86400,
+            dry_run: false,
+        }
+    }
+}
+
+pub struct OrphanedFile {
+    pub path: String,
+    pub size: u64,
+}
+
+// 一次清理的结果, 演练模式下为会被清理的文件
+pub struct GcReport {
+    pub dry_run: bool,
+    pub removed: Vec<OrphanedFile>,
+}
+
+impl GcReport {
+    pub fn removed_size(&self) -> u64 {
+        self.removed.iter().map(|file| file.size).sum()
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.removed.iter().any(|file| file.path == path)
+    }
+}
+
+// 删除超过宽限期的孤立文件, 并记录到报告中
+pub(crate) struct OrphanSweeper {
+    grace_period: Duration,
+    now: SystemTime,
+    report: GcReport,
+}
+
+impl OrphanSweeper {
+    fn new(config: &GcConfig) -> Self {
+        OrphanSweeper {
+            grace_period: Duration::from_secs(config.grace_period),
+            now: SystemTime::now(),
+            report: GcReport {
+                dry_run: config.dry_run,
+                removed: Vec::new(),
+            },
+        }
+    }
+
+    // 调用方已确认文件不被引用, 这里只检查修改时间
+    pub(crate) async fn sweep(&mut self, path: &Path, metadata: &Metadata) {
+        let modified = match metadata.modified() {
+            Ok(modified) => modified,
+            Err(_) => return,
+        };
+        if self.now.duration_since(modified).unwrap_or_default() < self.grace_period {
+            return;
+        }
+        let path = path.to_string_lossy().into_owned();
+        if self.report.dry_run {
+            println!("orphaned file (dry run): {} ({} bytes)", path, metadata.len());
+        } else if let Err(err) = fs::remove_file(&path).await {
+            println!("failed to remove orphaned file {}: {}", path, err);
+            return;
+        } else {
+            println!("removed orphaned file: {} ({} bytes)", path, metadata.len());
+        }
+        self.report.removed.push(OrphanedFile {
+            path,
+            size: metadata.len(),
+        });
+    }
+}
+
+// 对比磁盘上的文件和内存中的会话及分享, 清理不再被引用的文件
+// 只有挂载了 transfer 服务时才清理分享的文件, 否则无法区分分享和其他服务的文件
+#[cfg_attr(not(feature = "transfer"), allow(unused_variables))]
+#[cfg_attr(
+    not(any(feature = "transfer", feature = "upload-large-file", feature = "s3")),
+    allow(unused_mut)
+)]
+pub async fn collect_garbage(config: &GcConfig, transfer_mounted: bool) -> GcReport {
+    let mut sweeper = OrphanSweeper::new(config);
+
+    #[cfg(any(feature = "transfer", feature = "upload-large-file", feature = "s3"))]
+    if let Err(err) = crate::split_chunks_upload_operations_raw::sweep_orphaned_chunks(&mut sweeper).await {
+        println!("failed to collect orphaned chunks: {}", err);
+    }
+    #[cfg(feature = "transfer")]
+    if transfer_mounted {
+        if let Err(err) = crate::transfer_serve::sweep_orphaned_shares(&mut sweeper).await {
+            println!("failed to collect orphaned shares: {}", err);
+        }
+    }
+
+    let report = sweeper.report;
+    println!(
+        "garbage collection{}: {} files, {} bytes",
+        if report.dry_run { " (dry run)" } else { "" },
+        report.removed.len(),
+        report.removed_size()
+    );
+    report
+}
+
+// 立即清理一次, 之后按间隔定期清理
+pub fn spawn_garbage_collector(config: GcConfig, transfer_mounted: bool) {
+    task::spawn(async move {
+        loop {
+            collect_garbage(&config, transfer_mounted).await;
+            sleep(Duration::from_secs(config.interval.max(1))).await;
+        }
+    });
+}
@@ -0,0 +1,310 @@
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt},
+    time::{Duration, Instant},
+};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::time::SystemTime;
+
+use parking_lot::Mutex;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk_hash::{normalize_hash, HashAlgorithm};
+use crate::split_chunks_upload_operations_raw::{chunks_path, place_file, resolve_save_path, ConflictPolicy, MergeTarget};
+
+use lazy_static::lazy_static;
+
+// challenge 5分钟内有效
+const CHALLENGE_SURVIVAL_TIME: Duration = Duration::from_secs(300);
+
+// 客户端需要提供的内容最多 64KB
+const MAX_CHALLENGE_LENGTH: u64 = 65536;
+
+// 已合并的文件, 修改时间或大小变化后不再使用
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredFile {
+    path: String,
+    size: u64,
+    modified: SystemTime,
+}
+
+impl StoredFile {
+    async fn is_unchanged(&self) -> bool {
+        match fs::metadata(&self.path).await {
+            Ok(metadata) => metadata.len() == self.size && metadata.modified().ok() == Some(self.modified),
+            Err(_) => false,
+        }
+    }
+}
+
+struct Challenge {
+    scope: String,
+    hash_algorithm: HashAlgorithm,
+    file_hash: String,
+    stored_file: StoredFile,
+    offset: u64,
+    length: u64,
+    expires_at: Instant,
+}
+
+// 秒传前的检查请求
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstantUploadRequest {
+    pub file_hash: String,
+    pub file_size: u64,
+    pub hash_algorithm: Option<String>,
+}
+
+// 回答 challenge 的请求, 请求体为 challenge 要求的内容
+pub struct InstantUploadAnswer {
+    pub challenge_id: String,
+    // 回答者可见的范围, 需与创建 challenge 时一致
+    pub scope: String,
+    pub full_path: String,
+    pub conflict_policy: ConflictPolicy,
+    // 超过时不保存
+    pub max_file_size: Option<u64>,
+}
+
+// 文件已存在时, 客户端需要在 challenge 有效期内提交文件中 [offset, offset + length) 的内容
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstantUploadChallenge {
+    pub exists: bool,
+    pub challenge_id: Option<String>,
+    pub offset: u64,
+    pub length: u64,
+}
+
+lazy_static! {
+    // key 为可见范围, 算法, hash 和大小
+    static ref STORED_FILES: Mutex<HashMap<String, Vec<StoredFile>>> = Mutex::new(HashMap::new());
+    static ref CHALLENGES: Mutex<HashMap<String, Challenge>> = Mutex::new(HashMap::new());
+    // 依次写入索引文件, 避免旧的快照覆盖新的
+    static ref INDEX_WRITE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+// 记录的文件保存在索引文件中, 重启后仍可秒传
+fn index_path() -> String {
+    format!("{}stored_files.json", chunks_path())
+}
+
+async fn persist_stored_files() {
+    let _guard = INDEX_WRITE_LOCK.lock().await;
+    let content = match serde_json::to_vec(&*STORED_FILES.lock()) {
+        Ok(content) => content,
+        Err(_) => return,
+    };
+    let path = index_path();
+    let temp_path = format!("{}.tmp", path);
+    let written = async {
+        fs::write(&temp_path, content).await?;
+        fs::rename(&temp_path, &path).await
+    };
+    if let Err(err) = written.await {
+        println!("failed to save instant upload index: {}", err);
+    }
+}
+
+// 启动时从索引文件恢复记录, 已变化的文件不再使用
+pub async fn restore_stored_files() -> Result<usize, Box<dyn std::error::Error>> {
+    let content = match fs::read(index_path()).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    let mut saved: HashMap<String, Vec<StoredFile>> = serde_json::from_slice(&content)?;
+    // 已在内存中的记录一起校验
+    for (key, files) in STORED_FILES.lock().iter() {
+        saved.entry(key.clone()).or_default().extend(files.iter().cloned());
+    }
+    let (mut restored, mut changed) = (Vec::new(), Vec::new());
+    for (key, files) in saved {
+        for stored_file in files {
+            if stored_file.is_unchanged().await {
+                restored.push((key.clone(), stored_file));
+            } else {
+                changed.push((key.clone(), stored_file.path));
+            }
+        }
+    }
+    let mut count = 0;
+    {
+        let mut stored_files = STORED_FILES.lock();
+        for (key, path) in changed {
+            if let Some(files) = stored_files.get_mut(&key) {
+                files.retain(|file| file.path != path);
+            }
+        }
+        for (key, stored_file) in restored {
+            let files = stored_files.entry(key).or_default();
+            if !files.iter().any(|file| file.path == stored_file.path) {
+                files.push(stored_file);
+                count += 1;
+            }
+        }
+        stored_files.retain(|_, files| !files.is_empty());
+    }
+    persist_stored_files().await;
+    Ok(count)
+}
+
+// 记录按服务和创建者分开, 只能秒传自己可见的文件, 也不能借此得知其他用户的文件是否存在
+pub(crate) fn stored_file_scope(service: &str, owner: Option<&str>) -> String {
+    format!("{}:{}", service, owner.unwrap_or_default())
+}
+
+fn stored_file_key(scope: &str, hash_algorithm: HashAlgorithm, file_hash: &str, file_size: u64) -> String {
+    format!("{}:{}-{}-{}", scope, hash_algorithm.name(), file_hash, file_size)
+}
+
+// 合并完成后记录文件, 之后相同的文件可以秒传
+pub(crate) async fn record_stored_file(scope: &str, hash_algorithm: HashAlgorithm, file_hash: &str, file_path: &str) {
+    if !hash_algorithm.supports_instant_upload() {
+        return;
+    }
+    let metadata = match fs::metadata(file_path).await {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+    let stored_file = StoredFile {
+        path: String::from(file_path),
+        size: metadata.len(),
+        modified: match metadata.modified() {
+            Ok(modified) => modified,
+            Err(_) => return,
+        },
+    };
+    STORED_FILES
+        .lock()
+        .entry(stored_file_key(scope, hash_algorithm, file_hash, stored_file.size))
+        .or_default()
+        .push(stored_file);
+    persist_stored_files().await;
+}
+
+// 找到一个内容未变化的文件, 同时清除已变化的记录
+async fn find_stored_file(key: &str) -> Option<StoredFile> {
+    loop {
+        let stored_file = STORED_FILES.lock().get(key)?.last().cloned()?;
+        if stored_file.is_unchanged().await {
+            return Some(stored_file);
+        }
+        {
+            let mut stored_files = STORED_FILES.lock();
+            if let Some(files) = stored_files.get_mut(key) {
+                files.retain(|file| file.path != stored_file.path);
+                if files.is_empty() {
+                    stored_files.remove(key);
+                }
+            }
+        }
+        persist_stored_files().await;
+    }
+}
+
+// 文件已存在时创建 challenge, 由服务端随机选择需要客户端提供的范围
+pub async fn create_instant_upload_challenge_raw(
+    challenge_id: &str,
+    scope: &str,
+    request: InstantUploadRequest,
+) -> Result<InstantUploadChallenge, Box<dyn std::error::Error>> {
+    let hash_algorithm = match &request.hash_algorithm {
+        Some(name) => HashAlgorithm::from_name(name)?,
+        None => HashAlgorithm::default(),
+    };
+    if !hash_algorithm.supports_instant_upload() {
+        return Err(format!("hash algorithm {} does not support instant upload", hash_algorithm.name()).into());
+    }
+
+    let file_hash = normalize_hash(&request.file_hash);
+    let key = stored_file_key(scope, hash_algorithm, &file_hash, request.file_size);
+    let stored_file = match find_stored_file(&key).await {
+        Some(stored_file) => stored_file,
+        None => {
+            return Ok(InstantUploadChallenge {
+                exists: false,
+                challenge_id: None,
+                offset: 0,
+                length: 0,
+            })
+        }
+    };
+
+    let length = stored_file.size.min(MAX_CHALLENGE_LENGTH);
+    let offset = rand::thread_rng().gen_range(0..=stored_file.size - length);
+    let now = Instant::now();
+    let mut challenges = CHALLENGES.lock();
+    // 顺便清除过期的 challenge
+    challenges.retain(|_, challenge| challenge.expires_at > now);
+    challenges.insert(
+        String::from(challenge_id),
+        Challenge {
+            scope: String::from(scope),
+            hash_algorithm,
+            file_hash,
+            stored_file,
+            offset,
+            length,
+            expires_at: now + CHALLENGE_SURVIVAL_TIME,
+        },
+    );
+
+    Ok(InstantUploadChallenge {
+        exists: true,
+        challenge_id: Some(String::from(challenge_id)),
+        offset,
+        length,
+    })
+}
+
+// 校验 challenge 的回答, 通过后把已存储的文件复制到保存路径, 返回目标后续操作的结果
+// 每个 challenge 只能回答一次
+pub async fn instant_upload_raw(
+    request: InstantUploadAnswer,
+    answer: &[u8],
+    target: &dyn MergeTarget,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let challenge = CHALLENGES
+        .lock()
+        .remove(&request.challenge_id)
+        .filter(|challenge| challenge.expires_at > Instant::now() && challenge.scope == request.scope)
+        .ok_or_else(|| String::from("challenge not found or expired"))?;
+    if !challenge.stored_file.is_unchanged().await {
+        return Err("stored file has changed, upload the file instead".into());
+    }
+
+    let mut expected = vec![0; challenge.length as usize];
+    let mut file = File::open(&challenge.stored_file.path).await?;
+    file.seek(SeekFrom::Start(challenge.offset)).await?;
+    file.read_exact(&mut expected).await?;
+    if answer != expected.as_slice() {
+        return Err("challenge failed".into());
+    }
+    if let Some(max_file_size) = request.max_file_size {
+        if challenge.stored_file.size > max_file_size {
+            return Err(format!("file is larger than {} bytes", max_file_size).into());
+        }
+    }
+
+    let file_path = resolve_save_path(&request.full_path, target).await?;
+    // 先复制到临时文件, 完成后才放到保存路径
+    let temp_path = format!("{}.{}.copying", file_path, rand::random::<u32>());
+    if let Err(err) = fs::copy(&challenge.stored_file.path, &temp_path).await {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(err.into());
+    }
+    let placed = place_file(&temp_path, &file_path, request.conflict_policy).await;
+    if placed.is_err() {
+        let _ = fs::remove_file(&temp_path).await;
+    }
+    let file_path = placed.map_err(|err| -> Box<dyn std::error::Error> { err })?;
+    println!("instant upload: {} copied to {}", challenge.stored_file.path, file_path);
+    // 复制出的文件同样可以用于之后的秒传
+    record_stored_file(&challenge.scope, challenge.hash_algorithm, &challenge.file_hash, &file_path).await;
+
+    Ok(target.after_merge(file_path).await?)
+}
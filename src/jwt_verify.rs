@@ -0,0 +1,348 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hotwatch::Hotwatch;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, JwkSet, PublicKeyUse};
+use jsonwebtoken::{decode, decode_header, errors::ErrorKind, Algorithm, DecodingKey, Validation};
+use lazy_static::lazy_static;
+use parking_lot::{Mutex, RwLock};
+use serde::Deserialize;
+
+use crate::file_watch::watch_file;
+use crate::split_chunks_upload_operations_raw::relative_path;
+
+// 上传大文件服务的 token 校验配置
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default)]
+pub struct JwtConfig {
+    // HS256 共享密钥
+    pub secret: Option<String>,
+    // RS256 或 ES256 公钥文件, PEM 格式, 可以同时配置多个以便轮换
+    pub public_key_paths: Vec<String>,
+    // 配置后 token 中的 iss 必须一致
+    pub issuer: Option<String>,
+    // 配置后 token 中的 aud 必须包含该值
+    pub audience: Option<String>,
+    // 校验 exp 和 nbf 时允许的时钟误差, 秒
+    pub leeway: u64,
+    // 本地 JWKS 文件, 文件变化时重新加载
+    pub jwks_path: Option<String>,
+    // 远程 JWKS 地址, 在后台定时获取, 遇到未知的 kid 时也会获取
+    pub jwks_url: Option<String>,
+    // 远程 JWKS 的缓存时间, 秒
+    pub jwks_cache_seconds: u64,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        JwtConfig {
+            secret: None,
+            public_key_paths: Vec::new(),
+            issuer: None,
+            audience: None,
+            leeway: 60,
+            jwks_path: None,
+            jwks_url: None,
+            jwks_cache_seconds: 300,
+        }
+    }
+}
+
+impl JwtConfig {
+    // 是否配置了任何校验密钥的来源
+    pub fn has_keys(&self) -> bool {
+        self.secret.is_some() || !self.public_key_paths.is_empty() || self.jwks_path.is_some() || self.jwks_url.is_some()
+    }
+}
+
+struct VerificationKey {
+    // JWKS 中的 kid, 配置文件中的密钥没有
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+struct Verifier {
+    keys: Vec<VerificationKey>,
+    validation: Validation,
+    jwks_url: Option<String>,
+}
+
+// 从远程 JWKS 获取的密钥, 只对获取时所用的地址有效
+struct UrlKeys {
+    url: String,
+    keys: Arc<Vec<VerificationKey>>,
+}
+
+lazy_static! {
+    static ref VERIFIER: RwLock<Option<Arc<Verifier>>> = RwLock::new(None);
+    // 本地 JWKS 文件中的密钥, 重新加载成功时整体替换
+    static ref FILE_KEYS: RwLock<Arc<Vec<VerificationKey>>> = RwLock::new(Arc::new(Vec::new()));
+    static ref URL_KEYS: RwLock<Option<UrlKeys>> = RwLock::new(None);
+    // 上次因未知 kid 获取远程 JWKS 的时间
+    static ref UNKNOWN_KID_FETCHED_AT: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+// 遇到未知 kid 时最多每隔这段时间获取一次远程 JWKS, 防止伪造的 kid 频繁触发请求
+const UNKNOWN_KID_FETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+// 按 PEM 中的密钥类型决定算法
+fn load_public_key(path: &str) -> Result<VerificationKey, String> {
+    let pem = std::fs::read(path).map_err(|err| format!("failed to read public key {}: {}", path, err))?;
+    if let Ok(key) = DecodingKey::from_rsa_pem(&pem) {
+        return Ok(VerificationKey {
+            kid: None,
+            algorithm: Algorithm::RS256,
+            key,
+        });
+    }
+    if let Ok(key) = DecodingKey::from_ec_pem(&pem) {
+        return Ok(VerificationKey {
+            kid: None,
+            algorithm: Algorithm::ES256,
+            key,
+        });
+    }
+    Err(format!("public key {} is neither an RSA nor an EC key", path))
+}
+
+// 未声明 alg 时按密钥类型决定算法
+fn jwk_algorithm(jwk: &Jwk) -> Result<Algorithm, String> {
+    if let Some(algorithm) = jwk.common.algorithm {
+        return Ok(algorithm);
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => Ok(Algorithm::RS256),
+        AlgorithmParameters::EllipticCurve(params) => match params.curve {
+            EllipticCurve::P256 => Ok(Algorithm::ES256),
+            EllipticCurve::P384 => Ok(Algorithm::ES384),
+            _ => Err(String::from("unsupported elliptic curve")),
+        },
+        AlgorithmParameters::OctetKeyPair(_) => Ok(Algorithm::EdDSA),
+        AlgorithmParameters::OctetKey(_) => Ok(Algorithm::HS256),
+    }
+}
+
+fn jwk_key(jwk: &Jwk) -> Result<VerificationKey, String> {
+    let key = match &jwk.algorithm {
+        // JWK 中的对称密钥是 base64url 编码
+        AlgorithmParameters::OctetKey(params) => {
+            DecodingKey::from_secret(&URL_SAFE_NO_PAD.decode(&params.value).map_err(|err| err.to_string())?)
+        }
+        _ => DecodingKey::from_jwk(jwk).map_err(|err| err.to_string())?,
+    };
+    Ok(VerificationKey {
+        kid: jwk.common.key_id.clone(),
+        algorithm: jwk_algorithm(jwk)?,
+        key,
+    })
+}
+
+// 解析 JWKS, 跳过用于加密的密钥, 没有可用密钥时视为失败
+fn parse_jwks(content: &str) -> Result<Vec<VerificationKey>, String> {
+    let jwks: JwkSet = serde_json::from_str(content).map_err(|err| format!("invalid jwks: {}", err))?;
+    let mut keys = Vec::new();
+    for jwk in jwks.keys.iter() {
+        if matches!(jwk.common.public_key_use, Some(PublicKeyUse::Encryption)) {
+            continue;
+        }
+        let key = jwk_key(jwk).map_err(|err| {
+            format!("invalid jwks key {}: {}", jwk.common.key_id.as_deref().unwrap_or_default(), err)
+        })?;
+        keys.push(key);
+    }
+    if keys.is_empty() {
+        return Err(String::from("jwks contains no signing key"));
+    }
+    Ok(keys)
+}
+
+fn load_jwks_file(path: &Path) -> Result<Vec<VerificationKey>, String> {
+    let content =
+        std::fs::read_to_string(path).map_err(|err| format!("failed to read jwks {}: {}", path.display(), err))?;
+    parse_jwks(&content)
+}
+
+// 加载校验配置, 出错时保留之前的配置
+pub fn set_jwt_config(config: &JwtConfig) -> Result<(), String> {
+    let mut keys = Vec::new();
+    if let Some(secret) = &config.secret {
+        keys.push(VerificationKey {
+            kid: None,
+            algorithm: Algorithm::HS256,
+            key: DecodingKey::from_secret(secret.as_bytes()),
+        });
+    }
+    for path in config.public_key_paths.iter() {
+        keys.push(load_public_key(path)?);
+    }
+    let file_keys = match &config.jwks_path {
+        Some(path) => load_jwks_file(Path::new(path))?,
+        None => Vec::new(),
+    };
+    if keys.is_empty() && file_keys.is_empty() && config.jwks_url.is_none() {
+        return Err(String::from("no token verification key configured"));
+    }
+    if config.jwks_url.is_some() && config.jwks_cache_seconds == 0 {
+        return Err(String::from("jwksCacheSeconds must be greater than 0"));
+    }
+
+    // exp 必须存在, nbf 存在时校验
+    let mut validation = Validation::default();
+    validation.validate_nbf = true;
+    validation.leeway = config.leeway;
+    if let Some(issuer) = &config.issuer {
+        validation.set_issuer(&[issuer]);
+    }
+    if let Some(audience) = &config.audience {
+        validation.set_audience(&[audience]);
+    }
+    *FILE_KEYS.write() = Arc::new(file_keys);
+    *UNKNOWN_KID_FETCHED_AT.lock() = None;
+    *VERIFIER.write() = Some(Arc::new(Verifier {
+        keys,
+        validation,
+        jwks_url: config.jwks_url.clone(),
+    }));
+    Ok(())
+}
+
+// 重新加载本地 JWKS 文件, 失败时保留之前的密钥, 返回加载的密钥数
+pub fn reload_jwks(path: &Path) -> Result<usize, String> {
+    let keys = load_jwks_file(path)?;
+    let count = keys.len();
+    *FILE_KEYS.write() = Arc::new(keys);
+    Ok(count)
+}
+
+// 观察 JWKS 文件变化并重新加载, 返回的 Hotwatch 需要保持存活
+pub fn watch_jwks(watch_path: &str) -> Result<Hotwatch, String> {
+    let mut hot_watch = Hotwatch::new().map_err(|err| err.to_string())?;
+    let jwks_path = PathBuf::from(watch_path);
+    watch_file(&mut hot_watch, watch_path, move || match reload_jwks(&jwks_path) {
+        Ok(count) => println!("jwks refreshed, {} keys", count),
+        Err(err) => println!("{}", err),
+    })
+    .map_err(|err| err.to_string())?;
+    Ok(hot_watch)
+}
+
+// 获取远程 JWKS, 会阻塞当前线程, 失败时保留之前的密钥, 返回获取的密钥数
+fn fetch_jwks_url(url: &str) -> Result<usize, String> {
+    let content = ureq::get(url)
+        .timeout(Duration::from_secs(10))
+        .call()
+        .map_err(|err| err.to_string())
+        .and_then(|response| response.into_string().map_err(|err| err.to_string()))
+        .map_err(|err| format!("failed to fetch jwks {}: {}", url, err))?;
+    let keys = parse_jwks(&content)?;
+    let count = keys.len();
+    *URL_KEYS.write() = Some(UrlKeys {
+        url: url.to_string(),
+        keys: Arc::new(keys),
+    });
+    Ok(count)
+}
+
+// 获取远程 JWKS, 失败时保留之前的密钥, 返回获取的密钥数
+pub async fn refresh_jwks_url(url: &str) -> Result<usize, String> {
+    let url = url.to_string();
+    actix_web::web::block(move || fetch_jwks_url(&url))
+        .await
+        .map_err(|err| err.to_string())?
+}
+
+// 距上次因未知 kid 获取已超过间隔时记录本次获取
+fn take_unknown_kid_fetch() -> bool {
+    let mut fetched_at = UNKNOWN_KID_FETCHED_AT.lock();
+    if fetched_at.is_some_and(|fetched_at| fetched_at.elapsed() < UNKNOWN_KID_FETCH_INTERVAL) {
+        return false;
+    }
+    *fetched_at = Some(Instant::now());
+    true
+}
+
+// 每经过缓存时间重新获取一次远程 JWKS
+pub async fn keep_jwks_url_fresh(url: String, cache_time: Duration) {
+    loop {
+        match refresh_jwks_url(&url).await {
+            Ok(count) => println!("jwks fetched from {}, {} keys", url, count),
+            Err(err) => println!("{}", err),
+        }
+        actix_web::rt::time::sleep(cache_time).await;
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ClaimsData {
+    user_directory: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    data: ClaimsData,
+}
+
+// 校验签名和 exp, nbf, iss, aud, 返回 token 中的用户目录
+pub(crate) async fn verify_token(token: &str) -> Result<String, String> {
+    let verifier = VERIFIER
+        .read()
+        .clone()
+        .ok_or_else(|| String::from("token verification is not configured"))?;
+    let header = decode_header(token).map_err(|_| String::from("invalid token"))?;
+    let mut validation = verifier.validation.clone();
+    validation.algorithms = vec![header.alg];
+
+    let file_keys = FILE_KEYS.read().clone();
+    let cached_url_keys = || match (&*URL_KEYS.read(), &verifier.jwks_url) {
+        (Some(cached), Some(url)) if cached.url == *url => cached.keys.clone(),
+        _ => Arc::new(Vec::new()),
+    };
+    let mut url_keys = cached_url_keys();
+    // 远程密钥可能已轮换, 未知的 kid 在拒绝前重新获取一次
+    if let (Some(kid), Some(url)) = (&header.kid, &verifier.jwks_url) {
+        let is_known = |key: &VerificationKey| key.kid.as_ref() == Some(kid);
+        let known = verifier.keys.iter().chain(file_keys.iter()).chain(url_keys.iter()).any(is_known);
+        if !known && take_unknown_kid_fetch() {
+            // 在阻塞线程中获取, 不占用处理请求的线程
+            match refresh_jwks_url(url).await {
+                Ok(count) => println!("jwks fetched from {} for unknown kid, {} keys", url, count),
+                Err(err) => println!("{}", err),
+            }
+            url_keys = cached_url_keys();
+        }
+    }
+    let keys: Vec<&VerificationKey> = verifier.keys.iter().chain(file_keys.iter()).chain(url_keys.iter()).collect();
+    // token 带有 kid 时只使用该 kid 的密钥, 没有对应的密钥时才使用未设置 kid 的密钥
+    let kid_matches = |key: &&VerificationKey| key.kid.is_some() && key.kid == header.kid;
+    let candidates: Vec<&VerificationKey> = if header.kid.is_some() && keys.iter().any(kid_matches) {
+        keys.into_iter().filter(kid_matches).collect()
+    } else {
+        keys.into_iter().filter(|key| header.kid.is_none() || key.kid.is_none()).collect()
+    };
+
+    // 只使用与算法对应的密钥, 签名不符时尝试下一个
+    let mut claims = None;
+    let mut error = format!("token algorithm {:?} is not accepted", header.alg);
+    for key in candidates.into_iter().filter(|key| key.algorithm == header.alg) {
+        match decode::<Claims>(token, &key.key, &validation) {
+            Ok(token_data) => {
+                claims = Some(token_data.claims);
+                break;
+            }
+            Err(err) => {
+                error = format!("token is invalid: {}", err);
+                if *err.kind() != ErrorKind::InvalidSignature {
+                    break;
+                }
+            }
+        }
+    }
+    let claims = claims.ok_or(error)?;
+    // 用户目录不能离开基本路径
+    relative_path(Path::new(&claims.data.user_directory)).map_err(|_| String::from("userDirectory is invalid"))?;
+    Ok(claims.data.user_directory)
+}
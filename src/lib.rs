@@ -0,0 +1,58 @@
+// 只编译 s3 服务时, 分片上传中其他服务使用的部分不会被用到
+#[cfg(any(feature = "transfer", feature = "upload-large-file", feature = "s3"))]
+#[cfg_attr(not(any(feature = "transfer", feature = "upload-large-file")), allow(dead_code))]
+mod chunk_hash;
+#[cfg(any(feature = "transfer", feature = "upload-large-file", feature = "s3"))]
+#[cfg_attr(not(any(feature = "transfer", feature = "upload-large-file")), allow(dead_code))]
+mod split_chunks_upload_operations_raw;
+#[cfg(any(feature = "transfer", feature = "upload-large-file", feature = "s3"))]
+pub use split_chunks_upload_operations_raw::{
+    restore_chunks_sessions, set_max_chunk_size, set_min_chunk_size, set_session_idle_time,
+    set_session_max_lifetime,
+};
+#[cfg(any(feature = "transfer", feature = "upload-large-file", feature = "s3"))]
+#[cfg_attr(not(any(feature = "transfer", feature = "upload-large-file")), allow(dead_code))]
+mod instant_upload;
+#[cfg(any(feature = "transfer", feature = "upload-large-file", feature = "s3"))]
+pub use instant_upload::restore_stored_files;
+
+#[cfg(any(feature = "transfer", feature = "upload-large-file"))]
+mod actix_split_chunks_upload_handlers;
+#[cfg(any(feature = "transfer", feature = "upload-large-file"))]
+mod actix_tus_handlers;
+#[cfg(any(feature = "transfer", feature = "upload-large-file"))]
+mod actix_ws_handlers;
+// 合并后的处理步骤
+#[cfg(any(feature = "transfer", feature = "upload-large-file"))]
+pub mod post_merge;
+// 各服务通过上传策略接入分片上传的接口
+#[cfg(any(feature = "transfer", feature = "upload-large-file"))]
+pub mod upload_policy;
+#[cfg(any(feature = "transfer", feature = "upload-large-file", feature = "s3"))]
+#[cfg_attr(not(any(feature = "transfer", feature = "upload-large-file")), allow(dead_code, unused_imports))]
+mod actix_utils;
+#[cfg(feature = "s3")]
+mod s3_sigv4;
+
+#[cfg(feature = "transfer")]
+pub mod transfer_serve;
+#[cfg(feature = "cloud-text")]
+pub mod cloud_text_serve;
+#[cfg(feature = "upload-large-file")]
+pub mod upload_large_file;
+// 校验上传大文件服务的 token
+#[cfg(feature = "upload-large-file")]
+pub mod jwt_verify;
+// 解压用户目录中的压缩包
+#[cfg(feature = "upload-large-file")]
+pub mod archive_extract;
+#[cfg(feature = "s3")]
+pub mod s3_serve;
+
+// 只编译 cloud-text 服务时没有需要清理的文件
+#[cfg_attr(not(any(feature = "transfer", feature = "upload-large-file", feature = "s3")), allow(dead_code))]
+pub mod garbage_collector;
+// 观察配置文件的变化
+mod file_watch;
+pub mod server_config;
+pub mod tls;
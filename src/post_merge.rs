@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use lazy_static::lazy_static;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tokio::{fs::File, io::AsyncReadExt, process::Command, task, time::sleep};
+
+#[cfg(feature = "upload-large-file")]
+use crate::archive_extract::{default_extract_dir, extract_archive};
+use crate::chunk_hash::HashAlgorithm;
+use crate::instant_upload::{record_stored_file, stored_file_scope};
+use crate::split_chunks_upload_operations_raw::{MergeJobState, MergeTarget};
+
+// 处理完成后状态保留多少秒
+const PIPELINE_STATUS_SURVIVAL_TIME: u64 = 3600;
+
+// 识别文件类型时读取的文件头长度
+const CONTENT_SNIFF_LENGTH: usize = 8192;
+
+// 合并后的一个处理步骤, 参数为保存路径, 返回该步骤的结果
+// 步骤不应移动或删除文件, 失败时文件保留在原处
+pub trait PostMergeStep: Send + Sync {
+    fn name(&self) -> String;
+
+    fn run(&self, file_path: String) -> BoxFuture<'static, Result<String, String>>;
+
+    // 结果为该算法的文件 hash 时返回算法, 成功后记录到合并会话所在范围用于秒传
+    fn stored_file_hash(&self) -> Option<HashAlgorithm> {
+        None
+    }
+}
+
+// 配置文件中的处理步骤
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum PostMergeStepConfig {
+    // 计算整个文件的 hash 并记录, 之后可以用该算法秒传
+    Checksum {
+        #[serde(default)]
+        algorithm: HashAlgorithm,
+    },
+    // 按文件开头的内容识别文件类型, 无法识别时按扩展名
+    ContentType,
+    // 把压缩包解压到同一目录下去掉扩展名的文件夹, 不是压缩包时跳过
+    #[cfg(feature = "upload-large-file")]
+    Extract,
+    // 执行外部程序, 文件路径追加在参数最后, 退出码为 0 时成功, 结果为标准输出
+    // 用于病毒扫描, 生成缩略图, 通知等
+    #[serde(rename_all = "camelCase")]
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+impl PostMergeStepConfig {
+    pub fn build(&self) -> Arc<dyn PostMergeStep> {
+        match self {
+            PostMergeStepConfig::Checksum { algorithm } => Arc::new(ChecksumStep(*algorithm)),
+            PostMergeStepConfig::ContentType => Arc::new(ContentTypeStep),
+            #[cfg(feature = "upload-large-file")]
+            PostMergeStepConfig::Extract => Arc::new(ExtractStep),
+            PostMergeStepConfig::Command { program, args } => Arc::new(CommandStep {
+                program: program.clone(),
+                args: args.clone(),
+            }),
+        }
+    }
+}
+
+struct ChecksumStep(HashAlgorithm);
+
+impl PostMergeStep for ChecksumStep {
+    fn name(&self) -> String {
+        format!("checksum-{}", self.0.name())
+    }
+
+    fn run(&self, file_path: String) -> BoxFuture<'static, Result<String, String>> {
+        let hash_algorithm = self.0;
+        Box::pin(async move {
+            let mut file = File::open(&file_path).await.map_err(|err| err.to_string())?;
+            let mut hasher = hash_algorithm.hasher();
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                let read = file.read(&mut buffer).await.map_err(|err| err.to_string())?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+            }
+            Ok(hasher.finalize())
+        })
+    }
+
+    fn stored_file_hash(&self) -> Option<HashAlgorithm> {
+        Some(self.0)
+    }
+}
+
+struct ContentTypeStep;
+
+impl PostMergeStep for ContentTypeStep {
+    fn name(&self) -> String {
+        String::from("contentType")
+    }
+
+    fn run(&self, file_path: String) -> BoxFuture<'static, Result<String, String>> {
+        Box::pin(async move {
+            // 扩展名可以随意修改, 优先使用文件头
+            let mut file = File::open(&file_path).await.map_err(|err| err.to_string())?;
+            let mut head = Vec::with_capacity(CONTENT_SNIFF_LENGTH);
+            (&mut file)
+                .take(CONTENT_SNIFF_LENGTH as u64)
+                .read_to_end(&mut head)
+                .await
+                .map_err(|err| err.to_string())?;
+            if let Some(kind) = infer::get(&head) {
+                return Ok(String::from(kind.mime_type()));
+            }
+            let extension = Path::new(&file_path)
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .unwrap_or("");
+            Ok(actix_files::file_extension_to_mime(extension).to_string())
+        })
+    }
+}
+
+#[cfg(feature = "upload-large-file")]
+struct ExtractStep;
+
+#[cfg(feature = "upload-large-file")]
+impl PostMergeStep for ExtractStep {
+    fn name(&self) -> String {
+        String::from("extract")
+    }
+
+    // 结果为解压出的文件列表
+    fn run(&self, file_path: String) -> BoxFuture<'static, Result<String, String>> {
+        Box::pin(async move {
+            let archive_path = Path::new(&file_path).to_path_buf();
+            let target_dir = match default_extract_dir(&archive_path) {
+                Some(target_dir) => target_dir,
+                None => return Ok(String::from("not an archive")),
+            };
+            let report = task::spawn_blocking(move || extract_archive(&archive_path, &target_dir))
+                .await
+                .map_err(|err| err.to_string())??;
+            serde_json::to_string(&report).map_err(|err| err.to_string())
+        })
+    }
+}
+
+struct CommandStep {
+    program: String,
+    args: Vec<String>,
+}
+
+impl PostMergeStep for CommandStep {
+    fn name(&self) -> String {
+        self.program.clone()
+    }
+
+    fn run(&self, file_path: String) -> BoxFuture<'static, Result<String, String>> {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).arg(file_path);
+        Box::pin(async move {
+            let output = command.output().await.map_err(|err| err.to_string())?;
+            if output.status.success() {
+                Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+            } else {
+                let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+                Err(if stderr.is_empty() { output.status.to_string() } else { stderr })
+            }
+        })
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum PostMergeStepState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Skipped, // 之前的步骤失败
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PostMergeStepStatus {
+    pub name: String,
+    pub state: PostMergeStepState,
+    pub result: Option<String>,
+    pub error: Option<String>,
+}
+
+// 一次合并后的处理进度
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PostMergeStatus {
+    pub state: MergeJobState,
+    pub steps: Vec<PostMergeStepStatus>,
+    // 同一 key 再次合并时, 旧的清理任务不能删除新的状态
+    #[serde(skip)]
+    run_id: u64,
+    // 合并会话的创建者, 设置后只有创建者可以查询
+    #[serde(skip)]
+    owner: Option<String>,
+}
+
+lazy_static! {
+    // 各服务的处理步骤, 按服务名保存
+    static ref PIPELINES: RwLock<HashMap<String, Vec<Arc<dyn PostMergeStep>>>> = RwLock::new(HashMap::new());
+    static ref PIPELINE_STATUSES: Mutex<HashMap<String, PostMergeStatus>> = Mutex::new(HashMap::new());
+}
+
+// 设置服务合并后的处理步骤, 按顺序执行, 为空时不处理
+pub fn set_post_merge_steps(service: &str, steps: Vec<Arc<dyn PostMergeStep>>) {
+    PIPELINES.write().insert(String::from(service), steps);
+}
+
+fn update_status(key: &str, run_id: u64, update: impl FnOnce(&mut PostMergeStatus)) {
+    if let Some(status) = PIPELINE_STATUSES.lock().get_mut(key) {
+        if status.run_id == run_id {
+            update(status);
+        }
+    }
+}
+
+// 在后台按顺序执行服务的处理步骤, 进度通过 key 查询
+// 每个步骤在单独的任务中执行, 步骤失败或 panic 时之后的步骤跳过, 文件保留
+pub(crate) fn start_post_merge_pipeline(service: &str, key: String, owner: Option<String>, file_path: String) {
+    let steps = match PIPELINES.read().get(service) {
+        Some(steps) if !steps.is_empty() => steps.clone(),
+        _ => return,
+    };
+    let run_id = rand::random::<u64>();
+    let scope = stored_file_scope(service, owner.as_deref());
+    PIPELINE_STATUSES.lock().insert(
+        key.clone(),
+        PostMergeStatus {
+            state: MergeJobState::Running,
+            steps: steps
+                .iter()
+                .map(|step| PostMergeStepStatus {
+                    name: step.name(),
+                    state: PostMergeStepState::Pending,
+                    result: None,
+                    error: None,
+                })
+                .collect(),
+            run_id,
+            owner,
+        },
+    );
+
+    task::spawn(async move {
+        let mut failed = false;
+        for (index, step) in steps.iter().enumerate() {
+            if failed {
+                update_status(&key, run_id, |status| status.steps[index].state = PostMergeStepState::Skipped);
+                continue;
+            }
+            update_status(&key, run_id, |status| status.steps[index].state = PostMergeStepState::Running);
+            let result = match task::spawn(step.run(file_path.clone())).await {
+                Ok(result) => result,
+                Err(err) => Err(err.to_string()),
+            };
+            match (&result, step.stored_file_hash()) {
+                (Ok(file_hash), Some(hash_algorithm)) => {
+                    record_stored_file(&scope, hash_algorithm, file_hash, &file_path).await;
+                }
+                (Err(err), _) => {
+                    println!("post-merge step {} failed for {}: {}", step.name(), file_path, err);
+                    failed = true;
+                }
+                _ => {}
+            }
+            update_status(&key, run_id, |status| {
+                let step_status = &mut status.steps[index];
+                match result {
+                    Ok(result) => {
+                        step_status.state = PostMergeStepState::Succeeded;
+                        step_status.result = Some(result);
+                    }
+                    Err(err) => {
+                        step_status.state = PostMergeStepState::Failed;
+                        step_status.error = Some(err);
+                    }
+                }
+            });
+        }
+        update_status(&key, run_id, |status| {
+            status.state = if failed { MergeJobState::Failed } else { MergeJobState::Succeeded };
+        });
+
+        sleep(Duration::from_secs(PIPELINE_STATUS_SURVIVAL_TIME)).await;
+        let mut statuses = PIPELINE_STATUSES.lock();
+        if statuses.get(&key).is_some_and(|status| status.run_id == run_id) {
+            statuses.remove(&key);
+        }
+    });
+}
+
+pub fn get_post_merge_status_raw(key: &str, owner: Option<&str>) -> Option<PostMergeStatus> {
+    PIPELINE_STATUSES
+        .lock()
+        .get(key)
+        .filter(|status| status.owner.is_none() || status.owner.as_deref() == owner)
+        .cloned()
+}
+
+// 包装服务的合并目标, 合并目标完成后启动服务的处理步骤
+pub(crate) struct PipelineTarget<T> {
+    service: &'static str,
+    key: String,
+    owner: Option<String>,
+    target: T,
+}
+
+impl<T> PipelineTarget<T> {
+    pub(crate) fn new(service: &'static str, key: String, owner: Option<String>, target: T) -> Self {
+        PipelineTarget { service, key, owner, target }
+    }
+}
+
+impl<T: MergeTarget> MergeTarget for PipelineTarget<T> {
+    fn save_path(&self, base_path: &str, full_path: &str) -> String {
+        self.target.save_path(base_path, full_path)
+    }
+
+    fn root_path(&self, base_path: &str) -> String {
+        self.target.root_path(base_path)
+    }
+
+    fn after_merge(&self, file_path: String) -> BoxFuture<'static, Result<String, String>> {
+        let after_merge = self.target.after_merge(file_path.clone());
+        let (service, key, owner) = (self.service, self.key.clone(), self.owner.clone());
+        Box::pin(async move {
+            let result = after_merge.await?;
+            start_post_merge_pipeline(service, key, owner, file_path);
+            Ok(result)
+        })
+    }
+}
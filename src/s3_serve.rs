@@ -0,0 +1,547 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_files::NamedFile;
+use actix_web::{delete, get, guard, head, http::StatusCode, put, web, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose, Engine as _};
+use futures::{future, stream, StreamExt};
+use hotwatch::Hotwatch;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::actix_utils::get_header;
+use crate::s3_sigv4::{verify_request, PayloadHash, SignatureError};
+use crate::split_chunks_upload_operations_raw::{
+    complete_multipart_raw,
+    create_multipart_session_raw,
+    list_parts_raw,
+    remove_upload_session_raw,
+    session_key,
+    upload_part_raw,
+    ChunkContentStream,
+    MergeTarget,
+    MultipartError,
+    MAX_SIZE,
+};
+
+use lazy_static::lazy_static;
+
+// chunk 会话所属服务
+const SERVICE_NAME: &str = "s3";
+
+// 对象保存在 ./files/s3/{bucket}/{key}
+const OBJECTS_PATH: &str = "./files/s3/";
+
+// 合并后的对象保存到对象目录
+struct ObjectsTarget;
+
+impl MergeTarget for ObjectsTarget {
+    fn save_path(&self, base_path: &str, full_path: &str) -> String {
+        format!("{}s3/{}", base_path, full_path)
+    }
+}
+
+// S3 分段序号为 1 到 10000
+const MAX_PARTS: usize = 10000;
+
+const DEFAULT_MAX_PARTS_LISTED: usize = 1000;
+
+// POST 请求体最大 4MB, 足够列出 10000 个分段的 CompleteMultipartUpload
+const MAX_POST_BODY_SIZE: usize = 4194304;
+
+// 分段上传合并后对象的最大字节数
+const MAX_OBJECT_SIZE: u64 = MAX_SIZE as u64;
+
+const XMLNS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+// 本地配置的访问密钥
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct S3Credential {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+lazy_static! {
+    static ref CREDENTIALS: Arc<RwLock<HashMap<String, String>>> = Arc::new(RwLock::new(HashMap::new()));
+}
+
+#[derive(Serialize)]
+#[serde(rename = "Error", rename_all = "PascalCase")]
+struct ErrorResult<'a> {
+    code: &'a str,
+    message: String,
+    resource: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "InitiateMultipartUploadResult", rename_all = "PascalCase")]
+struct InitiateMultipartUploadResult<'a> {
+    #[serde(rename = "@xmlns")]
+    xmlns: &'a str,
+    bucket: &'a str,
+    key: &'a str,
+    upload_id: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedPart {
+    part_number: usize,
+    #[serde(rename = "ETag")]
+    etag: String,
+    size: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "ListPartsResult", rename_all = "PascalCase")]
+struct ListPartsResult<'a> {
+    #[serde(rename = "@xmlns")]
+    xmlns: &'a str,
+    bucket: &'a str,
+    key: &'a str,
+    upload_id: &'a str,
+    part_number_marker: usize,
+    next_part_number_marker: usize,
+    max_parts: usize,
+    is_truncated: bool,
+    part: Vec<ListedPart>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CompletedPart {
+    part_number: usize,
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CompleteMultipartUpload {
+    #[serde(default)]
+    part: Vec<CompletedPart>,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "CompleteMultipartUploadResult", rename_all = "PascalCase")]
+struct CompleteMultipartUploadResult<'a> {
+    #[serde(rename = "@xmlns")]
+    xmlns: &'a str,
+    location: &'a str,
+    bucket: &'a str,
+    key: &'a str,
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+fn xml_response<T: Serialize>(status: StatusCode, body: &T) -> HttpResponse {
+    match quick_xml::se::to_string(body) {
+        Ok(body) => HttpResponse::build(status)
+            .content_type("application/xml")
+            .body(format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}", body)),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+fn s3_error(req: &HttpRequest, status: StatusCode, code: &str, message: impl ToString) -> HttpResponse {
+    xml_response(
+        status,
+        &ErrorResult {
+            code,
+            message: message.to_string(),
+            resource: req.path(),
+        },
+    )
+}
+
+fn signature_error(req: &HttpRequest, err: SignatureError) -> HttpResponse {
+    let (status, code) = match err {
+        SignatureError::Missing => (StatusCode::FORBIDDEN, "AccessDenied"),
+        SignatureError::Malformed(_) => (StatusCode::BAD_REQUEST, "AuthorizationHeaderMalformed"),
+        SignatureError::UnknownAccessKey => (StatusCode::FORBIDDEN, "InvalidAccessKeyId"),
+        SignatureError::RequestTimeTooSkewed => (StatusCode::FORBIDDEN, "RequestTimeTooSkewed"),
+        SignatureError::Expired => (StatusCode::FORBIDDEN, "AccessDenied"),
+        SignatureError::Mismatch => (StatusCode::FORBIDDEN, "SignatureDoesNotMatch"),
+        SignatureError::Unsupported(_) => (StatusCode::NOT_IMPLEMENTED, "NotImplemented"),
+    };
+    s3_error(req, status, code, err)
+}
+
+fn multipart_error(req: &HttpRequest, err: &MultipartError) -> HttpResponse {
+    let (status, code) = match err {
+        MultipartError::NoSuchUpload => (StatusCode::NOT_FOUND, "NoSuchUpload"),
+        MultipartError::InvalidPart(_) => (StatusCode::BAD_REQUEST, "InvalidPart"),
+        MultipartError::InvalidPartOrder => (StatusCode::BAD_REQUEST, "InvalidPartOrder"),
+        MultipartError::BadDigest => (StatusCode::BAD_REQUEST, "BadDigest"),
+        MultipartError::EntityTooLarge(_) => (StatusCode::BAD_REQUEST, "EntityTooLarge"),
+        MultipartError::Failed(_) => (StatusCode::BAD_REQUEST, "InvalidRequest"),
+    };
+    s3_error(req, status, code, err)
+}
+
+// 验证签名, 失败时返回错误响应
+fn authorize(req: &HttpRequest) -> Result<PayloadHash, HttpResponse> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    verify_request(req, |access_key_id| CREDENTIALS.read().get(access_key_id).cloned(), now)
+        .map_err(|err| signature_error(req, err))
+}
+
+// 签名中声明的 hash 与请求体不一致
+fn check_payload(req: &HttpRequest, payload_hash: &PayloadHash, content: &[u8]) -> Result<(), HttpResponse> {
+    if payload_hash.matches(content) {
+        Ok(())
+    } else {
+        Err(s3_error(
+            req,
+            StatusCode::BAD_REQUEST,
+            "XAmzContentSHA256Mismatch",
+            "x-amz-content-sha256 does not match the payload",
+        ))
+    }
+}
+
+// bucket 和 key 都会成为文件路径的一部分, 不允许跳出对象目录
+fn object_location(req: &HttpRequest) -> Result<(String, String), HttpResponse> {
+    let bucket = req.match_info().query("bucket");
+    let key = req.match_info().query("key");
+    let valid_bucket = (3..=63).contains(&bucket.len())
+        && bucket
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'.' || byte == b'-');
+    if !valid_bucket {
+        return Err(s3_error(req, StatusCode::BAD_REQUEST, "InvalidBucketName", "invalid bucket name"));
+    }
+    let valid_key = !key.is_empty()
+        && key
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != "..");
+    if !valid_key {
+        return Err(s3_error(req, StatusCode::BAD_REQUEST, "KeyTooLongError", "unsupported object key"));
+    }
+    Ok((String::from(bucket), String::from(key)))
+}
+
+fn query_params(req: &HttpRequest) -> HashMap<String, String> {
+    web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(|query| query.into_inner())
+        .unwrap_or_default()
+}
+
+// 分段上传会话绑定到 bucket 和 key, 其他对象无法使用该上传 id
+fn upload_identify(bucket: &str, key: &str, upload_id: &str) -> String {
+    session_key(SERVICE_NAME, &format!("{}/{}/{}", bucket, key, upload_id))
+}
+
+fn part_index(req: &HttpRequest, part_number: &str) -> Result<usize, HttpResponse> {
+    match part_number.parse::<usize>() {
+        Ok(part_number) if (1..=MAX_PARTS).contains(&part_number) => Ok(part_number - 1),
+        _ => Err(s3_error(
+            req,
+            StatusCode::BAD_REQUEST,
+            "InvalidArgument",
+            format!("part number must be an integer between 1 and {}", MAX_PARTS),
+        )),
+    }
+}
+
+fn etag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+// 与 S3 一致: 各分段 md5 拼接后的 md5, 加上分段数
+fn multipart_etag(parts_hash: &[String]) -> String {
+    let mut digests = Vec::new();
+    for hash in parts_hash.iter() {
+        for index in (0..hash.len()).step_by(2) {
+            digests.push(u8::from_str_radix(&hash[index..index + 2], 16).unwrap_or(0));
+        }
+    }
+    format!("\"{:x}-{}\"", md5::compute(digests), parts_hash.len())
+}
+
+// 边接收边计算 sha256, 与签名中的 hash 不一致时在结尾产生错误, 不会存入 chunk
+fn verified_payload(payload: web::Payload, payload_hash: PayloadHash) -> ChunkContentStream {
+    let hasher = Arc::new(Mutex::new(Sha256::new()));
+    let update_hasher = hasher.clone();
+    let content = payload.map(move |chunk| {
+        let chunk = chunk?;
+        update_hasher.lock().update(&chunk);
+        Ok(chunk)
+    });
+    let check = stream::once(async move {
+        match payload_hash {
+            PayloadHash::Sha256(expected) if format!("{:x}", hasher.lock().clone().finalize()) != expected => {
+                Some(Err("x-amz-content-sha256 does not match the payload".into()))
+            }
+            _ => None,
+        }
+    });
+    Box::pin(content.map(Some).chain(check).filter_map(future::ready))
+}
+
+// CreateMultipartUpload(?uploads) 和 CompleteMultipartUpload(?uploadId)
+// 请求体大小限制只作用于该路由, 在 actix_configure 中注册
+async fn post_object(req: HttpRequest, body: web::Bytes) -> HttpResponse {
+    let payload_hash = match authorize(&req) {
+        Ok(payload_hash) => payload_hash,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_payload(&req, &payload_hash, &body) {
+        return response;
+    }
+    let (bucket, key) = match object_location(&req) {
+        Ok(location) => location,
+        Err(response) => return response,
+    };
+    let params = query_params(&req);
+
+    if params.contains_key("uploads") {
+        let upload_id = format!("{:032x}", rand::random::<u128>());
+        if let Err(err) = create_multipart_session_raw(&upload_identify(&bucket, &key, &upload_id), MAX_PARTS).await {
+            return s3_error(&req, StatusCode::INTERNAL_SERVER_ERROR, "InternalError", err);
+        }
+        return xml_response(
+            StatusCode::OK,
+            &InitiateMultipartUploadResult {
+                xmlns: XMLNS,
+                bucket: &bucket,
+                key: &key,
+                upload_id: &upload_id,
+            },
+        );
+    }
+
+    let upload_id = match params.get("uploadId") {
+        Some(upload_id) => upload_id,
+        None => return s3_error(&req, StatusCode::NOT_IMPLEMENTED, "NotImplemented", "only multipart uploads are supported"),
+    };
+    let request: CompleteMultipartUpload = match std::str::from_utf8(&body)
+        .map_err(|err| err.to_string())
+        .and_then(|body| quick_xml::de::from_str(body).map_err(|err| err.to_string()))
+    {
+        Ok(request) => request,
+        Err(err) => return s3_error(&req, StatusCode::BAD_REQUEST, "MalformedXML", err),
+    };
+    if request.part.is_empty() {
+        return s3_error(&req, StatusCode::BAD_REQUEST, "MalformedXML", "at least one part is required");
+    }
+    let mut parts = Vec::with_capacity(request.part.len());
+    for part in request.part.iter() {
+        match part_index(&req, &part.part_number.to_string()) {
+            Ok(index) => parts.push((index, String::from(part.etag.trim_matches('"')))),
+            Err(response) => return response,
+        }
+    }
+    let parts_hash: Vec<String> = parts.iter().map(|(_, hash)| hash.to_ascii_lowercase()).collect();
+
+    let identify = upload_identify(&bucket, &key, upload_id);
+    let object_path = format!("{}/{}", bucket, key);
+    match complete_multipart_raw(&identify, parts, object_path, &ObjectsTarget, MAX_OBJECT_SIZE).await {
+        Ok(_) => xml_response(
+            StatusCode::OK,
+            &CompleteMultipartUploadResult {
+                xmlns: XMLNS,
+                location: req.path(),
+                bucket: &bucket,
+                key: &key,
+                etag: multipart_etag(&parts_hash),
+            },
+        ),
+        Err(err) => match err.downcast_ref::<MultipartError>() {
+            Some(err) => multipart_error(&req, err),
+            None => s3_error(&req, StatusCode::INTERNAL_SERVER_ERROR, "InternalError", err),
+        },
+    }
+}
+
+// UploadPart(?partNumber&uploadId), 响应头 ETag 为分段的 md5
+#[put("/{bucket}/{key:.*}")]
+async fn put_object(req: HttpRequest, payload: web::Payload) -> HttpResponse {
+    let payload_hash = match authorize(&req) {
+        Ok(payload_hash) => payload_hash,
+        Err(response) => return response,
+    };
+    let (bucket, key) = match object_location(&req) {
+        Ok(location) => location,
+        Err(response) => return response,
+    };
+    let params = query_params(&req);
+    let (part_number, upload_id) = match (params.get("partNumber"), params.get("uploadId")) {
+        (Some(part_number), Some(upload_id)) => (part_number, upload_id),
+        _ => return s3_error(&req, StatusCode::NOT_IMPLEMENTED, "NotImplemented", "only multipart uploads are supported"),
+    };
+    let index = match part_index(&req, part_number) {
+        Ok(index) => index,
+        Err(response) => return response,
+    };
+    // Content-MD5 为 base64 编码的 md5
+    let expected_hash = match get_header(&req, "Content-MD5").map(|md5| general_purpose::STANDARD.decode(md5)) {
+        Some(Ok(md5)) => Some(md5.iter().map(|byte| format!("{:02x}", byte)).collect()),
+        Some(Err(_)) => return s3_error(&req, StatusCode::BAD_REQUEST, "InvalidDigest", "invalid Content-MD5"),
+        None => None,
+    };
+
+    let identify = upload_identify(&bucket, &key, upload_id);
+    match upload_part_raw(&identify, index, verified_payload(payload, payload_hash), expected_hash).await {
+        Ok(hash) => HttpResponse::Ok().insert_header(("ETag", etag(&hash))).finish(),
+        Err(err) => multipart_error(&req, &err),
+    }
+}
+
+// ListParts(?uploadId) 或 GetObject
+#[get("/{bucket}/{key:.*}")]
+async fn get_object(req: HttpRequest) -> HttpResponse {
+    let payload_hash = match authorize(&req) {
+        Ok(payload_hash) => payload_hash,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_payload(&req, &payload_hash, &[]) {
+        return response;
+    }
+    let (bucket, key) = match object_location(&req) {
+        Ok(location) => location,
+        Err(response) => return response,
+    };
+    let params = query_params(&req);
+    let upload_id = match params.get("uploadId") {
+        Some(upload_id) => upload_id,
+        None => return serve_object(&req, &bucket, &key).await,
+    };
+
+    let parts = match list_parts_raw(&upload_identify(&bucket, &key, upload_id)).await {
+        Some(parts) => parts,
+        None => return multipart_error(&req, &MultipartError::NoSuchUpload),
+    };
+    let marker = params.get("part-number-marker").and_then(|marker| marker.parse().ok()).unwrap_or(0);
+    let max_parts = params
+        .get("max-parts")
+        .and_then(|max_parts| max_parts.parse().ok())
+        .unwrap_or(DEFAULT_MAX_PARTS_LISTED)
+        .min(DEFAULT_MAX_PARTS_LISTED);
+    let mut listed: Vec<ListedPart> = parts
+        .into_iter()
+        .map(|(index, hash, size)| ListedPart {
+            part_number: index + 1,
+            etag: etag(&hash),
+            size,
+        })
+        .filter(|part| part.part_number > marker)
+        .take(max_parts + 1)
+        .collect();
+    let is_truncated = listed.len() > max_parts;
+    listed.truncate(max_parts);
+    xml_response(
+        StatusCode::OK,
+        &ListPartsResult {
+            xmlns: XMLNS,
+            bucket: &bucket,
+            key: &key,
+            upload_id,
+            part_number_marker: marker,
+            next_part_number_marker: listed.last().map_or(marker, |part| part.part_number),
+            max_parts,
+            is_truncated,
+            part: listed,
+        },
+    )
+}
+
+// HeadObject
+#[head("/{bucket}/{key:.*}")]
+async fn head_object(req: HttpRequest) -> HttpResponse {
+    if let Err(response) = authorize(&req) {
+        return response;
+    }
+    match object_location(&req) {
+        Ok((bucket, key)) => serve_object(&req, &bucket, &key).await,
+        Err(response) => response,
+    }
+}
+
+// 文件支持 Range, HEAD 请求只返回响应头
+async fn serve_object(req: &HttpRequest, bucket: &str, key: &str) -> HttpResponse {
+    match NamedFile::open_async(format!("{}{}/{}", OBJECTS_PATH, bucket, key)).await {
+        Ok(file) => file.disable_content_disposition().into_response(req),
+        Err(_) => s3_error(req, StatusCode::NOT_FOUND, "NoSuchKey", "object not found"),
+    }
+}
+
+// AbortMultipartUpload(?uploadId)
+#[delete("/{bucket}/{key:.*}")]
+async fn delete_object(req: HttpRequest) -> HttpResponse {
+    let payload_hash = match authorize(&req) {
+        Ok(payload_hash) => payload_hash,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_payload(&req, &payload_hash, &[]) {
+        return response;
+    }
+    let (bucket, key) = match object_location(&req) {
+        Ok(location) => location,
+        Err(response) => return response,
+    };
+    let upload_id = match query_params(&req).remove("uploadId") {
+        Some(upload_id) => upload_id,
+        None => return s3_error(&req, StatusCode::NOT_IMPLEMENTED, "NotImplemented", "only multipart uploads are supported"),
+    };
+    match remove_upload_session_raw(&upload_identify(&bucket, &key, &upload_id), None).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(_) => multipart_error(&req, &MultipartError::NoSuchUpload),
+    }
+}
+
+pub fn actix_configure(config: &mut web::ServiceConfig) {
+    config
+        .service(
+            web::resource("/{bucket}/{key:.*}")
+                .guard(guard::Post())
+                .app_data(web::PayloadConfig::new(MAX_POST_BODY_SIZE))
+                .to(post_object),
+        )
+        .service(put_object)
+        .service(get_object)
+        .service(head_object)
+        .service(delete_object);
+}
+
+// 在外部调用该方法更新访问密钥
+pub fn update_credentials(credentials: Vec<S3Credential>) {
+    let mut current = CREDENTIALS.write();
+    *current = credentials
+        .into_iter()
+        .map(|credential| (credential.access_key_id, credential.secret_access_key))
+        .collect();
+}
+
+// 读取访问密钥文件
+fn load_credentials(path: &std::path::Path) -> Result<Vec<S3Credential>, String> {
+    let content = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    serde_json::from_str(&content).map_err(|_| String::from("read watched file failed"))
+}
+
+// 启动时读取一次访问密钥, 之后观察文件变化更新, 返回的 Hotwatch 需要保持存活
+pub fn watch_credentials(watch_path: &str) -> Result<Hotwatch, String> {
+    match load_credentials(std::path::Path::new(watch_path)) {
+        Ok(credentials) => update_credentials(credentials),
+        Err(err) => println!("{}", err),
+    }
+
+    let mut hot_watch = Hotwatch::new().map_err(|err| err.to_string())?;
+    let path = String::from(watch_path);
+    crate::file_watch::watch_file(&mut hot_watch, watch_path, move || {
+        match load_credentials(std::path::Path::new(&path)) {
+            Ok(credentials) => {
+                update_credentials(credentials);
+                println!("s3 credentials refreshed");
+            }
+            Err(err) => {
+                println!("{}", err);
+            }
+        }
+    })
+    .map_err(|err| err.to_string())?;
+    Ok(hot_watch)
+}
@@ -0,0 +1,330 @@
+use actix_web::HttpRequest;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use urlencoding::decode;
+
+use crate::actix_utils::get_header;
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+// 请求时间与服务器时间最多相差15分钟
+const MAX_CLOCK_SKEW: u64 = 900;
+
+// 预签名 url 最长有效7天
+const MAX_PRESIGNED_EXPIRES: u64 = 604800;
+
+// 请求体的 hash, 验证签名后由上层在读取请求体时校验
+pub enum PayloadHash {
+    Unsigned,
+    Sha256(String),
+}
+
+impl PayloadHash {
+    pub fn matches(&self, content: &[u8]) -> bool {
+        match self {
+            PayloadHash::Unsigned => true,
+            PayloadHash::Sha256(expected) => &sha256_hex(content) == expected,
+        }
+    }
+}
+
+// 签名验证失败的原因, 对应 S3 的错误码
+#[derive(Debug)]
+pub enum SignatureError {
+    Missing,
+    Malformed(String),
+    UnknownAccessKey,
+    RequestTimeTooSkewed,
+    Expired,
+    Mismatch,
+    Unsupported(String),
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "request is not signed"),
+            SignatureError::Malformed(err) => write!(f, "malformed signature: {}", err),
+            SignatureError::UnknownAccessKey => write!(f, "access key id does not exist"),
+            SignatureError::RequestTimeTooSkewed => write!(f, "request time is too far from server time"),
+            SignatureError::Expired => write!(f, "request has expired"),
+            SignatureError::Mismatch => write!(f, "signature does not match"),
+            SignatureError::Unsupported(err) => write!(f, "{} is not supported", err),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+// 签名的各个组成部分, 来自 Authorization 头或预签名 url 的参数
+struct SignedRequest {
+    access_key_id: String,
+    scope: String, // 日期/区域/s3/aws4_request
+    amz_date: String,
+    signed_headers: Vec<String>,
+    signature: String,
+    payload_hash: String,
+}
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+fn hmac_sha256(key: &[u8], content: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts keys of any length");
+    mac.update(content.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// RFC 3986 编码, 只保留非保留字符, 路径中的 / 不编码
+fn uri_encode(content: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(content.len());
+    for byte in content.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// 查询参数解码后按 AWS 规则重新编码, 按名称和值排序
+fn canonical_query(query: &str, skip_signature: bool) -> Result<String, SignatureError> {
+    let mut params = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let name = decode(name).map_err(|err| SignatureError::Malformed(err.to_string()))?;
+        let value = decode(value).map_err(|err| SignatureError::Malformed(err.to_string()))?;
+        if skip_signature && name == "X-Amz-Signature" {
+            continue;
+        }
+        params.push((uri_encode(&name, true), uri_encode(&value, true)));
+    }
+    params.sort();
+    Ok(params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join("&"))
+}
+
+// 多个同名头用逗号连接, 去掉首尾空白并合并连续空格
+fn canonical_header_value(req: &HttpRequest, name: &str) -> Option<String> {
+    let mut values = Vec::new();
+    for value in req.headers().get_all(name) {
+        let value = value.to_str().ok()?;
+        values.push(value.split_whitespace().collect::<Vec<&str>>().join(" "));
+    }
+    if values.is_empty() && name == "host" {
+        // HTTP/2 请求没有 host 头
+        values.push(String::from(req.connection_info().host()));
+    }
+    (!values.is_empty()).then(|| values.join(","))
+}
+
+fn canonical_request(req: &HttpRequest, signed: &SignedRequest, presigned: bool) -> Result<String, SignatureError> {
+    let path = decode(req.uri().path()).map_err(|err| SignatureError::Malformed(err.to_string()))?;
+    let mut headers = String::new();
+    for name in signed.signed_headers.iter() {
+        let value = canonical_header_value(req, name)
+            .ok_or_else(|| SignatureError::Malformed(format!("signed header {} is missing", name)))?;
+        headers.push_str(&format!("{}:{}\n", name, value));
+    }
+    Ok(format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        req.method().as_str(),
+        uri_encode(&path, false),
+        canonical_query(req.query_string(), presigned)?,
+        headers,
+        signed.signed_headers.join(";"),
+        signed.payload_hash,
+    ))
+}
+
+// YYYYMMDDTHHMMSSZ 转为 unix 秒
+fn parse_amz_date(amz_date: &str) -> Option<u64> {
+    let bytes = amz_date.as_bytes();
+    if bytes.len() != 16 || bytes[8] != b'T' || bytes[15] != b'Z' {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| amz_date.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (number(0..4)?, number(4..6)?, number(6..8)?);
+    let (hour, minute, second) = (number(9..11)?, number(11..13)?, number(13..15)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    // 公历日期到 1970-01-01 的天数
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+// Credential 为 访问密钥/日期/区域/s3/aws4_request
+fn parse_credential(credential: &str, amz_date: &str) -> Result<(String, String), SignatureError> {
+    let (access_key_id, scope) = credential
+        .split_once('/')
+        .ok_or_else(|| SignatureError::Malformed(String::from("invalid credential")))?;
+    let parts: Vec<&str> = scope.split('/').collect();
+    if parts.len() != 4 || parts[2] != "s3" || parts[3] != "aws4_request" {
+        return Err(SignatureError::Malformed(String::from("invalid credential scope")));
+    }
+    if !amz_date.starts_with(parts[0]) {
+        return Err(SignatureError::Malformed(String::from("credential date does not match x-amz-date")));
+    }
+    Ok((String::from(access_key_id), String::from(scope)))
+}
+
+fn parse_signed_headers(signed_headers: &str) -> Result<Vec<String>, SignatureError> {
+    let signed_headers: Vec<String> = signed_headers.split(';').map(|name| name.to_ascii_lowercase()).collect();
+    if !signed_headers.iter().any(|name| name == "host") {
+        return Err(SignatureError::Malformed(String::from("host must be signed")));
+    }
+    Ok(signed_headers)
+}
+
+fn parse_authorization(req: &HttpRequest, authorization: &str, now: u64) -> Result<SignedRequest, SignatureError> {
+    let fields = authorization
+        .strip_prefix(ALGORITHM)
+        .ok_or_else(|| SignatureError::Unsupported(String::from("signature algorithm")))?;
+    let (mut credential, mut signed_headers, mut signature) = (None, None, None);
+    for field in fields.split(',') {
+        match field.trim().split_once('=') {
+            Some(("Credential", value)) => credential = Some(value),
+            Some(("SignedHeaders", value)) => signed_headers = Some(value),
+            Some(("Signature", value)) => signature = Some(value),
+            _ => {}
+        }
+    }
+    let (credential, signed_headers, signature) = match (credential, signed_headers, signature) {
+        (Some(credential), Some(signed_headers), Some(signature)) => (credential, signed_headers, signature),
+        _ => return Err(SignatureError::Malformed(String::from("incomplete authorization header"))),
+    };
+
+    let amz_date = get_header(req, "x-amz-date")
+        .ok_or_else(|| SignatureError::Malformed(String::from("x-amz-date is required")))?;
+    let request_time =
+        parse_amz_date(amz_date).ok_or_else(|| SignatureError::Malformed(String::from("invalid x-amz-date")))?;
+    if now.abs_diff(request_time) > MAX_CLOCK_SKEW {
+        return Err(SignatureError::RequestTimeTooSkewed);
+    }
+    let payload_hash = get_header(req, "x-amz-content-sha256")
+        .ok_or_else(|| SignatureError::Malformed(String::from("x-amz-content-sha256 is required")))?;
+    if payload_hash.starts_with("STREAMING-") {
+        return Err(SignatureError::Unsupported(String::from("streaming payload signature")));
+    }
+
+    let (access_key_id, scope) = parse_credential(credential, amz_date)?;
+    Ok(SignedRequest {
+        access_key_id,
+        scope,
+        amz_date: String::from(amz_date),
+        signed_headers: parse_signed_headers(signed_headers)?,
+        signature: String::from(signature),
+        payload_hash: String::from(payload_hash),
+    })
+}
+
+// 预签名 url, 签名参数都在查询参数中, 请求体不参与签名
+fn parse_presigned(req: &HttpRequest, now: u64) -> Result<SignedRequest, SignatureError> {
+    let mut params = std::collections::HashMap::new();
+    for pair in req.query_string().split('&') {
+        if let Some((name, value)) = pair.split_once('=') {
+            if name.starts_with("X-Amz-") {
+                let value = decode(value).map_err(|err| SignatureError::Malformed(err.to_string()))?;
+                params.insert(name, value.into_owned());
+            }
+        }
+    }
+    let param = |name: &str| {
+        params
+            .get(name)
+            .cloned()
+            .ok_or_else(|| SignatureError::Malformed(format!("{} is required", name)))
+    };
+    if param("X-Amz-Algorithm")? != ALGORITHM {
+        return Err(SignatureError::Unsupported(String::from("signature algorithm")));
+    }
+
+    let amz_date = param("X-Amz-Date")?;
+    let request_time =
+        parse_amz_date(&amz_date).ok_or_else(|| SignatureError::Malformed(String::from("invalid X-Amz-Date")))?;
+    let expires = param("X-Amz-Expires")?
+        .parse::<u64>()
+        .ok()
+        .filter(|expires| *expires <= MAX_PRESIGNED_EXPIRES)
+        .ok_or_else(|| SignatureError::Malformed(String::from("invalid X-Amz-Expires")))?;
+    if now + MAX_CLOCK_SKEW < request_time {
+        return Err(SignatureError::RequestTimeTooSkewed);
+    }
+    if now > request_time + expires {
+        return Err(SignatureError::Expired);
+    }
+
+    let (access_key_id, scope) = parse_credential(&param("X-Amz-Credential")?, &amz_date)?;
+    Ok(SignedRequest {
+        access_key_id,
+        scope,
+        amz_date,
+        signed_headers: parse_signed_headers(&param("X-Amz-SignedHeaders")?)?,
+        signature: param("X-Amz-Signature")?,
+        payload_hash: String::from("UNSIGNED-PAYLOAD"),
+    })
+}
+
+// 验证 SigV4 签名(Authorization 头或预签名 url), 通过后返回请求体应有的 hash
+// secret_of 根据访问密钥查找对应的私钥, now 为当前 unix 秒
+pub fn verify_request(
+    req: &HttpRequest,
+    secret_of: impl Fn(&str) -> Option<String>,
+    now: u64,
+) -> Result<PayloadHash, SignatureError> {
+    let presigned = get_header(req, "Authorization").is_none();
+    let signed = match get_header(req, "Authorization") {
+        Some(authorization) => parse_authorization(req, authorization, now)?,
+        None if req.query_string().contains("X-Amz-Signature=") => parse_presigned(req, now)?,
+        None => return Err(SignatureError::Missing),
+    };
+    let secret = secret_of(&signed.access_key_id).ok_or(SignatureError::UnknownAccessKey)?;
+
+    let canonical_request = canonical_request(req, &signed, presigned)?;
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        signed.amz_date,
+        signed.scope,
+        sha256_hex(canonical_request.as_bytes())
+    );
+    // 签名密钥依次由日期, 区域, 服务和 aws4_request 派生
+    let mut signing_key = format!("AWS4{}", secret).into_bytes();
+    for part in signed.scope.split('/') {
+        signing_key = hmac_sha256(&signing_key, part);
+    }
+    let signature: String = hmac_sha256(&signing_key, &string_to_sign)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    // 逐字节比较全部内容, 避免泄露匹配的长度
+    let matched = signature.len() == signed.signature.len()
+        && signature
+            .bytes()
+            .zip(signed.signature.bytes())
+            .fold(0, |diff, (left, right)| diff | (left ^ right))
+            == 0;
+    if !matched {
+        return Err(SignatureError::Mismatch);
+    }
+
+    match signed.payload_hash.as_str() {
+        "UNSIGNED-PAYLOAD" => Ok(PayloadHash::Unsigned),
+        payload_hash if payload_hash.len() == 64 && payload_hash.bytes().all(|byte| byte.is_ascii_hexdigit()) => {
+            Ok(PayloadHash::Sha256(payload_hash.to_ascii_lowercase()))
+        }
+        _ => Err(SignatureError::Malformed(String::from("invalid x-amz-content-sha256"))),
+    }
+}
@@ -0,0 +1,356 @@
+use actix_web::web;
+use hotwatch::Hotwatch;
+use serde::Deserialize;
+
+use std::{fs as fsSync, io};
+#[cfg(any(feature = "transfer", feature = "upload-large-file"))]
+use std::collections::HashMap;
+
+#[cfg(feature = "upload-large-file")]
+use crate::archive_extract::ExtractLimits;
+#[cfg(feature = "upload-large-file")]
+use crate::jwt_verify::JwtConfig;
+use crate::garbage_collector::GcConfig;
+#[cfg(any(feature = "transfer", feature = "upload-large-file"))]
+use crate::post_merge::PostMergeStepConfig;
+use crate::tls::TlsConfig;
+
+// 可挂载的服务
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ServiceKind {
+    Transfer,
+    CloudText,
+    UploadLargeFile,
+    S3,
+}
+
+impl ServiceKind {
+    // 服务是否被编译进来
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            ServiceKind::Transfer => cfg!(feature = "transfer"),
+            ServiceKind::CloudText => cfg!(feature = "cloud-text"),
+            ServiceKind::UploadLargeFile => cfg!(feature = "upload-large-file"),
+            ServiceKind::S3 => cfg!(feature = "s3"),
+        }
+    }
+
+    // 通过上传策略合并文件的服务名, 其他服务没有合并后的处理
+    pub fn upload_service_name(&self) -> Option<&'static str> {
+        match self {
+            #[cfg(feature = "transfer")]
+            ServiceKind::Transfer => Some(crate::transfer_serve::SERVICE_NAME),
+            #[cfg(feature = "upload-large-file")]
+            ServiceKind::UploadLargeFile => Some(crate::upload_large_file::SERVICE_NAME),
+            _ => None,
+        }
+    }
+
+    // 挂载在同一前缀下时两个服务的路由是否重叠, actix 只会使用先注册的路由
+    fn routes_overlap(&self, other: &ServiceKind) -> bool {
+        match (self, other) {
+            _ if self == other => true,
+            // S3 的 /{bucket}/{key} 匹配所有路径
+            (ServiceKind::S3, _) | (_, ServiceKind::S3) => true,
+            // 都通过上传策略注册 /upload_chunk, /tus 等相同的路由
+            (ServiceKind::Transfer, ServiceKind::UploadLargeFile)
+            | (ServiceKind::UploadLargeFile, ServiceKind::Transfer) => true,
+            _ => false,
+        }
+    }
+
+    fn actix_configure(&self) -> fn(&mut web::ServiceConfig) {
+        match self {
+            #[cfg(feature = "transfer")]
+            ServiceKind::Transfer => crate::transfer_serve::actix_configure,
+            #[cfg(feature = "cloud-text")]
+            ServiceKind::CloudText => crate::cloud_text_serve::actix_configure,
+            #[cfg(feature = "upload-large-file")]
+            ServiceKind::UploadLargeFile => crate::upload_large_file::actix_configure,
+            #[cfg(feature = "s3")]
+            ServiceKind::S3 => crate::s3_serve::actix_configure,
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("service {:?} is not compiled in", self),
+        }
+    }
+}
+
+// 服务挂载到监听器上的路径前缀
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MountConfig {
+    pub service: ServiceKind,
+    #[serde(default)]
+    pub prefix: String,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ListenerConfig {
+    pub bind: String, // 监听地址, 配置 tls 时为 https 地址
+    pub tls: Option<TlsConfig>,
+    pub services: Vec<MountConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerConfig {
+    pub listeners: Vec<ListenerConfig>,
+    // 分片上传中单个 chunk 的最大字节数, 不配置时为 64MB
+    #[serde(default)]
+    pub max_chunk_size: Option<usize>,
+    // 创建上传会话时除最后一个外每个 chunk 的最小字节数, 不配置时为 64KB
+    #[serde(default)]
+    pub min_chunk_size: Option<usize>,
+    // 分片上传会话闲置多少秒后清理, 每次接受 chunk 后重新计时, 不配置时为24小时
+    #[serde(default)]
+    pub chunk_session_idle_time: Option<u64>,
+    // 分片上传会话从创建起最多存在多少秒, 不配置时为7天
+    #[serde(default)]
+    pub chunk_session_max_lifetime: Option<u64>,
+    // 孤立的 chunk 和分享文件的清理, 默认开启
+    #[serde(default)]
+    pub garbage_collection: GcConfig,
+    // 上传大文件服务校验 token 的密钥和声明
+    #[cfg(feature = "upload-large-file")]
+    #[serde(default)]
+    pub token_verification: JwtConfig,
+    // 解压压缩包的总大小和数量限制
+    #[cfg(feature = "upload-large-file")]
+    #[serde(default)]
+    pub archive_extract: ExtractLimits,
+    // 各服务合并后按顺序执行的处理步骤
+    #[cfg(any(feature = "transfer", feature = "upload-large-file"))]
+    #[serde(default)]
+    pub post_merge: HashMap<ServiceKind, Vec<PostMergeStepConfig>>,
+}
+
+impl Default for ServerConfig {
+    // 与原先两个独立程序的端口和服务保持一致
+    fn default() -> Self {
+        let mount = |service| MountConfig {
+            service,
+            prefix: String::new(),
+        };
+        let listeners = vec![
+            ListenerConfig {
+                bind: String::from("0.0.0.0:16383"),
+                tls: None,
+                services: vec![mount(ServiceKind::Transfer), mount(ServiceKind::CloudText)],
+            },
+            ListenerConfig {
+                bind: String::from("0.0.0.0:16382"),
+                tls: None,
+                services: vec![mount(ServiceKind::UploadLargeFile)],
+            },
+        ];
+        // 只保留编译进来的服务
+        ServerConfig {
+            listeners: listeners
+                .into_iter()
+                .map(|mut listener| {
+                    listener.services.retain(|mount| mount.service.is_enabled());
+                    listener
+                })
+                .filter(|listener| !listener.services.is_empty())
+                .collect(),
+            max_chunk_size: None,
+            min_chunk_size: None,
+            chunk_session_idle_time: None,
+            chunk_session_max_lifetime: None,
+            garbage_collection: GcConfig::default(),
+            #[cfg(feature = "upload-large-file")]
+            token_verification: JwtConfig::default(),
+            #[cfg(feature = "upload-large-file")]
+            archive_extract: ExtractLimits::default(),
+            #[cfg(any(feature = "transfer", feature = "upload-large-file"))]
+            post_merge: HashMap::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    // 检查配置中的服务都已编译
+    pub fn validate(&self) -> Result<(), String> {
+        if self.listeners.is_empty() {
+            return Err(String::from("no listener configured"));
+        }
+        for listener in self.listeners.iter() {
+            for mount in listener.services.iter() {
+                if !mount.service.is_enabled() {
+                    return Err(format!(
+                        "service {:?} on {} is not compiled in",
+                        mount.service, listener.bind
+                    ));
+                }
+            }
+            for (index, mount) in listener.services.iter().enumerate() {
+                for other in listener.services[index + 1..].iter() {
+                    if mounts_overlap(mount, other) || mounts_overlap(other, mount) {
+                        return Err(format!(
+                            "routes of {:?} at \"{}\" and {:?} at \"{}\" overlap on {}",
+                            mount.service, mount.prefix, other.service, other.prefix, listener.bind
+                        ));
+                    }
+                }
+            }
+        }
+        #[cfg(feature = "upload-large-file")]
+        if self.mounts_service(ServiceKind::UploadLargeFile) && !self.token_verification.has_keys() {
+            return Err(String::from("uploadLargeFile is mounted but no token verification key is configured"));
+        }
+        #[cfg(any(feature = "transfer", feature = "upload-large-file"))]
+        for service in self.post_merge.keys() {
+            if service.upload_service_name().is_none() {
+                return Err(format!("service {:?} does not support post-merge steps", service));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn mounts_service(&self, service: ServiceKind) -> bool {
+        self.listeners
+            .iter()
+            .any(|listener| listener.services.iter().any(|mount| mount.service == service))
+    }
+
+    // 移除服务的所有挂载, 不再有服务的监听器一并移除
+    pub fn unmount_service(&mut self, service: ServiceKind) {
+        for listener in self.listeners.iter_mut() {
+            listener.services.retain(|mount| mount.service != service);
+        }
+        self.listeners.retain(|listener| !listener.services.is_empty());
+    }
+}
+
+// mount 的路由是否会拦截 other 的请求, S3 会拦截其前缀下的所有路径
+fn mounts_overlap(mount: &MountConfig, other: &MountConfig) -> bool {
+    if mount.prefix == other.prefix {
+        return mount.service.routes_overlap(&other.service);
+    }
+    mount.service == ServiceKind::S3
+        && (mount.prefix.is_empty() || other.prefix.starts_with(&format!("{}/", mount.prefix)))
+}
+
+fn invalid_input(err: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+// 绑定监听器之前按配置初始化各服务, 返回的文件观察需要保持存活
+// 挂载了上传大文件服务却没有任何校验密钥时无法启动
+pub fn prepare_services(config: &mut ServerConfig) -> io::Result<Vec<Hotwatch>> {
+    #[cfg_attr(not(any(feature = "upload-large-file", feature = "s3")), allow(unused_mut))]
+    let mut watches = Vec::new();
+
+    // 加载校验 token 的密钥, 观察 JWKS 文件变化, 定时获取远程 JWKS
+    #[cfg(feature = "upload-large-file")]
+    if config.mounts_service(ServiceKind::UploadLargeFile) {
+        let token_verification = &config.token_verification;
+        if !token_verification.has_keys() {
+            return Err(invalid_input(String::from(
+                "uploadLargeFile is mounted but no token verification key is configured",
+            )));
+        }
+        crate::jwt_verify::set_jwt_config(token_verification).map_err(invalid_input)?;
+        if let Some(url) = &token_verification.jwks_url {
+            let cache_time = std::time::Duration::from_secs(token_verification.jwks_cache_seconds);
+            actix_web::rt::spawn(crate::jwt_verify::keep_jwks_url_fresh(url.clone(), cache_time));
+        }
+        if let Some(path) = &token_verification.jwks_path {
+            watches.push(crate::jwt_verify::watch_jwks(path).map_err(invalid_input)?);
+        }
+    }
+    if config.listeners.is_empty() {
+        return Err(invalid_input(String::from("no listener has a service to mount")));
+    }
+
+    // 观察文件变化，更新 s3 访问密钥
+    #[cfg(feature = "s3")]
+    if config.mounts_service(ServiceKind::S3) {
+        watches.push(crate::s3_serve::watch_credentials("./s3Credentials.json").map_err(invalid_input)?);
+    }
+
+    #[cfg(any(feature = "transfer", feature = "upload-large-file", feature = "s3"))]
+    {
+        if let Some(max_chunk_size) = config.max_chunk_size {
+            crate::set_max_chunk_size(max_chunk_size);
+        }
+        if let Some(min_chunk_size) = config.min_chunk_size {
+            crate::set_min_chunk_size(min_chunk_size);
+        }
+        if let Some(idle_time) = config.chunk_session_idle_time {
+            crate::set_session_idle_time(idle_time);
+        }
+        if let Some(max_lifetime) = config.chunk_session_max_lifetime {
+            crate::set_session_max_lifetime(max_lifetime);
+        }
+    }
+
+    #[cfg(feature = "upload-large-file")]
+    crate::archive_extract::set_extract_limits(config.archive_extract.clone());
+
+    #[cfg(any(feature = "transfer", feature = "upload-large-file"))]
+    for (service, steps) in config.post_merge.iter() {
+        if let Some(service_name) = service.upload_service_name() {
+            crate::post_merge::set_post_merge_steps(service_name, steps.iter().map(|step| step.build()).collect());
+        }
+    }
+    Ok(watches)
+}
+
+// 没有配置文件时使用的配置, 没有校验密钥时跳过上传大文件服务, 其他服务照常启动
+fn default_config() -> ServerConfig {
+    #[cfg_attr(not(feature = "upload-large-file"), allow(unused_mut))]
+    let mut config = ServerConfig::default();
+    #[cfg(feature = "upload-large-file")]
+    if !config.token_verification.has_keys() {
+        println!("no token verification key configured, uploadLargeFile service is not mounted");
+        config.unmount_service(ServiceKind::UploadLargeFile);
+    }
+    config
+}
+
+// 读取服务配置, 文件不存在时使用默认配置
+// 默认配置没有校验密钥时不挂载上传大文件服务, 配置文件中挂载了则必须配置密钥
+pub fn load_server_config(path: &str) -> io::Result<ServerConfig> {
+    let config: ServerConfig = match fsSync::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => default_config(),
+        Err(err) => return Err(err),
+    };
+    config
+        .validate()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    Ok(config)
+}
+
+// 按前缀挂载服务, 同一前缀的服务放在同一个 scope 中
+pub fn mount_services(config: &mut web::ServiceConfig, mounts: &[MountConfig]) {
+    let mut prefixes: Vec<&str> = Vec::new();
+    for mount in mounts.iter() {
+        if !prefixes.contains(&mount.prefix.as_str()) {
+            prefixes.push(&mount.prefix);
+        }
+    }
+
+    for prefix in prefixes {
+        let services: Vec<ServiceKind> = mounts
+            .iter()
+            .filter(|mount| mount.prefix == prefix)
+            .map(|mount| mount.service)
+            .collect();
+        let configure = move |config: &mut web::ServiceConfig| {
+            for service in services.iter() {
+                (service.actix_configure())(config);
+            }
+        };
+        // 空前缀直接挂载在根上, 避免空 scope 拦截其他路由
+        if prefix.is_empty() {
+            configure(config);
+        } else {
+            config.service(web::scope(prefix).configure(configure));
+        }
+    }
+}
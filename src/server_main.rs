@@ -0,0 +1,80 @@
+use actix_cors::Cors;
+use actix_web::{App, HttpServer, get};
+use futures::future::try_join_all;
+
+use web_server::server_config::{self, ListenerConfig};
+use web_server::tls;
+use web_server::garbage_collector;
+
+#[get("/")]
+async fn g() -> &'static str {
+  "hello world"
+}
+
+// 启动一个监听器, 返回其上运行的所有服务器(包括重定向服务器)
+fn start_listener(listener: ListenerConfig, cert_watches: &mut Vec<hotwatch::Hotwatch>) -> std::io::Result<Vec<actix_web::dev::Server>> {
+  let mounts = listener.services.clone();
+  let server = HttpServer::new(move || {
+    App::new()
+      .wrap(
+        Cors::default()
+          .allow_any_origin()
+          .allow_any_method()
+          .allow_any_header()
+          .expose_any_header()
+          .max_age(3600),
+      )
+      .configure(|config| server_config::mount_services(config, &mounts))
+      .service(g)
+  });
+
+  // 存在 tls 配置时只提供 https, 可选的 http 端口只做重定向
+  match &listener.tls {
+    Some(tls_config) => {
+      let (server_config, cert_watch) = tls::rustls_server_config(tls_config)?;
+      cert_watches.push(cert_watch);
+      let mut servers = vec![server.bind_rustls(&listener.bind, server_config)?.run()];
+      if let Some(redirect_bind) = &tls_config.redirect_bind {
+        servers.push(tls::redirect_server(redirect_bind, &listener.bind)?);
+      }
+      Ok(servers)
+    }
+    None => Ok(vec![server.bind(&listener.bind)?.run()]),
+  }
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+  let mut config = server_config::load_server_config("./serverConfig.json")?;
+
+  // 返回的文件观察需要保持存活
+  let _watches = server_config::prepare_services(&mut config)?;
+
+  // 恢复重启前未完成的分片上传
+  #[cfg(any(feature = "transfer", feature = "upload-large-file", feature = "s3"))]
+  if let Err(err) = web_server::restore_chunks_sessions().await {
+    println!("failed to restore chunks sessions: {}", err);
+  }
+  // 恢复秒传使用的已合并文件记录
+  #[cfg(any(feature = "transfer", feature = "upload-large-file", feature = "s3"))]
+  if let Err(err) = web_server::restore_stored_files().await {
+    println!("failed to restore instant upload index: {}", err);
+  }
+
+  // 恢复会话之后才能判断哪些文件不再被引用
+  if config.garbage_collection.enabled {
+    garbage_collector::spawn_garbage_collector(
+      config.garbage_collection.clone(),
+      config.mounts_service(server_config::ServiceKind::Transfer),
+    );
+  }
+
+  let mut cert_watches = Vec::new();
+  let mut servers = Vec::new();
+  for listener in config.listeners {
+    servers.extend(start_listener(listener, &mut cert_watches)?);
+  }
+
+  try_join_all(servers).await?;
+  Ok(())
+}
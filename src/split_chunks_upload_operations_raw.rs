@@ -1,40 +1,535 @@
 use tokio::{
-    fs::{self, File},
+    fs::{self, File, OpenOptions},
     sync::Mutex,
-    time::{ sleep, Duration },
-    task,
+    time::{ sleep, Duration, Instant },
+    task::{self, AbortHandle},
 };
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use std::collections::HashSet;
+use std::io::SeekFrom;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::{collections::HashMap, path::Path};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
 
-use urlencoding::decode;
-
-use bytes::BytesMut;
-use std::future::Future;
+use bytes::Bytes;
+use futures::{future::BoxFuture, Stream, StreamExt};
 use std::pin::Pin;
-// serde_json used
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use md5::compute as computeHash;
 
+use parking_lot::Mutex as SyncMutex;
+
+use crate::chunk_hash::{normalize_hash, ChunkHasher, HashAlgorithm};
+use crate::instant_upload::{record_stored_file, stored_file_scope};
+use crate::garbage_collector::OrphanSweeper;
+
 use lazy_static::lazy_static;
 
-const BASE_PATH: &'static str = "./files/";
+const BASE_PATH: &str = "./files/";
 
 // 512MB 最大尺寸
 pub const MAX_SIZE: usize = 536870912;
 
-// 尚未合并的chunks最多存在24小时
+// 单个 chunk 默认最大 64MB
+const DEFAULT_MAX_CHUNK_SIZE: usize = 67108864;
+
+// 声明布局时除最后一个外每个 chunk 默认至少 64KB
+const DEFAULT_MIN_CHUNK_SIZE: usize = 65536;
+
+// 一个会话最多的 chunk 数量, 与 S3 的最大分段数一致
+const MAX_CHUNKS_NUMBER: usize = 10000;
+
+// 尚未合并的chunks默认闲置24小时后清理
 const CHUNK_SURVIVAL_TIME: u64 = 86400;
 
+// 上传会话默认最多存在7天, 即使一直在上传
+const SESSION_MAX_LIFETIME: u64 = 7 * 86400;
+
+// 未上传的 chunk 占位
+const EMPTY_CHUNK: &str = "empty";
+
+// 合并任务结束后状态保留1小时供查询
+const MERGE_JOB_SURVIVAL_TIME: u64 = 3600;
+
+// 追加记录少于该数量时不重写会话文件
+const MIN_LOGGED_CHUNKS: usize = 64;
+
 pub struct UploadChunksConfig {
     pub chunks_path: String, // 基本路径，存放chunks位置
     pub base_path: String,
+    pub max_chunk_size: AtomicUsize, // 单个 chunk 最大尺寸
+    pub min_chunk_size: AtomicUsize, // 声明布局时 chunk 的最小尺寸
+    pub session_idle_time: AtomicU64, // 会话闲置多少秒后清理
+    pub session_max_lifetime: AtomicU64, // 会话从创建起最多存在多少秒
 }
 
 pub type ChunksHash = Vec<String>;
 
-pub type Files = HashMap<String, ChunksHash>;
+// 持久化时只保存已上传的 chunk, 分段上传的序号可以很大
+#[derive(Serialize, Deserialize)]
+struct SparseChunksHash {
+    length: usize,
+    hashes: BTreeMap<usize, String>,
+}
+
+// 旧的会话文件保存完整的数组
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredChunksHash {
+    Dense(ChunksHash),
+    Sparse(SparseChunksHash),
+}
+
+fn serialize_chunks_hash<S: Serializer>(chunks_hash: &ChunksHash, serializer: S) -> Result<S::Ok, S::Error> {
+    SparseChunksHash {
+        length: chunks_hash.len(),
+        hashes: chunks_hash
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk_hash)| *chunk_hash != EMPTY_CHUNK)
+            .map(|(index, chunk_hash)| (index, chunk_hash.clone()))
+            .collect(),
+    }
+    .serialize(serializer)
+}
+
+fn deserialize_chunks_hash<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ChunksHash, D::Error> {
+    match StoredChunksHash::deserialize(deserializer)? {
+        StoredChunksHash::Dense(chunks_hash) => Ok(chunks_hash),
+        StoredChunksHash::Sparse(SparseChunksHash { length, hashes }) => {
+            check_chunks_number(length).map_err(serde::de::Error::custom)?;
+            let mut chunks_hash = vec![String::from(EMPTY_CHUNK); length];
+            for (index, chunk_hash) in hashes {
+                match chunks_hash.get_mut(index) {
+                    Some(slot) => *slot = chunk_hash,
+                    None => return Err(serde::de::Error::custom(format!("chunk index {} is out of range", index))),
+                }
+            }
+            Ok(chunks_hash)
+        }
+    }
+}
+
+// 创建上传会话时客户端声明的文件布局
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunksLayout {
+    pub file_name: String,
+    pub file_size: u64,
+    pub chunk_size: u64, // 除最后一个外每个 chunk 的大小
+    pub file_hash: Option<String>, // 整个文件的 hash, 与 chunk 使用相同算法
+}
+
+impl ChunksLayout {
+    // chunk 在文件中的偏移
+    fn chunk_offset(&self, chunk_index: usize) -> u64 {
+        self.chunk_size * chunk_index as u64
+    }
+}
+
+// 文件的组装方式
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum AssemblyMode {
+    // 每个 chunk 单独保存, 合并时依次写入目标文件
+    #[default]
+    Chunks,
+    // 创建会话时预先分配整个文件, chunk 校验时直接写入对应位置, 合并时只需落盘并重命名
+    Preallocated,
+    // tus 上传, 从头按顺序追加写入, 整个文件作为一个 chunk, 合并方式与预分配模式相同
+    Append,
+}
+
+// 保存路径已有文件时的处理方式, 默认不破坏已有文件
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum ConflictPolicy {
+    // 返回错误
+    Fail,
+    // 覆盖已有文件
+    Overwrite,
+    // 新文件加上序号保存, 如 a (1).txt
+    #[default]
+    Rename,
+    // 已有文件加上版本号保留, 如 a.v1.txt, 新文件保存到原路径
+    Version,
+}
+
+impl ConflictPolicy {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "fail" => Ok(ConflictPolicy::Fail),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "rename" => Ok(ConflictPolicy::Rename),
+            "version" => Ok(ConflictPolicy::Version),
+            _ => Err(format!("unsupported conflict policy: {}", name)),
+        }
+    }
+}
+
+// 已写入的 chunk, 每个 chunk 占一位
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ChunkBitmap(Vec<u8>);
+
+impl ChunkBitmap {
+    fn new(chunks_number: usize) -> Self {
+        ChunkBitmap(vec![0; chunks_number.div_ceil(8)])
+    }
+
+    fn get(&self, index: usize) -> bool {
+        self.0.get(index / 8).is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+    }
+
+    fn set(&mut self, index: usize, written: bool) {
+        if written {
+            self.0[index / 8] |= 1 << (index % 8);
+        } else {
+            self.0[index / 8] &= !(1 << (index % 8));
+        }
+    }
+
+    fn missing(&self, chunks_number: usize) -> Vec<usize> {
+        (0..chunks_number).filter(|index| !self.get(*index)).collect()
+    }
+}
+
+// 创建上传会话的请求
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateUploadSession {
+    pub file_name: String,
+    pub file_size: u64,
+    pub chunk_size: u64,
+    pub chunks_number: usize,
+    pub file_hash: Option<String>,
+    pub hash_algorithm: Option<String>,
+    #[serde(default)]
+    pub assembly: AssemblyMode,
+}
+
+// 上传会话, 持久化到 chunks 目录中以便重启后继续上传
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunksSession {
+    pub identify: String,
+    #[serde(serialize_with = "serialize_chunks_hash", deserialize_with = "deserialize_chunks_hash")]
+    pub chunks_hash: ChunksHash,
+    pub expires_at: u64, // 过期时间, unix 秒, 每次接受 chunk 后顺延
+    // 创建时间, unix 秒, 旧会话文件中没有该字段时按过期时间推算
+    #[serde(default)]
+    pub created_at: u64,
+    // 旧会话文件中没有该字段, 为 md5
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    // 通过创建会话接口创建时才有, 旧的隐式会话只知道 chunk 数量
+    #[serde(default)]
+    pub layout: Option<ChunksLayout>,
+    #[serde(default)]
+    pub assembly: AssemblyMode,
+    // 预分配模式下已写入的 chunk
+    #[serde(default)]
+    pub written: ChunkBitmap,
+    // 预分配模式下正在写入的 chunk, 同一位置不能并发写入
+    #[serde(skip)]
+    uploading: HashSet<usize>,
+    // 追加模式下已写入的字节数
+    #[serde(default)]
+    pub appended: u64,
+    // 追加模式下已写入内容的 hash 状态, 重启后丢失, 完成时重新读取文件计算
+    #[serde(skip)]
+    append_hasher: Option<ChunkHasher>,
+    // 创建者标识, 设置后只有创建者可以上传, 查询, 合并和中止
+    #[serde(default)]
+    pub owner: Option<String>,
+    // 分段上传的最大分段数, 设置时 chunks_hash 只保存到已上传的最大序号
+    #[serde(default)]
+    pub max_parts: Option<usize>,
+    // 定时清理任务, 会话提前结束或顺延过期时间时取消
+    #[serde(skip)]
+    expiration: Option<AbortHandle>,
+    // 最长存活时间的截止时刻
+    #[serde(skip)]
+    lifetime_deadline: Option<Instant>,
+    // 已合并或过期, 等待锁的请求拿到锁后需要放弃
+    #[serde(skip)]
+    removed: bool,
+    // 每次重写会话文件时递增, 追加记录中与之不一致的是重写前留下的
+    #[serde(default)]
+    log_generation: u64,
+    // 重写会话文件之后追加的 chunk 记录数, 还没有写过会话文件时为 None
+    #[serde(skip)]
+    logged_chunks: Option<usize>,
+}
+
+// 写入一个 chunk 后追加到会话记录中的一行
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoggedChunk {
+    generation: u64,
+    index: usize,
+    hash: String,
+    expires_at: u64,
+}
+
+impl ChunksSession {
+    // 会话设置了创建者时, owner 需要一致
+    fn is_owned_by(&self, owner: Option<&str>) -> bool {
+        self.owner.is_none() || self.owner.as_deref() == owner
+    }
+
+    // 拿到会话锁后检查会话仍可写入, 且 chunk 与会话一致
+    fn accept_chunk(
+        &self,
+        chunk_index: usize,
+        chunks_number: usize,
+        hash_algorithm: HashAlgorithm,
+        owner: Option<&str>,
+    ) -> Result<Option<u64>, String> {
+        if self.removed {
+            return Err(String::from("upload session has been merged or removed"));
+        }
+        if !self.is_owned_by(owner) {
+            return Err(String::from("not the owner of the upload session"));
+        }
+        if self.assembly == AssemblyMode::Append {
+            return Err(String::from("upload session only accepts tus requests"));
+        }
+        if self.hash_algorithm != hash_algorithm {
+            return Err(format!(
+                "hash algorithm {} does not match session algorithm {}",
+                hash_algorithm.name(),
+                self.hash_algorithm.name()
+            ));
+        }
+        self.validate_chunk(chunk_index, chunks_number)
+    }
+
+    // 会话在 chunk 存储中的引用者
+    fn holder(&self) -> String {
+        chunk_holder(&self.identify, self.owner.as_deref())
+    }
+
+    // 记录已引用存储中 chunk 的位置, 释放该位置原先引用的 chunk
+    async fn set_stored_chunk(&mut self, chunk_index: usize, chunk_hash: String) {
+        if chunk_index >= self.chunks_hash.len() {
+            self.chunks_hash.resize(chunk_index + 1, String::from(EMPTY_CHUNK));
+        }
+        let previous = std::mem::replace(&mut self.chunks_hash[chunk_index], chunk_hash);
+        if previous != EMPTY_CHUNK {
+            release_stored_chunk(&store_key(self.hash_algorithm, &previous, &self.identify), &self.holder()).await;
+        }
+    }
+
+    // 校验 chunk 是否符合会话布局, 返回该 chunk 应有的大小(已知时)
+    fn validate_chunk(&self, chunk_index: usize, chunks_number: usize) -> Result<Option<u64>, String> {
+        // 分段上传只限制序号
+        if let Some(max_parts) = self.max_parts {
+            if chunk_index >= max_parts {
+                return Err(format!("chunkIndex {} is out of range, session has {} parts", chunk_index, max_parts));
+            }
+            return Ok(None);
+        }
+        let session_chunks_number = self.chunks_hash.len();
+        if chunks_number != session_chunks_number {
+            return Err(format!(
+                "chunksNumber {} does not match session chunksNumber {}",
+                chunks_number, session_chunks_number
+            ));
+        }
+        if chunk_index >= session_chunks_number {
+            return Err(format!(
+                "chunkIndex {} is out of range, session has {} chunks",
+                chunk_index, session_chunks_number
+            ));
+        }
+        Ok(self.layout.as_ref().map(|layout| {
+            if chunk_index + 1 == session_chunks_number {
+                layout.file_size - layout.chunk_size * (session_chunks_number as u64 - 1)
+            } else {
+                layout.chunk_size
+            }
+        }))
+    }
+
+    // 按序号选出要合并的 chunk, 序号需递增且 hash 与已上传的一致
+    fn select_parts(&self, parts: &[(usize, String)]) -> Result<Vec<String>, MultipartError> {
+        if self.assembly != AssemblyMode::Chunks {
+            return Err(MultipartError::Failed(String::from("only chunk sessions can merge selected chunks")));
+        }
+        let mut selected = Vec::with_capacity(parts.len());
+        let mut previous: Option<usize> = None;
+        for (index, chunk_hash) in parts.iter() {
+            if previous.is_some_and(|previous| previous >= *index) {
+                return Err(MultipartError::InvalidPartOrder);
+            }
+            previous = Some(*index);
+            match self.chunks_hash.get(*index) {
+                Some(uploaded) if uploaded != EMPTY_CHUNK && *uploaded == normalize_hash(chunk_hash) => {
+                    selected.push(uploaded.clone())
+                }
+                _ => return Err(MultipartError::InvalidPart(*index)),
+            }
+        }
+        if selected.is_empty() {
+            return Err(MultipartError::Failed(String::from("no chunk selected")));
+        }
+        Ok(selected)
+    }
+}
+
+// 每个会话单独加锁, 全局表只在查找和增删时短暂加锁
+// 加锁顺序: 持有会话锁时可以获取全局锁, 持有全局锁时不能等待会话锁
+pub type SharedSession = Arc<Mutex<ChunksSession>>;
+
+pub type Files = HashMap<String, SharedSession>;
+
+// chunk 内容, 边接收边写入
+pub type ChunkContentStream = Pin<Box<dyn Stream<Item = Result<Bytes, Box<dyn std::error::Error>>>>>;
+
+// 合并的目标, 由各服务决定保存路径和合并完成后的操作
+pub trait MergeTarget: Send + Sync {
+    // 把请求中的路径转换为保存路径, 默认直接拼接在基本路径后
+    fn save_path(&self, base_path: &str, full_path: &str) -> String {
+        format!("{}{}", base_path, full_path)
+    }
+
+    // 保存路径必须位于的目录, 默认为基本路径
+    fn root_path(&self, base_path: &str) -> String {
+        String::from(base_path)
+    }
+
+    // 合并完成后执行, 参数为最终保存路径, 返回响应给客户端的结果
+    fn after_merge(&self, file_path: String) -> BoxFuture<'static, Result<String, String>> {
+        Box::pin(async move { Ok(file_path) })
+    }
+}
+
+// 上传 chunk 的请求
+pub struct UploadChunkRequest {
+    pub identify: String,
+    pub chunk_hash: String,
+    pub chunk_index: usize,
+    pub chunks_number: usize,
+    pub hash_algorithm: HashAlgorithm,
+}
+
+// 引用服务端已存储 chunk 的请求, chunks_hash 按顺序排列
+pub struct ReuseChunksRequest {
+    pub identify: String,
+    pub chunks_number: usize,
+    pub hash_algorithm: HashAlgorithm,
+    pub chunks_hash: Vec<String>,
+}
+
+// 合并请求
+pub struct MergeChunksRequest {
+    pub identify: String,
+    // 会话设置了创建者时需要一致
+    pub owner: Option<String>,
+    // 未提供时使用创建会话时声明的文件名
+    pub full_path: Option<String>,
+    // 整个文件的 hash, 未提供时使用创建会话时声明的 hash
+    pub file_hash: Option<String>,
+    pub conflict_policy: ConflictPolicy,
+    // 超过时不合并
+    pub max_file_size: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum MergeJobState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+// 后台合并任务的状态
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeJobStatus {
+    pub state: MergeJobState,
+    pub merged_chunks: usize,
+    pub total_chunks: usize,
+    pub result: Option<String>, // 成功时的结果
+    pub error: Option<String>,  // 失败原因
+    // 合并会话的创建者, 设置后只有创建者可以查询
+    #[serde(skip)]
+    owner: Option<String>,
+}
+
+// 合并时仍有未上传的 chunk
+#[derive(Debug)]
+pub struct MissingChunks(pub Vec<usize>);
+
+impl std::fmt::Display for MissingChunks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "missing chunks: {}", serde_json::to_string(&self.0).map_err(|_| std::fmt::Error)?)
+    }
+}
+
+impl std::error::Error for MissingChunks {}
+
+// 追加写入失败的原因, 由上层转换为对应的响应
+#[derive(Debug)]
+pub enum AppendError {
+    NotFound,
+    OffsetMismatch(u64), // 当前已写入的字节数
+    Locked,
+    ChecksumMismatch,
+    TooLarge,
+    Failed(String),
+}
+
+impl std::fmt::Display for AppendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppendError::NotFound => write!(f, "upload not found"),
+            AppendError::OffsetMismatch(offset) => write!(f, "offset does not match, current offset is {}", offset),
+            AppendError::Locked => write!(f, "upload is being written by another request"),
+            AppendError::ChecksumMismatch => write!(f, "checksum mismatch"),
+            AppendError::TooLarge => write!(f, "upload exceeds its declared length"),
+            AppendError::Failed(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for AppendError {}
+
+// 追加模式会话的进度
+pub struct AppendStatus {
+    pub offset: u64,
+    pub length: u64,
+    pub expires_at: u64, // unix 秒
+}
+
+// 分段上传失败的原因, 由上层转换为对应的响应
+#[derive(Debug)]
+#[cfg_attr(not(feature = "s3"), allow(dead_code))]
+pub enum MultipartError {
+    NoSuchUpload,
+    InvalidPart(usize), // chunk 序号
+    InvalidPartOrder,
+    BadDigest,
+    EntityTooLarge(u64), // 允许的最大字节数
+    Failed(String),
+}
+
+impl std::fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MultipartError::NoSuchUpload => write!(f, "upload session not found"),
+            MultipartError::InvalidPart(index) => write!(f, "chunk {} is not uploaded or its hash does not match", index),
+            MultipartError::InvalidPartOrder => write!(f, "chunks must be listed in ascending order without duplicates"),
+            MultipartError::BadDigest => write!(f, "chunk hash not match"),
+            MultipartError::EntityTooLarge(max_size) => write!(f, "object is larger than {} bytes", max_size),
+            MultipartError::Failed(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {}
 
 pub struct UploadedChunksDatas {
     pub files: Mutex<Files>,
@@ -44,176 +539,1752 @@ lazy_static! {
     static ref UPLOAD_CHUNKS_CONFIG: Arc<UploadChunksConfig> = Arc::new(UploadChunksConfig {
         base_path: String::from(BASE_PATH),
         chunks_path: String::from("./chunks/"),
+        max_chunk_size: AtomicUsize::new(DEFAULT_MAX_CHUNK_SIZE),
+        min_chunk_size: AtomicUsize::new(DEFAULT_MIN_CHUNK_SIZE),
+        session_idle_time: AtomicU64::new(CHUNK_SURVIVAL_TIME),
+        session_max_lifetime: AtomicU64::new(SESSION_MAX_LIFETIME),
     });
     static ref UPLOADED_CHUNKS_DATAS: Arc<UploadedChunksDatas> = Arc::new(UploadedChunksDatas {
         files: Mutex::new(HashMap::new()),
     });
+    static ref MERGE_JOBS: SyncMutex<HashMap<String, MergeJobStatus>> = SyncMutex::new(HashMap::new());
+    // 按内容保存的 chunk 被多少个会话引用, 为0时删除
+    // 只在存取引用计数时短暂加锁, 持有该锁时不获取其他锁
+    static ref CHUNK_STORE_REFS: Mutex<HashMap<String, ChunkHolders>> = Mutex::new(HashMap::new());
 }
 
-pub async fn get_uploaded_chunks_hashes_raw(identify: &str) -> String {
-    let files = UPLOADED_CHUNKS_DATAS.files.lock().await;
+// 引用 chunk 的会话按服务和创建者分别计数, 只有上传过该 chunk 的同一创建者可以直接引用
+type ChunkHolders = HashMap<String, usize>;
 
-    // 如果为空，不存在，没法获取或者错误，就返回一个空数组 json
-    let chunks_hash_json_array: String = files.get(identify).map_or_else(
-        || String::from("[]"),
-        |chunks_info| serde_json::to_string(&chunks_info).unwrap_or_else(|_| String::from("[]")),
-    );
+// 设置单个 chunk 的最大尺寸, 不超过整个文件的最大尺寸
+pub fn set_max_chunk_size(max_chunk_size: usize) {
+    UPLOAD_CHUNKS_CONFIG
+        .max_chunk_size
+        .store(max_chunk_size.min(MAX_SIZE), Ordering::Relaxed);
+}
 
-    chunks_hash_json_array
+// 设置声明布局时 chunk 的最小尺寸, 文件只有一个 chunk 时不限制
+pub fn set_min_chunk_size(min_chunk_size: usize) {
+    UPLOAD_CHUNKS_CONFIG
+        .min_chunk_size
+        .store(min_chunk_size.clamp(1, MAX_SIZE), Ordering::Relaxed);
 }
 
-pub async fn split_chunks_upload_raw(
-    headers: Vec<&str>,
-    chunk_content: Pin<Box<dyn Future<Output = Result<BytesMut, Box<dyn std::error::Error>>>>>,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let (identify, chunk_hash, chunk_index, chunks_number) = (
-        headers[0],
-        headers[1],
-        headers[2].parse::<usize>()?,
-        headers[3].parse::<usize>()?,
-    );
+// chunk 数量需在分配会话前校验, 客户端可以声明任意数量
+fn check_chunks_number(chunks_number: usize) -> Result<(), String> {
+    if chunks_number == 0 || chunks_number > MAX_CHUNKS_NUMBER {
+        return Err(format!("chunksNumber must be between 1 and {}", MAX_CHUNKS_NUMBER));
+    }
+    Ok(())
+}
 
-    // 获得文件内容
-    let chunk_content = chunk_content.await?;
+// 单个 chunk 的最大尺寸
+pub(crate) fn max_chunk_size() -> usize {
+    UPLOAD_CHUNKS_CONFIG.max_chunk_size.load(Ordering::Relaxed)
+}
 
-    // 计算hash
-    let computed_hash = format!("{:?}", computeHash(&chunk_content));
+// 设置上传会话闲置多少秒后清理
+pub fn set_session_idle_time(idle_time: u64) {
+    UPLOAD_CHUNKS_CONFIG.session_idle_time.store(idle_time.max(1), Ordering::Relaxed);
+}
 
-    println!(
-        "computed_hash: {:?}\r\nchunkHash: {}",
-        &computed_hash, chunk_hash
-    );
+// 设置上传会话从创建起最多存在多少秒
+pub fn set_session_max_lifetime(max_lifetime: u64) {
+    UPLOAD_CHUNKS_CONFIG.session_max_lifetime.store(max_lifetime.max(1), Ordering::Relaxed);
+}
 
-    if chunk_hash != &computed_hash {
-        println!("chunk hash not match");
-        return Err("chunk hash not match".into());
+fn session_idle_time() -> Duration {
+    Duration::from_secs(UPLOAD_CHUNKS_CONFIG.session_idle_time.load(Ordering::Relaxed))
+}
+
+// 合并后文件的大小, 声明了布局时以布局为准
+async fn session_file_size(session: &ChunksSession, chunks_hash: &[String]) -> std::io::Result<u64> {
+    if let Some(layout) = &session.layout {
+        return Ok(layout.file_size);
     }
+    let mut file_size = 0;
+    for chunk_hash in chunks_hash.iter() {
+        let key = store_key(session.hash_algorithm, chunk_hash, &session.identify);
+        file_size += fs::metadata(stored_chunk_path(&key)).await?.len();
+    }
+    Ok(file_size)
+}
+
+// 存放 chunk 和会话文件的目录
+pub(crate) fn chunks_path() -> &'static str {
+    UPLOAD_CHUNKS_CONFIG.chunks_path.as_str()
+}
+
+// 保存文件的基本路径
+#[cfg(feature = "upload-large-file")]
+pub(crate) fn base_path() -> &'static str {
+    UPLOAD_CHUNKS_CONFIG.base_path.as_str()
+}
+
+// 多个服务共用 chunk 状态, 以服务名区分各自的会话
+pub fn session_key(service: &str, identify: &str) -> String {
+    format!("{}/{}", service, identify)
+}
 
-    let chunk_full_path = format!(
+// 会话所属的服务
+fn session_service(key: &str) -> &str {
+    key.split_once('/').map_or(key, |(service, _)| service)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+// 旧版本按会话保存的 chunk, 恢复时移入共享存储
+fn legacy_chunk_path(chunk_hash: &str, identify: &str) -> String {
+    format!(
         "{}{}{:?}.chunk",
         UPLOAD_CHUNKS_CONFIG.chunks_path,
         chunk_hash,
         computeHash(identify)
-    ); // 完整的文件存放路径
+    )
+}
 
-    // 存储chunk到本地
-    fs::write(&chunk_full_path, &chunk_content).await?;
-    println!("saved chunk: {}", &chunk_full_path);
+// chunk 在共享存储中的 key, 不能去重的算法仍按会话区分
+fn store_key(hash_algorithm: HashAlgorithm, chunk_hash: &str, identify: &str) -> String {
+    if hash_algorithm.supports_deduplication() {
+        format!("{}-{}", hash_algorithm.name(), chunk_hash)
+    } else {
+        format!("{}-{}-{:?}", hash_algorithm.name(), chunk_hash, computeHash(identify))
+    }
+}
 
-    // 存储chunk标识
-    let mut files = UPLOADED_CHUNKS_DATAS.files.lock().await;
+fn stored_chunk_path(store_key: &str) -> String {
+    format!("{}{}.chunk", UPLOAD_CHUNKS_CONFIG.chunks_path, store_key)
+}
+
+// chunk 的引用者, 由会话所属的服务和创建者组成
+fn chunk_holder(identify: &str, owner: Option<&str>) -> String {
+    let service = identify.split('/').next().unwrap_or_default();
+    format!("{}/{}", service, owner.unwrap_or_default())
+}
 
-    match files.get_mut(identify) {
-        Some(chunks_hash) => {
-            // 存储hash至vec中指定位置
-            chunks_hash[chunk_index] = String::from(chunk_hash);
+// 把校验过的临时文件放入存储并增加引用, 已有相同内容时丢弃临时文件
+async fn store_chunk(store_key: &str, temp_path: &str, holder: &str) -> std::io::Result<()> {
+    let mut refs = CHUNK_STORE_REFS.lock().await;
+    match refs.get_mut(store_key) {
+        Some(holders) => {
+            *holders.entry(String::from(holder)).or_default() += 1;
+            let _ = fs::remove_file(temp_path).await;
+            println!("reused stored chunk: {}", store_key);
         }
         None => {
-            // 开辟指定长度vec空间，填充"empty"
-            let mut chunks_hash = vec![String::from("empty"); chunks_number];
-            chunks_hash[chunk_index] = String::from(chunk_hash);
-
-            // 定时清理
-            let uploaded_datas_ref = UPLOADED_CHUNKS_DATAS.clone();
-
-            // 拷贝一份identidy因为其引用自req
-            let identify_clone = String::from(identify);
-
-            task::spawn(async move {
-                sleep(Duration::from_secs(CHUNK_SURVIVAL_TIME)).await;
-
-                // 结束之后删除所有chunk, 并删除对应哈希表中项目1
-                let mut files = uploaded_datas_ref.files.lock().await;
-
-                if let Some(chunks_hash) = files.get(&identify_clone) {
-                    for current_chunk_hash in chunks_hash.iter() {
-                        let chunk_path = format!(
-                            "{}{}{:?}.chunk",
-                            UPLOAD_CHUNKS_CONFIG.chunks_path,
-                            &current_chunk_hash,
-                            computeHash(&identify_clone)
-                        );
-
-                        if let Ok(_) = fs::remove_file(&chunk_path).await {
-                            println!("deleted chunk: {}", current_chunk_hash);
-                        }
-                    }
-                }
+            fs::rename(temp_path, stored_chunk_path(store_key)).await?;
+            refs.insert(String::from(store_key), HashMap::from([(String::from(holder), 1)]));
+        }
+    }
+    Ok(())
+}
 
-                // 从hashmap中删除节点
-                if let Some(_) = files.remove(&identify_clone) {
-                    println!("clear chunks: {}", identify_clone)
-                }
-            });
+// 引用已存储的 chunk, 只有该引用者已经引用时才成功
+async fn retain_stored_chunk(store_key: &str, holder: &str) -> bool {
+    match CHUNK_STORE_REFS
+        .lock()
+        .await
+        .get_mut(store_key)
+        .and_then(|holders| holders.get_mut(holder))
+    {
+        Some(count) => {
+            *count += 1;
+            true
+        }
+        None => false,
+    }
+}
 
-            files.insert(String::from(identify), chunks_hash);
+// 释放引用, 没有会话引用时删除
+async fn release_stored_chunk(store_key: &str, holder: &str) {
+    let mut refs = CHUNK_STORE_REFS.lock().await;
+    if let Some(holders) = refs.get_mut(store_key) {
+        if let Some(count) = holders.get_mut(holder) {
+            *count -= 1;
+            if *count == 0 {
+                holders.remove(holder);
+            }
+        }
+        if holders.is_empty() {
+            refs.remove(store_key);
+            if fs::remove_file(stored_chunk_path(store_key)).await.is_ok() {
+                println!("deleted chunk: {}", store_key);
+            }
         }
+    }
+}
+
+// 预分配模式下正在组装的文件
+fn assembling_path(identify: &str) -> String {
+    format!(
+        "{}{:?}.assembling",
+        UPLOAD_CHUNKS_CONFIG.chunks_path,
+        computeHash(identify)
+    )
+}
+
+fn session_path(identify: &str) -> String {
+    format!(
+        "{}{:?}.session.json",
+        UPLOAD_CHUNKS_CONFIG.chunks_path,
+        computeHash(identify)
+    )
+}
+
+// 会话文件之后写入的 chunk, 每个 chunk 追加一行, 不用每次重写整个会话文件
+fn session_log_path(identify: &str) -> String {
+    format!(
+        "{}{:?}.session.log",
+        UPLOAD_CHUNKS_CONFIG.chunks_path,
+        computeHash(identify)
+    )
+}
+
+// 写入会话文件, 先写临时文件再重命名, 避免中途崩溃留下不完整的文件
+// 之前追加的记录已包含在内, 随后删除
+async fn persist_session(session: &mut ChunksSession) -> Result<(), Box<dyn std::error::Error>> {
+    let path = session_path(&session.identify);
+    let temp_path = format!("{}.tmp", path);
+    session.log_generation += 1;
+    fs::write(&temp_path, serde_json::to_vec(session)?).await?;
+    fs::rename(&temp_path, &path).await?;
+    let _ = fs::remove_file(session_log_path(&session.identify)).await;
+    session.logged_chunks = Some(0);
+    Ok(())
+}
+
+// 写入一个 chunk 后追加记录, 记录数超过 chunk 数量时才重写会话文件, 总开销与 chunk 数量成正比
+async fn persist_chunk(session: &mut ChunksSession, chunk_index: usize) -> Result<(), Box<dyn std::error::Error>> {
+    match session.logged_chunks {
+        Some(logged_chunks) if logged_chunks < session.chunks_hash.len().max(MIN_LOGGED_CHUNKS) => {}
+        _ => return persist_session(session).await,
+    }
+    let mut line = serde_json::to_vec(&LoggedChunk {
+        generation: session.log_generation,
+        index: chunk_index,
+        hash: session.chunks_hash[chunk_index].clone(),
+        expires_at: session.expires_at,
+    })?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(session_log_path(&session.identify))
+        .await?;
+    file.write_all(&line).await?;
+    session.logged_chunks = session.logged_chunks.map(|logged_chunks| logged_chunks + 1);
+    Ok(())
+}
+
+// 恢复时把追加的记录应用到会话上, 写到一半的行和重写前留下的记录忽略, 返回是否有记录
+async fn replay_session_log(session: &mut ChunksSession) -> bool {
+    let content = match fs::read(session_log_path(&session.identify)).await {
+        Ok(content) => content,
+        Err(_) => return false,
     };
-    Ok(String::from("true"))
+    let mut replayed = false;
+    for line in content.split(|byte| *byte == b'\n') {
+        let logged = match serde_json::from_slice::<LoggedChunk>(line) {
+            Ok(logged) if logged.generation == session.log_generation => logged,
+            _ => continue,
+        };
+        let in_range = match (session.assembly, session.max_parts) {
+            (AssemblyMode::Chunks, Some(max_parts)) => logged.index < max_parts,
+            (AssemblyMode::Append, _) => false,
+            _ => logged.index < session.chunks_hash.len(),
+        };
+        if !in_range {
+            continue;
+        }
+        if logged.index >= session.chunks_hash.len() {
+            session.chunks_hash.resize(logged.index + 1, String::from(EMPTY_CHUNK));
+        }
+        if session.assembly == AssemblyMode::Preallocated {
+            session.written.set(logged.index, true);
+        }
+        session.chunks_hash[logged.index] = logged.hash;
+        session.expires_at = session.expires_at.max(logged.expires_at);
+        replayed = true;
+    }
+    replayed
 }
 
-pub async fn file_chunks_merge_raw(
-    headers: Vec<&str>,
-    // 覆盖保存地址的函数
-    rewrite_save_path_fn: Option<Box<dyn Fn(&str, String) -> String>>,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let (identify, full_path) = (headers[0], decode(headers[1])?);
+// 释放会话引用的所有 chunk, 并删除会话文件
+async fn remove_session_files(session: &ChunksSession) {
+    match session.assembly {
+        AssemblyMode::Chunks => {
+            for current_chunk_hash in session.chunks_hash.iter() {
+                if current_chunk_hash != EMPTY_CHUNK {
+                    let key = store_key(session.hash_algorithm, current_chunk_hash, &session.identify);
+                    release_stored_chunk(&key, &session.holder()).await;
+                }
+            }
+        }
+        AssemblyMode::Preallocated | AssemblyMode::Append => {
+            // 合并后已被移走
+            let _ = fs::remove_file(assembling_path(&session.identify)).await;
+        }
+    }
+    if let Err(err) = fs::remove_file(session_path(&session.identify)).await {
+        println!("failed to delete session file of {}: {}", session.identify, err);
+    }
+    let _ = fs::remove_file(session_log_path(&session.identify)).await;
+}
+
+// 恢复时发现会话已过期, 会话还没有引用任何 chunk, 只删除它自己的文件
+async fn discard_session_files(session: &ChunksSession) {
+    for current_chunk_hash in session.chunks_hash.iter() {
+        if current_chunk_hash != EMPTY_CHUNK {
+            let _ = fs::remove_file(legacy_chunk_path(current_chunk_hash, &session.identify)).await;
+        }
+    }
+    let _ = fs::remove_file(assembling_path(&session.identify)).await;
+    let _ = fs::remove_file(session_path(&session.identify)).await;
+    let _ = fs::remove_file(session_log_path(&session.identify)).await;
+}
 
-    // 创建目录
-    if let Some(index) = full_path.rfind("/") {
-        fs::create_dir_all(Path::new(&UPLOAD_CHUNKS_CONFIG.base_path).join(&full_path[0..index]))
-            .await?;
+// 恢复会话时重新引用 chunk, 丢失或损坏时返回 false
+async fn restore_stored_chunk(
+    refs: &mut HashMap<String, ChunkHolders>,
+    session: &ChunksSession,
+    chunk_hash: &str,
+) -> bool {
+    let (hash_algorithm, identify) = (session.hash_algorithm, session.identify.as_str());
+    let key = store_key(hash_algorithm, chunk_hash, identify);
+    let path = stored_chunk_path(&key);
+    let legacy_path = legacy_chunk_path(chunk_hash, identify);
+    if let Some(holders) = refs.get_mut(&key) {
+        // 已被之前恢复的会话校验过
+        *holders.entry(session.holder()).or_default() += 1;
+        let _ = fs::remove_file(&legacy_path).await;
+        return true;
+    }
+    if fs::metadata(&legacy_path).await.is_ok() {
+        let _ = fs::rename(&legacy_path, &path).await;
+    }
+    // 之前在会话之间共享的 md5 chunk 复制一份给该会话, 共享的文件之后作为孤立文件清理
+    if !hash_algorithm.supports_deduplication() && fs::metadata(&path).await.is_err() {
+        let shared_path = stored_chunk_path(&format!("{}-{}", hash_algorithm.name(), chunk_hash));
+        let _ = fs::copy(&shared_path, &path).await;
+    }
+    let valid = match fs::read(&path).await {
+        Ok(chunk) => hash_algorithm.hash(&chunk) == chunk_hash,
+        Err(_) => false,
     };
+    if valid {
+        refs.insert(key, HashMap::from([(session.holder(), 1)]));
+    } else {
+        let _ = fs::remove_file(&path).await;
+    }
+    valid
+}
+
+// 定时清理, 返回的句柄用于取消
+fn spawn_expiration_clear(identify: String, survival_time: Duration) -> AbortHandle {
+    let uploaded_datas_ref = UPLOADED_CHUNKS_DATAS.clone();
+
+    task::spawn(async move {
+        sleep(survival_time).await;
+
+        // 结束之后删除所有chunk, 并删除对应哈希表中项目
+        let shared_session = match uploaded_datas_ref.files.lock().await.get(&identify) {
+            Some(shared_session) => shared_session.clone(),
+            None => return,
+        };
+        // 等待正在进行的上传或合并结束
+        let mut session = shared_session.lock().await;
+        if session.removed {
+            return;
+        }
+        // 不能取消自身
+        session.expiration = None;
+        close_session(&mut session, &shared_session).await;
+        println!("clear chunks: {}", identify)
+    })
+    .abort_handle()
+}
+
+// 结束会话: 取消定时清理, 删除所有 chunk, 并从全局表中删除
+async fn close_session(session: &mut ChunksSession, shared_session: &SharedSession) {
+    session.removed = true;
+    if let Some(expiration) = session.expiration.take() {
+        expiration.abort();
+    }
+    remove_session_files(session).await;
+    detach_session(&session.identify, shared_session).await;
+}
+
+impl ChunksSession {
+    // 开始计时, 最长存活时间从创建时算起, 重启后继续计算
+    fn start_expiration(&mut self, idle_time: Duration) {
+        let max_lifetime = UPLOAD_CHUNKS_CONFIG.session_max_lifetime.load(Ordering::Relaxed);
+        let lifetime = (self.created_at + max_lifetime).saturating_sub(unix_now());
+        self.lifetime_deadline = Some(Instant::now() + Duration::from_secs(lifetime));
+        self.schedule_expiration(idle_time);
+    }
+
+    // 重新计时, 闲置 idle_time 后清理, 但不超过最长存活时间
+    fn schedule_expiration(&mut self, idle_time: Duration) {
+        let survival_time = match self.lifetime_deadline {
+            Some(lifetime_deadline) => idle_time.min(lifetime_deadline.saturating_duration_since(Instant::now())),
+            None => idle_time,
+        };
+        self.expires_at = unix_now() + survival_time.as_secs();
+        let expiration = spawn_expiration_clear(self.identify.clone(), survival_time);
+        if let Some(expiration) = self.expiration.replace(expiration) {
+            expiration.abort();
+        }
+    }
+
+    // 接受 chunk 后顺延过期时间
+    fn touch(&mut self) {
+        self.schedule_expiration(session_idle_time());
+    }
+}
 
+// 未加入全局表就被丢弃的会话也要取消定时清理
+impl Drop for ChunksSession {
+    fn drop(&mut self) {
+        if let Some(expiration) = self.expiration.take() {
+            expiration.abort();
+        }
+    }
+}
+
+async fn get_session(identify: &str) -> Option<SharedSession> {
+    UPLOADED_CHUNKS_DATAS.files.lock().await.get(identify).cloned()
+}
+
+// 从全局表中删除会话, 表中已是同名的新会话时保留
+async fn detach_session(identify: &str, shared_session: &SharedSession) {
     let mut files = UPLOADED_CHUNKS_DATAS.files.lock().await;
+    if files.get(identify).is_some_and(|current| Arc::ptr_eq(current, shared_session)) {
+        files.remove(identify);
+    }
+}
 
-    // 合并chunks
-    println!("merge chunks");
-    // 如果有重写保存路径的函数，就使用，否则，直接拼接
-    let file_path = match rewrite_save_path_fn {
-        Some(rewrite_save_path_fn) => {
-            rewrite_save_path_fn(&UPLOAD_CHUNKS_CONFIG.base_path, String::from(full_path))
+fn update_merge_job(job_id: &str, update: impl FnOnce(&mut MergeJobStatus)) {
+    if let Some(status) = MERGE_JOBS.lock().get_mut(job_id) {
+        update(status);
+    }
+}
+
+// 接收 chunk 写入临时文件, 同时计算 hash, 返回计算出的 hash
+async fn receive_chunk(
+    chunk_content: ChunkContentStream,
+    hash_algorithm: HashAlgorithm,
+    temp_path: &str,
+    expected_size: Option<u64>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut file = File::create(temp_path).await?;
+    receive_chunk_into(chunk_content, hash_algorithm, &mut file, expected_size).await
+}
+
+// 从文件当前位置写入 chunk, 同时计算 hash
+async fn receive_chunk_into(
+    mut chunk_content: ChunkContentStream,
+    hash_algorithm: HashAlgorithm,
+    file: &mut File,
+    expected_size: Option<u64>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut max_chunk_size = UPLOAD_CHUNKS_CONFIG.max_chunk_size.load(Ordering::Relaxed);
+    if let Some(expected_size) = expected_size {
+        max_chunk_size = max_chunk_size.min(expected_size as usize);
+    }
+    let mut hasher = hash_algorithm.hasher();
+    let mut received = 0;
+
+    while let Some(bytes) = chunk_content.next().await {
+        let bytes = bytes?;
+        received += bytes.len();
+        if received > max_chunk_size {
+            return Err(format!("chunk is larger than {} bytes", max_chunk_size).into());
         }
-        None => format!("{}{}", UPLOAD_CHUNKS_CONFIG.base_path, full_path),
-    };
+        hasher.update(&bytes);
+        file.write_all(&bytes).await?;
+    }
+    file.flush().await?;
+
+    if let Some(expected_size) = expected_size {
+        if received as u64 != expected_size {
+            return Err(format!("chunk size {} does not match expected size {}", received, expected_size).into());
+        }
+    }
 
-    // 创建文件
-    File::create(&file_path).await?;
+    Ok(hasher.finalize())
+}
 
-    // 打开
-    let mut file = fs::OpenOptions::new().append(true).open(&file_path).await?;
+// 按顺序把会话的 chunk 写入目标文件, 提供了整个文件的 hash 时同时校验
+// progress 参数为已合并数量和总数量, 返回整个文件的 hash
+async fn merge_chunks_to(
+    session: &ChunksSession,
+    chunks_hash: &[String],
+    file_path: &str,
+    file_hash: Option<&str>,
+    progress: &(dyn Fn(usize, usize) + Sync),
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut file = File::create(file_path).await?;
+    let mut hasher = session.hash_algorithm.hasher();
+    let total_chunks = chunks_hash.len();
+    progress(0, total_chunks);
 
-    let chunks_hash = files
-        .get(identify)
-        .ok_or_else(|| String::from("get FileInfo error"))?;
-    let identify_hash = computeHash(identify);
     // 遍历拿到的hash并读取对应chunk写入目标文件
-    for current_chunk_hash in chunks_hash.iter() {
-        let chunk_path = format!(
-            "{}{}{:?}.chunk",
-            UPLOAD_CHUNKS_CONFIG.chunks_path, &current_chunk_hash, identify_hash,
-        );
-        let chunk = fs::read(&chunk_path).await?;
-
-        file.write(&chunk).await?;
+    for (index, current_chunk_hash) in chunks_hash.iter().enumerate() {
+        let chunk = fs::read(stored_chunk_path(&store_key(
+            session.hash_algorithm,
+            current_chunk_hash,
+            &session.identify,
+        )))
+        .await?;
+
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
         println!("chunk {} merged to file: {}", current_chunk_hash, file_path);
+        progress(index + 1, total_chunks);
     }
+    file.flush().await?;
 
-    // 结束之后删除所有chunk
-    for current_chunk_hash in chunks_hash.iter() {
-        let chunk_path = format!(
-            "{}{}{:?}.chunk",
-            UPLOAD_CHUNKS_CONFIG.chunks_path, &current_chunk_hash, identify_hash
-        );
-        fs::remove_file(&chunk_path).await?;
-        println!("deleted chunk: {}.chunk", current_chunk_hash);
+    let computed_hash = hasher.finalize();
+    if file_hash.is_some_and(|file_hash| file_hash != computed_hash) {
+        println!("file hash not match");
+        return Err("file hash not match".into());
     }
+    Ok(computed_hash)
+}
 
-    // 从hashmap中删除节点
-    files
-        .remove(identify)
-        .ok_or_else(|| String::from("remove hashmap element error"))?;
-    println!("deleted hashmap item: {}", file_path);
+// 预分配模式的合并: 提供了整个文件的 hash 时读取校验, 落盘后移动到目标位置
+// 返回最终保存路径和已知的整个文件的 hash, 未校验的预分配文件没有 hash, 由 checksum 步骤记录
+async fn finalize_assembled_to(
+    session: &ChunksSession,
+    file_path: &str,
+    file_hash: Option<&str>,
+    conflict_policy: ConflictPolicy,
+    progress: &(dyn Fn(usize, usize) + Sync),
+) -> Result<(String, Option<String>), Box<dyn std::error::Error + Send + Sync>> {
+    let path = assembling_path(&session.identify);
+    let mut file = OpenOptions::new().read(true).write(true).open(&path).await?;
+    let total_chunks = session.chunks_hash.len();
+
+    let computed_hash = if session.assembly == AssemblyMode::Append {
+        // 追加时已经边写边计算
+        Some(session.chunks_hash[0].clone())
+    } else if file_hash.is_some() {
+        let mut hasher = session.hash_algorithm.hasher();
+        let mut buffer = vec![0; 1 << 20];
+        let (mut read_bytes, file_size) = (0, file.metadata().await?.len());
+        loop {
+            let size = file.read(&mut buffer).await?;
+            if size == 0 {
+                break;
+            }
+            hasher.update(&buffer[..size]);
+            read_bytes += size as u64;
+            progress((read_bytes * total_chunks as u64 / file_size.max(1)) as usize, total_chunks);
+        }
+        Some(hasher.finalize())
+    } else {
+        None
+    };
+    if file_hash.is_some_and(|file_hash| computed_hash.as_deref() != Some(file_hash)) {
+        println!("file hash not match");
+        return Err("file hash not match".into());
+    }
+    file.sync_all().await?;
+    drop(file);
+
+    let temp_path = format!("{}.{}.merging", file_path, rand::random::<u32>());
+    if fs::rename(&path, &temp_path).await.is_err() {
+        // chunks 目录与目标目录不在同一文件系统时复制
+        if let Err(err) = fs::copy(&path, &temp_path).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(err.into());
+        }
+    }
+    let file_path = match place_file(&temp_path, file_path, conflict_policy).await {
+        Ok(file_path) => file_path,
+        Err(err) => {
+            // 放回组装文件, 会话可以换一种策略重新合并
+            if fs::rename(&temp_path, &path).await.is_err() {
+                let _ = fs::remove_file(&temp_path).await;
+            }
+            return Err(err);
+        }
+    };
+    progress(total_chunks, total_chunks);
+    Ok((file_path, computed_hash))
+}
+
+// 预分配模式下重新计算已写入的 chunk 的 hash, 不一致的需要重新上传
+async fn verify_assembled_chunks(session: &mut ChunksSession) -> bool {
+    let layout = match &session.layout {
+        Some(layout) => layout.clone(),
+        None => return false,
+    };
+    let chunks_number = session.chunks_hash.len();
+    let mut changed = false;
+    // 位图与 chunk 数量不一致时全部重新上传
+    if session.written.0.len() != chunks_number.div_ceil(8) {
+        session.written = ChunkBitmap::new(chunks_number);
+        session.chunks_hash = vec![String::from(EMPTY_CHUNK); chunks_number];
+        changed = true;
+    }
+    let mut file = File::open(assembling_path(&session.identify)).await.ok();
+    for index in 0..chunks_number {
+        if !session.written.get(index) {
+            continue;
+        }
+        let valid = match file.as_mut() {
+            Some(file) => {
+                let size = match session.validate_chunk(index, chunks_number) {
+                    Ok(Some(size)) => size as usize,
+                    _ => 0,
+                };
+                let mut chunk = vec![0; size];
+                let read = async {
+                    file.seek(SeekFrom::Start(layout.chunk_offset(index))).await?;
+                    file.read_exact(&mut chunk).await
+                };
+                read.await.is_ok() && session.hash_algorithm.hash(&chunk) == session.chunks_hash[index]
+            }
+            None => false,
+        };
+        if !valid {
+            println!("chunk {} of {} is missing or corrupted", index, session.identify);
+            session.written.set(index, false);
+            session.chunks_hash[index] = String::from(EMPTY_CHUNK);
+            changed = true;
+        }
+    }
+    // 组装文件丢失时重新分配
+    if file.is_none() {
+        if let Ok(file) = File::create(assembling_path(&session.identify)).await {
+            let _ = file.set_len(layout.file_size).await;
+        }
+        if session.appended != 0 {
+            session.appended = 0;
+            changed = true;
+        }
+    }
+    changed
+}
+
+// 启动时恢复持久化的会话, 并校验磁盘上的 chunk, 损坏或丢失的 chunk 需要重新上传
+pub async fn restore_chunks_sessions() -> Result<usize, Box<dyn std::error::Error>> {
+    let mut files = UPLOADED_CHUNKS_DATAS.files.lock().await;
+    let mut refs = CHUNK_STORE_REFS.lock().await;
+    let mut restored = 0;
+
+    let mut entries = fs::read_dir(&UPLOAD_CHUNKS_CONFIG.chunks_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if !path.to_string_lossy().ends_with(".session.json") {
+            continue;
+        }
+        let mut session: ChunksSession = match fs::read(&path).await.map(|content| serde_json::from_slice(&content)) {
+            Ok(Ok(session)) => session,
+            _ => {
+                println!("invalid session file: {}", path.display());
+                continue;
+            }
+        };
+        // 已在内存中的会话不需要恢复
+        if files.contains_key(&session.identify) {
+            continue;
+        }
+        // 有追加记录时之后重写会话文件
+        let mut changed = replay_session_log(&mut session).await;
+
+        let now = unix_now();
+        if session.created_at == 0 {
+            session.created_at = session.expires_at.saturating_sub(CHUNK_SURVIVAL_TIME);
+        }
+        let max_lifetime = UPLOAD_CHUNKS_CONFIG.session_max_lifetime.load(Ordering::Relaxed);
+        if session.expires_at <= now || session.created_at + max_lifetime <= now {
+            discard_session_files(&session).await;
+            println!("clear expired chunks: {}", session.identify);
+            continue;
+        }
+
+        let chunks_number = match session.assembly {
+            AssemblyMode::Chunks => session.chunks_hash.len(),
+            AssemblyMode::Preallocated | AssemblyMode::Append => {
+                changed = verify_assembled_chunks(&mut session).await;
+                0
+            }
+        };
+        for index in 0..chunks_number {
+            let current_chunk_hash = &session.chunks_hash[index];
+            if current_chunk_hash == EMPTY_CHUNK {
+                continue;
+            }
+            if !restore_stored_chunk(&mut refs, &session, current_chunk_hash).await {
+                println!("chunk {} of {} is missing or corrupted", index, session.identify);
+                session.chunks_hash[index] = String::from(EMPTY_CHUNK);
+                changed = true;
+            }
+        }
+        if changed {
+            persist_session(&mut session).await?;
+        } else {
+            session.logged_chunks = Some(0);
+        }
+
+        // 闲置时间从重启前最后一次接受 chunk 算起
+        session.start_expiration(Duration::from_secs(session.expires_at - now));
+        files.insert(session.identify.clone(), Arc::new(Mutex::new(session)));
+        restored += 1;
+    }
+
+    println!("restored chunks sessions: {}", restored);
+    Ok(restored)
+}
+
+// 清理 chunks 目录中不属于任何会话的文件, 以及中断的上传留下的临时文件
+pub(crate) async fn sweep_orphaned_chunks(sweeper: &mut OrphanSweeper) -> std::io::Result<()> {
+    let sessions: HashSet<String> = UPLOADED_CHUNKS_DATAS
+        .files
+        .lock()
+        .await
+        .keys()
+        .map(|identify| format!("{:?}", computeHash(identify)))
+        .collect();
+    // 扫描期间持有引用计数的锁, 不能同时存入或引用 chunk
+    let refs = CHUNK_STORE_REFS.lock().await;
+    let mut entries = fs::read_dir(&UPLOAD_CHUNKS_CONFIG.chunks_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let orphaned = if let Some(store_key) = file_name.strip_suffix(".chunk") {
+            !refs.contains_key(store_key)
+        } else if let Some(session_hash) = file_name
+            .strip_suffix(".session.json")
+            .or_else(|| file_name.strip_suffix(".session.log"))
+            .or_else(|| file_name.strip_suffix(".assembling"))
+        {
+            !sessions.contains(session_hash)
+        } else {
+            file_name.ends_with(".uploading") || file_name.ends_with(".tmp")
+        };
+        if orphaned {
+            sweeper.sweep(&entry.path(), &metadata).await;
+        }
+    }
+    drop(refs);
+
+    // 合并和秒传的临时文件在保存目录中
+    let mut directories = vec![Path::new(&UPLOAD_CHUNKS_CONFIG.base_path).to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let mut entries = fs::read_dir(&directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let path = entry.path();
+            if metadata.is_dir() {
+                directories.push(path);
+            } else if path.extension().is_some_and(|extension| extension == "merging" || extension == "copying") {
+                sweeper.sweep(&path, &metadata).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+// 按客户端声明的布局创建会话, 之后的每个 chunk 都按该布局校验
+pub async fn create_upload_session_raw(
+    identify: &str,
+    request: CreateUploadSession,
+    owner: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let hash_algorithm = match &request.hash_algorithm {
+        Some(name) => HashAlgorithm::from_name(name)?,
+        None => HashAlgorithm::default(),
+    };
+    if request.file_name.is_empty() {
+        return Err("fileName is empty".into());
+    }
+    if request.file_size > MAX_SIZE as u64 {
+        return Err(format!("fileSize is larger than {} bytes", MAX_SIZE).into());
+    }
+    let max_chunk_size = UPLOAD_CHUNKS_CONFIG.max_chunk_size.load(Ordering::Relaxed) as u64;
+    if request.chunk_size == 0 || request.chunk_size > max_chunk_size {
+        return Err(format!("chunkSize must be between 1 and {} bytes", max_chunk_size).into());
+    }
+    let min_chunk_size = UPLOAD_CHUNKS_CONFIG.min_chunk_size.load(Ordering::Relaxed) as u64;
+    if request.chunk_size < min_chunk_size && request.chunk_size < request.file_size {
+        return Err(format!("chunkSize must be at least {} bytes", min_chunk_size).into());
+    }
+    check_chunks_number(request.chunks_number)?;
+    // 空文件也需要一个(空的) chunk
+    let expected_chunks_number = request.file_size.div_ceil(request.chunk_size).max(1) as usize;
+    if request.chunks_number != expected_chunks_number {
+        return Err(format!(
+            "chunksNumber {} does not match fileSize {} and chunkSize {}, expected {}",
+            request.chunks_number, request.file_size, request.chunk_size, expected_chunks_number
+        )
+        .into());
+    }
+
+    let mut session = ChunksSession {
+        identify: String::from(identify),
+        chunks_hash: vec![String::from(EMPTY_CHUNK); request.chunks_number],
+        expires_at: 0,
+        created_at: unix_now(),
+        hash_algorithm,
+        layout: Some(ChunksLayout {
+            file_name: request.file_name,
+            file_size: request.file_size,
+            chunk_size: request.chunk_size,
+            file_hash: request.file_hash.as_deref().map(normalize_hash),
+        }),
+        assembly: request.assembly,
+        written: ChunkBitmap::new(request.chunks_number),
+        uploading: HashSet::new(),
+        appended: 0,
+        append_hasher: None,
+        owner: owner.map(String::from),
+        max_parts: None,
+        expiration: None,
+        lifetime_deadline: None,
+        removed: false,
+        log_generation: 0,
+        logged_chunks: None,
+    };
+    if session.assembly == AssemblyMode::Append {
+        return Err("assembly append is only used by tus uploads".into());
+    }
+
+    let mut files = UPLOADED_CHUNKS_DATAS.files.lock().await;
+    if files.contains_key(identify) {
+        return Err("upload session already exists".into());
+    }
+    if session.assembly == AssemblyMode::Preallocated {
+        let file = File::create(assembling_path(identify)).await?;
+        file.set_len(request.file_size).await?;
+    }
+    session.start_expiration(session_idle_time());
+    persist_session(&mut session).await?;
+    files.insert(String::from(identify), Arc::new(Mutex::new(session)));
+
+    Ok(())
+}
+
+pub async fn get_uploaded_chunks_hashes_raw(identify: &str, owner: Option<&str>) -> String {
+    let shared_session = match get_session(identify).await {
+        Some(shared_session) => shared_session,
+        None => return String::from("[]"),
+    };
+    let session = shared_session.lock().await;
+
+    // 如果为空，不存在，不是创建者，没法获取或者错误，就返回一个空数组 json
+    if session.removed || !session.is_owned_by(owner) {
+        return String::from("[]");
+    }
+    serde_json::to_string(&session.chunks_hash).unwrap_or_else(|_| String::from("[]"))
+}
+
+// 查询上传会话的结果
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadSessionStatus {
+    pub chunks_hash: ChunksHash,
+    pub expires_at: u64, // 闲置到该时间后清理, unix 秒
+    pub max_expires_at: u64, // 即使一直在上传, 最晚到该时间清理, unix 秒
+}
+
+pub async fn get_upload_session_status_raw(identify: &str, owner: Option<&str>) -> Option<UploadSessionStatus> {
+    let shared_session = get_session(identify).await?;
+    let session = shared_session.lock().await;
+    if session.removed || !session.is_owned_by(owner) {
+        return None;
+    }
+    let max_lifetime = UPLOAD_CHUNKS_CONFIG.session_max_lifetime.load(Ordering::Relaxed);
+    Some(UploadSessionStatus {
+        chunks_hash: session.chunks_hash.clone(),
+        expires_at: session.expires_at,
+        max_expires_at: session.created_at + max_lifetime,
+    })
+}
+
+// 预分配模式: 边接收边写入组装文件的对应位置, hash 一致后才标记为已写入
+async fn write_chunk_in_place(
+    shared_session: SharedSession,
+    chunk_index: usize,
+    chunks_number: usize,
+    chunk_hash: &str,
+    chunk_content: ChunkContentStream,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (identify, hash_algorithm, offset, expected_size) = {
+        let mut session = shared_session.lock().await;
+        if session.removed {
+            return Err("upload session has been merged or removed".into());
+        }
+        let expected_size = session.validate_chunk(chunk_index, chunks_number)?;
+        // 相同内容已写入
+        if session.written.get(chunk_index) && session.chunks_hash[chunk_index] == chunk_hash {
+            return Ok(String::from("true"));
+        }
+        if !session.uploading.insert(chunk_index) {
+            return Err(format!("chunk {} is being uploaded", chunk_index).into());
+        }
+        // 覆盖已写入的位置前先标记为未写入
+        session.written.set(chunk_index, false);
+        session.chunks_hash[chunk_index] = String::from(EMPTY_CHUNK);
+        let offset = session.layout.as_ref().map_or(0, |layout| layout.chunk_offset(chunk_index));
+        (session.identify.clone(), session.hash_algorithm, offset, expected_size)
+    };
+
+    let received = async {
+        let mut file = OpenOptions::new().write(true).open(assembling_path(&identify)).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        receive_chunk_into(chunk_content, hash_algorithm, &mut file, expected_size).await
+    };
+    let received: Result<String, Box<dyn std::error::Error>> = received.await;
+
+    let mut session = shared_session.lock().await;
+    session.uploading.remove(&chunk_index);
+    if session.removed {
+        return Err("upload session has been merged or removed".into());
+    }
+    let computed_hash = received?;
+    if computed_hash != chunk_hash {
+        println!("chunk hash not match");
+        return Err("chunk hash not match".into());
+    }
+    session.written.set(chunk_index, true);
+    session.chunks_hash[chunk_index] = computed_hash;
+    session.touch();
+    persist_chunk(&mut session, chunk_index).await?;
+    println!("wrote chunk {} of {} at offset {}", chunk_index, identify, offset);
+    Ok(String::from("true"))
+}
+
+pub async fn split_chunks_upload_raw(
+    request: UploadChunkRequest,
+    chunk_content: ChunkContentStream,
+    owner: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let UploadChunkRequest { identify, chunk_hash, chunk_index, chunks_number, hash_algorithm } = request;
+    let (identify, chunk_hash) = (identify.as_str(), normalize_hash(&chunk_hash));
+    let chunk_hash = chunk_hash.as_str();
+
+    // 同一会话只能使用一种算法, 且 chunk 需符合会话布局
+    let expected_size = match get_session(identify).await {
+        Some(shared_session) => {
+            let session = shared_session.lock().await;
+            let expected_size = session.accept_chunk(chunk_index, chunks_number, hash_algorithm, owner)?;
+            if session.assembly == AssemblyMode::Preallocated {
+                drop(session);
+                return write_chunk_in_place(shared_session, chunk_index, chunks_number, chunk_hash, chunk_content)
+                    .await;
+            }
+            expected_size
+        }
+        None => {
+            check_chunks_number(chunks_number)?;
+            if chunk_index >= chunks_number {
+                return Err(format!(
+                    "chunkIndex {} is out of range, chunksNumber is {}",
+                    chunk_index, chunks_number
+                )
+                .into());
+            }
+            None
+        }
+    };
+
+    let key = store_key(hash_algorithm, chunk_hash, identify);
+    // 同一 chunk 可能被并发上传, 临时文件名需要唯一
+    let temp_path = format!("{}.{}.uploading", stored_chunk_path(&key), rand::random::<u32>());
+
+    // 获得文件内容, 写入临时文件并计算hash
+    let computed_hash = match receive_chunk(chunk_content, hash_algorithm, &temp_path, expected_size).await {
+        Ok(computed_hash) => computed_hash,
+        Err(err) => {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(err);
+        }
+    };
+
+    println!(
+        "computed_hash: {:?}\r\nchunkHash: {}",
+        &computed_hash, chunk_hash
+    );
+
+    if chunk_hash != computed_hash {
+        println!("chunk hash not match");
+        let _ = fs::remove_file(&temp_path).await;
+        return Err("chunk hash not match".into());
+    }
+
+    // 存储chunk标识
+    let shared_session = get_or_create_session(identify, chunks_number, hash_algorithm, owner).await;
+    store_received_chunk(&shared_session, chunk_index, chunks_number, hash_algorithm, chunk_hash, &temp_path, owner)
+        .await?;
+    Ok(String::from("true"))
+}
+
+// 把 hash 校验过的临时文件放入存储并记录到会话中
+async fn store_received_chunk(
+    shared_session: &SharedSession,
+    chunk_index: usize,
+    chunks_number: usize,
+    hash_algorithm: HashAlgorithm,
+    chunk_hash: &str,
+    temp_path: &str,
+    owner: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut session = shared_session.lock().await;
+    // 接收期间会话可能已被合并或清理, 也可能被并发创建, 再次校验
+    if let Err(err) = session.accept_chunk(chunk_index, chunks_number, hash_algorithm, owner) {
+        let _ = fs::remove_file(temp_path).await;
+        return Err(err.into());
+    }
+
+    // hash 一致后才放入 chunk 存储
+    let key = store_key(hash_algorithm, chunk_hash, &session.identify);
+    store_chunk(&key, temp_path, &session.holder()).await?;
+    println!("saved chunk: {}", &key);
+    session.set_stored_chunk(chunk_index, String::from(chunk_hash)).await;
+    session.touch();
+    persist_chunk(&mut session, chunk_index).await?;
+    Ok(())
+}
+
+// 会话不存在时按 chunk 数量创建隐式会话
+async fn get_or_create_session(
+    identify: &str,
+    chunks_number: usize,
+    hash_algorithm: HashAlgorithm,
+    owner: Option<&str>,
+) -> SharedSession {
+    let mut files = UPLOADED_CHUNKS_DATAS.files.lock().await;
+    match files.get(identify) {
+        Some(shared_session) => shared_session.clone(),
+        None => {
+            // 开辟指定长度vec空间，填充"empty"
+            let mut session = ChunksSession {
+                identify: String::from(identify),
+                chunks_hash: vec![String::from(EMPTY_CHUNK); chunks_number],
+                expires_at: 0,
+                created_at: unix_now(),
+                hash_algorithm,
+                layout: None,
+                assembly: AssemblyMode::Chunks,
+                written: ChunkBitmap::default(),
+                uploading: HashSet::new(),
+                appended: 0,
+                append_hasher: None,
+                owner: owner.map(String::from),
+                max_parts: None,
+                expiration: None,
+                lifetime_deadline: None,
+                removed: false,
+        log_generation: 0,
+        logged_chunks: None,
+            };
+
+            session.start_expiration(session_idle_time());
+
+            let shared_session = Arc::new(Mutex::new(session));
+            files.insert(String::from(identify), shared_session.clone());
+            shared_session
+        }
+    }
+}
+
+// 把存储中的 chunk 复制到组装文件的对应位置
+async fn copy_stored_chunk_into(store_key: &str, identify: &str, offset: u64) -> std::io::Result<()> {
+    let chunk = fs::read(stored_chunk_path(store_key)).await?;
+    let mut file = OpenOptions::new().write(true).open(assembling_path(identify)).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    file.write_all(&chunk).await?;
+    file.flush().await
+}
+
+// 客户端按顺序提供所有 chunk 的 hash, 同一服务中同一创建者已上传过的 chunk 直接引用, 不需要再上传
+// 其他创建者上传的 chunk 不能引用, 匿名会话不能引用
+// 返回会话当前的 chunk hash 列表, 仍为 empty 的需要上传
+pub async fn reuse_stored_chunks_raw(
+    request: ReuseChunksRequest,
+    owner: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let ReuseChunksRequest { identify, chunks_number, hash_algorithm, chunks_hash } = request;
+    let identify = identify.as_str();
+    if !hash_algorithm.supports_deduplication() {
+        return Err(format!("hash algorithm {} does not support reusing stored chunks", hash_algorithm.name()).into());
+    }
+    if owner.unwrap_or_default().is_empty() {
+        return Err("reusing stored chunks requires an owner".into());
+    }
+    check_chunks_number(chunks_number)?;
+    if chunks_hash.len() != chunks_number {
+        return Err(format!(
+            "{} chunk hashes provided, chunksNumber is {}",
+            chunks_hash.len(),
+            chunks_number
+        )
+        .into());
+    }
+
+    let shared_session = get_or_create_session(identify, chunks_number, hash_algorithm, owner).await;
+    let mut session = shared_session.lock().await;
+    if session.owner.as_deref() != owner {
+        return Err("not the owner of the upload session".into());
+    }
+    let holder = session.holder();
+    let mut changed = false;
+    for (chunk_index, chunk_hash) in chunks_hash.iter().enumerate() {
+        let chunk_hash = normalize_hash(chunk_hash);
+        let expected_size = session.accept_chunk(chunk_index, chunks_number, hash_algorithm, owner)?;
+        if chunk_hash.is_empty() || chunk_hash == EMPTY_CHUNK || session.chunks_hash.get(chunk_index) == Some(&chunk_hash) {
+            continue;
+        }
+        // 预分配模式下正在写入的位置不能覆盖
+        if session.uploading.contains(&chunk_index) {
+            continue;
+        }
+
+        let key = store_key(hash_algorithm, &chunk_hash, identify);
+        if !retain_stored_chunk(&key, &holder).await {
+            continue;
+        }
+        // 大小不符合会话布局时不能使用
+        if let Some(expected_size) = expected_size {
+            let size = fs::metadata(stored_chunk_path(&key)).await.map(|metadata| metadata.len());
+            if size.ok() != Some(expected_size) {
+                release_stored_chunk(&key, &holder).await;
+                continue;
+            }
+        }
+
+        match session.assembly {
+            AssemblyMode::Chunks => session.set_stored_chunk(chunk_index, chunk_hash).await,
+            AssemblyMode::Preallocated | AssemblyMode::Append => {
+                // 覆盖前先标记为未写入, 复制失败时需要重新上传
+                session.written.set(chunk_index, false);
+                session.chunks_hash[chunk_index] = String::from(EMPTY_CHUNK);
+                let offset = session.layout.as_ref().map_or(0, |layout| layout.chunk_offset(chunk_index));
+                let copied = copy_stored_chunk_into(&key, identify, offset).await;
+                release_stored_chunk(&key, &holder).await;
+                if copied.is_ok() {
+                    session.written.set(chunk_index, true);
+                    session.chunks_hash[chunk_index] = chunk_hash;
+                }
+            }
+        }
+        changed = true;
+    }
+    if changed {
+        session.touch();
+        persist_session(&mut session).await?;
+    }
+
+    Ok(serde_json::to_string(&session.chunks_hash)?)
+}
+
+// 创建追加模式的会话, 预先分配整个文件
+pub async fn create_append_session_raw(
+    identify: &str,
+    file_name: String,
+    file_size: u64,
+    owner: Option<&str>,
+) -> Result<AppendStatus, Box<dyn std::error::Error>> {
+    if file_size > MAX_SIZE as u64 {
+        return Err(format!("file is larger than {} bytes", MAX_SIZE).into());
+    }
+    let hash_algorithm = HashAlgorithm::default();
+    let session = ChunksSession {
+        identify: String::from(identify),
+        chunks_hash: vec![String::from(EMPTY_CHUNK)],
+        expires_at: 0,
+        created_at: unix_now(),
+        hash_algorithm,
+        layout: Some(ChunksLayout {
+            file_name,
+            file_size,
+            chunk_size: file_size,
+            file_hash: None,
+        }),
+        assembly: AssemblyMode::Append,
+        written: ChunkBitmap::new(1),
+        uploading: HashSet::new(),
+        appended: 0,
+        append_hasher: Some(hash_algorithm.hasher()),
+        owner: owner.map(String::from),
+        max_parts: None,
+        expiration: None,
+        lifetime_deadline: None,
+        removed: false,
+        log_generation: 0,
+        logged_chunks: None,
+    };
+
+    let mut files = UPLOADED_CHUNKS_DATAS.files.lock().await;
+    if files.contains_key(identify) {
+        return Err("upload session already exists".into());
+    }
+    let file = File::create(assembling_path(identify)).await?;
+    file.set_len(file_size).await?;
+    let mut session = session;
+    // 空文件创建即完成
+    if file_size == 0 {
+        session.complete_append().await?;
+    }
+    session.start_expiration(session_idle_time());
+    persist_session(&mut session).await?;
+    let status = session.append_status();
+    files.insert(String::from(identify), Arc::new(Mutex::new(session)));
+
+    Ok(status)
+}
+
+impl ChunksSession {
+    fn append_status(&self) -> AppendStatus {
+        AppendStatus {
+            offset: self.appended,
+            length: self.layout.as_ref().map_or(0, |layout| layout.file_size),
+            expires_at: self.expires_at,
+        }
+    }
+
+    // 全部写入后把整个文件标记为已写入的 chunk
+    async fn complete_append(&mut self) -> std::io::Result<()> {
+        let file_hash = match self.append_hasher.take() {
+            Some(hasher) => hasher.finalize(),
+            None => self.hash_algorithm.hash(&fs::read(assembling_path(&self.identify)).await?),
+        };
+        self.chunks_hash[0] = file_hash;
+        self.written.set(0, true);
+        Ok(())
+    }
+}
+
+async fn get_append_session(identify: &str) -> Option<SharedSession> {
+    let shared_session = get_session(identify).await?;
+    let is_append = shared_session.lock().await.assembly == AssemblyMode::Append;
+    is_append.then_some(shared_session)
+}
+
+pub async fn get_append_status_raw(identify: &str, owner: Option<&str>) -> Option<AppendStatus> {
+    let shared_session = get_append_session(identify).await?;
+    let session = shared_session.lock().await;
+    (!session.removed && session.is_owned_by(owner)).then(|| session.append_status())
+}
+
+// 从 offset 开始追加写入, offset 必须等于已写入的字节数
+// 提供 checksum(算法和 hex)时校验不通过不会写入, 否则连接中断时保留已收到的部分
+pub async fn append_chunk_raw(
+    identify: &str,
+    owner: Option<&str>,
+    offset: u64,
+    mut chunk_content: ChunkContentStream,
+    checksum: Option<(HashAlgorithm, String)>,
+) -> Result<AppendStatus, AppendError> {
+    let shared_session = get_append_session(identify).await.ok_or(AppendError::NotFound)?;
+    let (length, mut file_hasher) = {
+        let mut session = shared_session.lock().await;
+        // 不是创建者时与不存在相同
+        if session.removed || !session.is_owned_by(owner) {
+            return Err(AppendError::NotFound);
+        }
+        if offset != session.appended {
+            return Err(AppendError::OffsetMismatch(session.appended));
+        }
+        if !session.uploading.insert(0) {
+            return Err(AppendError::Locked);
+        }
+        (session.append_status().length, session.append_hasher.clone())
+    };
+
+    let mut checksum_hasher = checksum.as_ref().map(|(hash_algorithm, _)| hash_algorithm.hasher());
+    let mut received: u64 = 0;
+    let written: Result<(), AppendError> = async {
+        let failed = |err: std::io::Error| AppendError::Failed(err.to_string());
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(assembling_path(identify))
+            .await
+            .map_err(failed)?;
+        file.seek(SeekFrom::Start(offset)).await.map_err(failed)?;
+        while let Some(bytes) = chunk_content.next().await {
+            let bytes = bytes.map_err(|err| AppendError::Failed(err.to_string()))?;
+            if offset + received + bytes.len() as u64 > length {
+                return Err(AppendError::TooLarge);
+            }
+            file.write_all(&bytes).await.map_err(failed)?;
+            received += bytes.len() as u64;
+            if let Some(file_hasher) = file_hasher.as_mut() {
+                file_hasher.update(&bytes);
+            }
+            if let Some(checksum_hasher) = checksum_hasher.as_mut() {
+                checksum_hasher.update(&bytes);
+            }
+        }
+        file.flush().await.map_err(failed)
+    }
+    .await;
+
+    let mut session = shared_session.lock().await;
+    session.uploading.remove(&0);
+    if session.removed {
+        return Err(AppendError::NotFound);
+    }
+    // 超出声明的长度时全部丢弃, 有 checksum 时只接受完整且校验通过的内容
+    let checksum_matches = checksum_hasher
+        .zip(checksum)
+        .map(|(checksum_hasher, (_, expected))| written.is_ok() && checksum_hasher.finalize() == expected);
+    match (&written, checksum_matches) {
+        (Err(AppendError::TooLarge), _) | (Err(_), Some(_)) => return written.map(|_| session.append_status()),
+        (Ok(_), Some(false)) => return Err(AppendError::ChecksumMismatch),
+        _ => {}
+    }
+
+    session.appended += received;
+    session.append_hasher = file_hasher;
+    session.touch();
+    if session.appended == length && !session.written.get(0) {
+        session.complete_append().await.map_err(|err| AppendError::Failed(err.to_string()))?;
+    }
+    persist_session(&mut session).await.map_err(|err| AppendError::Failed(err.to_string()))?;
+    println!("appended {} bytes to {}, offset {}", received, identify, session.appended);
+    written.map(|_| session.append_status())
+}
+
+// 删除会话及其所有 chunk, 并取消定时清理
+// 会话设置了创建者时, owner 需要一致
+pub async fn remove_upload_session_raw(identify: &str, owner: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let shared_session = get_session(identify)
+        .await
+        .ok_or_else(|| String::from("upload session not found"))?;
+    let mut session = shared_session.lock().await;
+    if session.removed {
+        return Err("upload session not found".into());
+    }
+    if !session.is_owned_by(owner) {
+        return Err("not the owner of the upload session".into());
+    }
+    close_session(&mut session, &shared_session).await;
+    println!("removed upload session: {}", identify);
+    Ok(())
+}
+
+// 创建分段上传会话, 分段按序号稀疏保存, 合并时只使用选中的 chunk
+#[cfg(feature = "s3")]
+pub async fn create_multipart_session_raw(identify: &str, max_parts: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files = UPLOADED_CHUNKS_DATAS.files.lock().await;
+    if files.contains_key(identify) {
+        return Err("upload session already exists".into());
+    }
+    let mut session = ChunksSession {
+        identify: String::from(identify),
+        chunks_hash: Vec::new(),
+        expires_at: 0,
+        created_at: unix_now(),
+        hash_algorithm: HashAlgorithm::Md5,
+        layout: None,
+        assembly: AssemblyMode::Chunks,
+        written: ChunkBitmap::default(),
+        uploading: HashSet::new(),
+        appended: 0,
+        append_hasher: None,
+        owner: None,
+        max_parts: Some(max_parts),
+        expiration: None,
+        lifetime_deadline: None,
+        removed: false,
+        log_generation: 0,
+        logged_chunks: None,
+    };
+    session.start_expiration(session_idle_time());
+    persist_session(&mut session).await?;
+    files.insert(String::from(identify), Arc::new(Mutex::new(session)));
+    Ok(())
+}
+
+// 上传分段, 由服务端计算 md5, 提供 expected_hash 时校验, 返回 md5
+#[cfg(feature = "s3")]
+pub async fn upload_part_raw(
+    identify: &str,
+    chunk_index: usize,
+    chunk_content: ChunkContentStream,
+    expected_hash: Option<String>,
+) -> Result<String, MultipartError> {
+    let shared_session = get_session(identify).await.ok_or(MultipartError::NoSuchUpload)?;
+    // 上传期间会话可能被合并或取消, 此时 store_received_chunk 会删除临时文件并返回错误
+    let chunks_number = {
+        let session = shared_session.lock().await;
+        if session.removed {
+            return Err(MultipartError::NoSuchUpload);
+        }
+        let chunks_number = session.chunks_hash.len();
+        session
+            .accept_chunk(chunk_index, chunks_number, HashAlgorithm::Md5, None)
+            .map_err(MultipartError::Failed)?;
+        chunks_number
+    };
+
+    // 接收前不知道 hash, 临时文件按会话命名
+    let temp_path = format!(
+        "{}{:?}.{}.uploading",
+        UPLOAD_CHUNKS_CONFIG.chunks_path,
+        computeHash(identify),
+        rand::random::<u32>()
+    );
+    let chunk_hash = match receive_chunk(chunk_content, HashAlgorithm::Md5, &temp_path, None).await {
+        Ok(chunk_hash) => chunk_hash,
+        Err(err) => {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(MultipartError::Failed(err.to_string()));
+        }
+    };
+    if expected_hash.is_some_and(|expected_hash| normalize_hash(&expected_hash) != chunk_hash) {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(MultipartError::BadDigest);
+    }
+
+    store_received_chunk(&shared_session, chunk_index, chunks_number, HashAlgorithm::Md5, &chunk_hash, &temp_path, None)
+        .await
+        .map_err(|err| MultipartError::Failed(err.to_string()))?;
+    Ok(chunk_hash)
+}
+
+// 已上传的分段: 序号, hash 和大小
+#[cfg(feature = "s3")]
+pub async fn list_parts_raw(identify: &str) -> Option<Vec<(usize, String, u64)>> {
+    let shared_session = get_session(identify).await?;
+    let session = shared_session.lock().await;
+    if session.removed {
+        return None;
+    }
+    let mut parts = Vec::new();
+    for (index, chunk_hash) in session.chunks_hash.iter().enumerate() {
+        if chunk_hash == EMPTY_CHUNK {
+            continue;
+        }
+        let path = stored_chunk_path(&store_key(session.hash_algorithm, chunk_hash, &session.identify));
+        let size = fs::metadata(path).await.map_or(0, |metadata| metadata.len());
+        parts.push((index, chunk_hash.clone(), size));
+    }
+    Some(parts)
+}
+
+// 按顺序合并选中的分段, 其余分段随会话一起释放, 返回保存路径
+#[cfg(feature = "s3")]
+pub async fn complete_multipart_raw(
+    identify: &str,
+    parts: Vec<(usize, String)>,
+    full_path: String,
+    target: &dyn MergeTarget,
+    max_file_size: u64,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    if get_session(identify).await.is_none() {
+        return Err(Box::new(MultipartError::NoSuchUpload));
+    }
+    let request = MergeChunksRequest {
+        identify: String::from(identify),
+        owner: None,
+        full_path: Some(full_path),
+        file_hash: None,
+        // 与 S3 一致, 覆盖同名对象
+        conflict_policy: ConflictPolicy::Overwrite,
+        max_file_size: Some(max_file_size),
+    };
+    merge_session(request, target, Some(parts), &|_, _| {}).await
+}
+
+// 只允许普通的相对路径, 压缩包和请求中的路径都不能离开所在目录
+pub(crate) fn relative_path(path: &Path) -> Result<PathBuf, String> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            _ => return Err(format!("path {} is not allowed", path.display())),
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Err(String::from("path is empty"));
+    }
+    Ok(relative)
+}
+
+// 得到文件的保存路径并创建目录, 请求中的路径不能离开服务的目录
+pub(crate) async fn resolve_save_path(full_path: &str, target: &dyn MergeTarget) -> std::io::Result<String> {
+    let full_path = relative_path(Path::new(full_path))
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+    let file_path = target.save_path(&UPLOAD_CHUNKS_CONFIG.base_path, &full_path.to_string_lossy());
+    // 按路径组成部分比较, 避免 al 和 alice 这样的前缀被当作同一目录
+    let root_path = target.root_path(&UPLOAD_CHUNKS_CONFIG.base_path);
+    if !Path::new(&file_path).starts_with(&root_path) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("path {} is not allowed", full_path.display()),
+        ));
+    }
+
+    // 创建目录, 以重写后的路径为准
+    if let Some(parent) = Path::new(&file_path).parent() {
+        fs::create_dir_all(parent).await?;
+    };
+    Ok(file_path)
+}
+
+// 在文件名和扩展名之间加上后缀, a/b.txt -> a/b{suffix}.txt
+fn with_name_suffix(file_path: &str, suffix: &str) -> String {
+    let name_start = file_path.rfind('/').map_or(0, |index| index + 1);
+    let insert_at = match file_path[name_start..].rfind('.') {
+        Some(index) if index > 0 => name_start + index,
+        _ => file_path.len(),
+    };
+    format!("{}{}{}", &file_path[..insert_at], suffix, &file_path[insert_at..])
+}
+
+// 目标不存在时才放入, 通过硬链接保证不会覆盖同时保存的文件, 已存在时返回 false
+async fn place_if_absent(temp_path: &str, file_path: &str) -> std::io::Result<bool> {
+    match fs::hard_link(temp_path, file_path).await {
+        Ok(_) => {
+            let _ = fs::remove_file(temp_path).await;
+            Ok(true)
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// 同名的文件最多尝试的序号
+const MAX_CONFLICT_SUFFIX: usize = 10000;
+
+// 把已完成的临时文件放到保存路径, 按策略处理已有文件, 返回最终保存路径
+pub(crate) async fn place_file(
+    temp_path: &str,
+    file_path: &str,
+    conflict_policy: ConflictPolicy,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    match conflict_policy {
+        ConflictPolicy::Overwrite => {
+            fs::rename(temp_path, file_path).await?;
+            Ok(String::from(file_path))
+        }
+        ConflictPolicy::Fail => {
+            if place_if_absent(temp_path, file_path).await? {
+                Ok(String::from(file_path))
+            } else {
+                Err("file already exists".into())
+            }
+        }
+        ConflictPolicy::Rename => {
+            for index in 0..MAX_CONFLICT_SUFFIX {
+                let candidate = match index {
+                    0 => String::from(file_path),
+                    _ => with_name_suffix(file_path, &format!(" ({})", index)),
+                };
+                if place_if_absent(temp_path, &candidate).await? {
+                    return Ok(candidate);
+                }
+            }
+            // 没有可用的文件名时不覆盖
+            Err("too many files with the same name".into())
+        }
+        ConflictPolicy::Version => {
+            // 已有文件先链接到版本路径, 再用新文件替换原路径
+            // 没有可用的版本路径时不替换, 避免丢失已有文件
+            for version in 1..MAX_CONFLICT_SUFFIX {
+                match fs::hard_link(file_path, with_name_suffix(file_path, &format!(".v{}", version))).await {
+                    Ok(_) => {}
+                    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
+                fs::rename(temp_path, file_path).await?;
+                return Ok(String::from(file_path));
+            }
+            Err("too many versions of the file".into())
+        }
+    }
+}
+
+// 合并会话的所有 chunk, 只锁住该会话, 返回合并后的文件路径
+async fn merge_session(
+    request: MergeChunksRequest,
+    target: &dyn MergeTarget,
+    // 只合并其中的部分 chunk(序号和 hash), 用于分段上传
+    parts: Option<Vec<(usize, String)>>,
+    progress: &(dyn Fn(usize, usize) + Sync),
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let MergeChunksRequest { identify, owner, full_path, file_hash, conflict_policy, max_file_size } = request;
+    let shared_session = get_session(&identify)
+        .await
+        .ok_or_else(|| String::from("get FileInfo error"))?;
+    let mut session = shared_session.lock().await;
+    // 等待锁期间会话可能已被合并或清理
+    if session.removed {
+        return Err("get FileInfo error".into());
+    }
+    if !session.is_owned_by(owner.as_deref()) {
+        return Err("not the owner of the upload session".into());
+    }
+
+    // 未选中的 chunk 不写入文件, 会话删除时一起释放
+    let selected_chunks_hash = match &parts {
+        Some(parts) => Some(session.select_parts(parts)?),
+        None => None,
+    };
+    let chunks_hash = selected_chunks_hash.as_ref().unwrap_or(&session.chunks_hash);
+
+    // 先检查是否所有 chunk 都已上传
+    let missing_chunks: Vec<usize> = match session.assembly {
+        AssemblyMode::Chunks => chunks_hash
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk_hash)| *chunk_hash == EMPTY_CHUNK)
+            .map(|(index, _)| index)
+            .collect(),
+        AssemblyMode::Preallocated | AssemblyMode::Append => session.written.missing(session.chunks_hash.len()),
+    };
+    if !missing_chunks.is_empty() {
+        return Err(Box::new(MissingChunks(missing_chunks)));
+    }
+
+    // 超过服务限制或最大尺寸的文件不合并, 隐式会话的大小只有合并时才知道
+    let max_file_size = max_file_size.unwrap_or(MAX_SIZE as u64).min(MAX_SIZE as u64);
+    if session_file_size(&session, chunks_hash).await? > max_file_size {
+        if parts.is_some() {
+            return Err(Box::new(MultipartError::EntityTooLarge(max_file_size)));
+        }
+        return Err(format!("file is larger than {} bytes", max_file_size).into());
+    }
+
+    // 合并时提供的 hash 优先, 否则使用创建会话时声明的 hash
+    let file_hash = match (file_hash, &session.layout) {
+        (Some(file_hash), _) => Some(normalize_hash(&file_hash)),
+        (None, Some(layout)) => layout.file_hash.clone(),
+        (None, None) => None,
+    };
+
+    // 未提供 fullPath 时使用创建会话时声明的文件名
+    let full_path = match (full_path, &session.layout) {
+        (Some(full_path), _) => full_path,
+        (None, Some(layout)) => layout.file_name.clone(),
+        (None, None) => return Err("request header fullPath not found or invalid".into()),
+    };
+
+    // 合并chunks
+    println!("merge chunks");
+    let file_path = resolve_save_path(&full_path, target).await?;
+    // 合并前先检查, 避免白白合并大文件, 放入时仍会再次检查
+    if conflict_policy == ConflictPolicy::Fail && fs::metadata(&file_path).await.is_ok() {
+        return Err("file already exists".into());
+    }
+
+    let (file_path, computed_hash) = match session.assembly {
+        AssemblyMode::Chunks => {
+            // 先合并到临时文件, 校验通过后才放到保存路径
+            let temp_path = format!("{}.{}.merging", file_path, rand::random::<u32>());
+            let merged = match merge_chunks_to(&session, chunks_hash, &temp_path, file_hash.as_deref(), progress).await {
+                Ok(computed_hash) => place_file(&temp_path, &file_path, conflict_policy)
+                    .await
+                    .map(|file_path| (file_path, Some(computed_hash))),
+                Err(err) => Err(err),
+            };
+            if merged.is_err() {
+                let _ = fs::remove_file(&temp_path).await;
+            }
+            merged?
+        }
+        AssemblyMode::Preallocated | AssemblyMode::Append => {
+            finalize_assembled_to(&session, &file_path, file_hash.as_deref(), conflict_policy, progress).await?
+        }
+    };
+    // 记录文件 hash, 之后相同的文件可以秒传
+    if let Some(computed_hash) = computed_hash {
+        let scope = stored_file_scope(session_service(&session.identify), session.owner.as_deref());
+        record_stored_file(&scope, session.hash_algorithm, &computed_hash, &file_path).await;
+    }
+
+    // 结束之后删除所有chunk, 并从hashmap中删除节点
+    close_session(&mut session, &shared_session).await;
+    println!("deleted hashmap item: {}", file_path);
+
+    Ok(file_path)
+}
+
+// 合并后执行目标的后续操作, 返回其结果
+pub async fn file_chunks_merge_raw(
+    request: MergeChunksRequest,
+    target: &dyn MergeTarget,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let file_path = merge_session(request, target, None, &|_, _| {})
+        .await
+        .map_err(|err| -> Box<dyn std::error::Error> { err })?;
+    Ok(target.after_merge(file_path).await?)
+}
+
+// 在后台合并, 进度和结果通过 job_id 查询, 合并成功后执行目标的后续操作
+pub async fn start_merge_job_raw(
+    job_id: String,
+    request: MergeChunksRequest,
+    target: Box<dyn MergeTarget>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let shared_session = get_session(&request.identify)
+        .await
+        .ok_or_else(|| String::from("get FileInfo error"))?;
+    if !shared_session.lock().await.is_owned_by(request.owner.as_deref()) {
+        return Err("not the owner of the upload session".into());
+    }
+
+    MERGE_JOBS.lock().insert(
+        job_id.clone(),
+        MergeJobStatus {
+            state: MergeJobState::Running,
+            merged_chunks: 0,
+            total_chunks: 0,
+            result: None,
+            error: None,
+            owner: request.owner.clone(),
+        },
+    );
+
+    task::spawn(async move {
+        let progress = |merged_chunks, total_chunks| {
+            update_merge_job(&job_id, |status| {
+                status.merged_chunks = merged_chunks;
+                status.total_chunks = total_chunks;
+            })
+        };
+        let merged = merge_session(request, target.as_ref(), None, &progress).await;
+        let result = match merged {
+            Ok(file_path) => target.after_merge(file_path).await,
+            Err(err) => Err(err.to_string()),
+        };
+        update_merge_job(&job_id, |status| match result {
+            Ok(result) => {
+                status.state = MergeJobState::Succeeded;
+                status.result = Some(result);
+            }
+            Err(err) => {
+                println!("merge job {} failed: {}", job_id, err);
+                status.state = MergeJobState::Failed;
+                status.error = Some(err);
+            }
+        });
+
+        sleep(Duration::from_secs(MERGE_JOB_SURVIVAL_TIME)).await;
+        MERGE_JOBS.lock().remove(&job_id);
+    });
+
+    Ok(())
+}
 
-    Ok(String::from(file_path))
+pub fn get_merge_job_status_raw(job_id: &str, owner: Option<&str>) -> Option<MergeJobStatus> {
+    MERGE_JOBS
+        .lock()
+        .get(job_id)
+        .filter(|status| status.owner.is_none() || status.owner.as_deref() == owner)
+        .cloned()
 }
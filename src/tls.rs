@@ -0,0 +1,145 @@
+use actix_web::{dev::Server, http::header, web, App, HttpRequest, HttpResponse, HttpServer};
+use hotwatch::Hotwatch;
+use parking_lot::RwLock;
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey, ServerConfig,
+};
+use rustls_pemfile::Item;
+use serde::Deserialize;
+
+use std::{fs as fsSync, io, io::BufReader, net::ToSocketAddrs, sync::Arc};
+
+use crate::file_watch::watch_file;
+
+// 监听器的 https 配置, 未配置时只提供 http 服务
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsConfig {
+    pub cert_path: String, // PEM 格式证书链
+    pub key_path: String,  // PEM 格式私钥
+    // 可选的 http 监听地址, 只负责重定向到 https
+    pub redirect_bind: Option<String>,
+}
+
+// 从 PEM 文件读取证书链和私钥
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<CertifiedKey, String> {
+    let mut cert_reader = BufReader::new(fsSync::File::open(cert_path).map_err(|err| err.to_string())?);
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut cert_reader)
+        .map_err(|err| err.to_string())?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(format!("no certificate found in {}", cert_path));
+    }
+
+    let mut key_reader = BufReader::new(fsSync::File::open(key_path).map_err(|err| err.to_string())?);
+    // 取第一个私钥, 支持 pkcs8 / rsa / ec
+    let key = loop {
+        match rustls_pemfile::read_one(&mut key_reader).map_err(|err| err.to_string())? {
+            Some(Item::PKCS8Key(key)) | Some(Item::RSAKey(key)) | Some(Item::ECKey(key)) => break PrivateKey(key),
+            Some(_) => continue,
+            None => return Err(format!("no private key found in {}", key_path)),
+        }
+    };
+    let signing_key = sign::any_supported_type(&key).map_err(|err| err.to_string())?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+// 每次握手时取当前证书, 证书文件变化后替换
+pub struct ReloadableCertResolver {
+    certified_key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for ReloadableCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key())
+    }
+}
+
+impl ReloadableCertResolver {
+    // 读取证书, 同时观察证书文件变化, 返回的 Hotwatch 需要保持存活
+    pub fn watch(cert_path: &str, key_path: &str) -> io::Result<(Arc<Self>, Hotwatch)> {
+        let certified_key =
+            load_certified_key(cert_path, key_path).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let resolver = Arc::new(ReloadableCertResolver {
+            certified_key: RwLock::new(Arc::new(certified_key)),
+        });
+
+        let mut hot_watch = Hotwatch::new().map_err(|err| io::Error::other(err.to_string()))?;
+        for watch_path in [cert_path, key_path] {
+            let resolver = resolver.clone();
+            let (cert_path, key_path) = (String::from(cert_path), String::from(key_path));
+            watch_file(&mut hot_watch, watch_path, move || {
+                // 重新加载失败时继续使用旧证书
+                match load_certified_key(&cert_path, &key_path) {
+                    Ok(certified_key) => {
+                        *resolver.certified_key.write() = Arc::new(certified_key);
+                        println!("certificate reloaded");
+                    }
+                    Err(err) => println!("failed to reload certificate: {}", err),
+                }
+            })?;
+        }
+        Ok((resolver, hot_watch))
+    }
+
+    // 当前使用的证书
+    pub fn certified_key(&self) -> Arc<CertifiedKey> {
+        self.certified_key.read().clone()
+    }
+}
+
+// 构建 rustls 配置, 同时观察证书文件变化, 返回的 Hotwatch 需要保持存活
+pub fn rustls_server_config(config: &TlsConfig) -> io::Result<(ServerConfig, Hotwatch)> {
+    let (resolver, hot_watch) = ReloadableCertResolver::watch(&config.cert_path, &config.key_path)?;
+
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+
+    Ok((server_config, hot_watch))
+}
+
+// https 监听端口, 重定向时使用
+#[derive(Clone, Copy)]
+pub struct HttpsPort(pub u16);
+
+// 所有请求重定向到 https 的同一路径
+pub async fn redirect_to_https(req: HttpRequest, https_port: web::Data<HttpsPort>) -> HttpResponse {
+    let connection_info = req.connection_info();
+    let host = connection_info.host();
+    // 去掉 http 端口, 兼容 [ipv6]:port
+    let host = match host.rfind(':') {
+        Some(index) if !host[index..].contains(']') => &host[..index],
+        _ => host,
+    };
+    let location = match https_port.0 {
+        443 => format!("https://{}{}", host, req.uri()),
+        port => format!("https://{}:{}{}", host, port, req.uri()),
+    };
+    HttpResponse::MovedPermanently()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+// 启动只做重定向的 http 服务
+pub fn redirect_server(redirect_bind: &str, https_bind: &str) -> io::Result<Server> {
+    // https 地址可以是主机名, 如 localhost:443
+    let https_addr = https_bind
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} has no address", https_bind)))?;
+    let https_port = HttpsPort(https_addr.port());
+    Ok(HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(https_port))
+            .default_service(web::to(redirect_to_https))
+    })
+    .bind(redirect_bind)?
+    .run())
+}
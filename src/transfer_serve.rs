@@ -1,20 +1,24 @@
 use actix_files::NamedFile;
-use actix_web::{error, get, post, web, Error, HttpRequest, HttpResponse};
+use actix_web::{error, get, post, web, Error, HttpRequest};
 use actix_web_lab::extract;
-use tokio::{fs, sync::Mutex, task, time::{ sleep, Duration }};
-use futures::StreamExt;
-use std::{collections::HashMap, fs as fsSync, sync::Arc};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex, task, time::{ sleep, Duration }};
+use futures::{future::BoxFuture, StreamExt};
+use std::{collections::{HashMap, HashSet}, fs as fsSync, path::Path, sync::Arc};
 use urlencoding::decode;
 
 use rand::Rng;
 
 use crate::actix_utils::get_header;
+use crate::garbage_collector::OrphanSweeper;
 
 const SURVIVAL_TIME: u64 = 7 * 86400; // 文件存活时间
 
 const MAX_SIZE: usize = 536870912; // 512MB最大尺寸
 
-const BASE_PATH: &'static str = "./files/";
+const BASE_PATH: &str = "./files/";
+
+// chunk 会话所属服务
+pub(crate) const SERVICE_NAME: &str = "transfer";
 
 struct UploadConfig {
     base_path: String, // 基本路径，存放文件的目录位置
@@ -41,6 +45,26 @@ lazy_static! {
     static ref UPLOADED_FILES_INFO: Arc<UploadedFilesInfo> = Arc::new(UploadedFilesInfo {
         files: Mutex::new(HashMap::new()),
     });
+    // 依次写入分享记录, 清理时重写记录不能丢失同时追加的分享
+    static ref SHARES_LEDGER_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// 创建过的分享文件, 每行一个 JSON 字符串, 重启后提取码丢失时据此清理
+// 分享的文件名以提取码结尾, 不会与该文件重名
+fn shares_ledger_path() -> String {
+    format!("{}.transfer_shares", UPLOAD_CONFIG.base_path)
+}
+
+async fn record_share(full_path: &str) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(full_path)?;
+    line.push('\n');
+    let _guard = SHARES_LEDGER_LOCK.lock().await;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(shares_ledger_path())
+        .await?;
+    file.write_all(line.as_bytes()).await
 }
 
 // 生成提取码
@@ -74,7 +98,7 @@ async fn save_and_expiration_clear(full_path: String, file_code: i32) -> Result<
                 None => println!("Remove HashMap Item Error"),
             }
             // 删除文件
-            if let Ok(_) = fs::remove_file(full_path).await {
+            if fs::remove_file(full_path).await.is_ok() {
                 // 哈希表中删除项目
                 println!("Removed Item in HashMap, key: {}", &file_code);
             }
@@ -83,18 +107,60 @@ async fn save_and_expiration_clear(full_path: String, file_code: i32) -> Result<
         action.await;
     });
 
+    record_share(&full_path).await?;
+
     // 将相关文件数据放入公共哈希表
     let mut files = UPLOADED_FILES_INFO.files.lock().await;
     files.insert(
         file_code,
         FileInfo {
-            full_path: full_path,
+            full_path,
         },
     );
 
     Ok(())
 }
 
+// 重启后提取码丢失, 记录过但不在表中的分享文件不会再被下载
+// 只清理记录中的文件, 保存目录下的其他文件不属于该服务, 已不存在的文件从记录中移除
+pub(crate) async fn sweep_orphaned_shares(sweeper: &mut OrphanSweeper) -> std::io::Result<()> {
+    let shared: HashSet<String> = UPLOADED_FILES_INFO
+        .files
+        .lock()
+        .await
+        .values()
+        .map(|file_info| file_info.full_path.clone())
+        .collect();
+    let _guard = SHARES_LEDGER_LOCK.lock().await;
+    let ledger_path = shares_ledger_path();
+    let content = match fs::read_to_string(&ledger_path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    let mut kept = String::new();
+    for line in content.lines() {
+        let full_path: String = match serde_json::from_str(line) {
+            Ok(full_path) => full_path,
+            Err(_) => continue,
+        };
+        let metadata = match fs::metadata(&full_path).await {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+        if !shared.contains(&full_path) {
+            sweeper.sweep(Path::new(&full_path), &metadata).await;
+        }
+        if fs::metadata(&full_path).await.is_ok() {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    let temp_path = format!("{}.tmp", ledger_path);
+    fs::write(&temp_path, kept).await?;
+    fs::rename(&temp_path, &ledger_path).await
+}
+
 #[post("/upload")]
 async fn upload(req: HttpRequest, payload: web::Payload) -> Result<String, Error> {
     async fn handler(
@@ -132,50 +198,59 @@ async fn upload(req: HttpRequest, payload: web::Payload) -> Result<String, Error
 
         Ok(fetch_code.to_string())
     }
-    handler(req, payload).await.or_else(|err| Err(error::ErrorBadRequest(err)))
+    handler(req, payload).await.map_err(error::ErrorBadRequest)
 }
 
-use crate::actix_split_chunks_upload_handlers::{
-    // merge chunk handler
-    file_chunks_merge_handler,
-    get_uploaded_chunks_hashes,
-    // uplaod chunk handler
-    split_chunks_upload_handler,
-};
-
-#[post("/fetch_uploaded_chunks_hashes")]
-async fn fetch_uploaded_chunks_hashes(req: HttpRequest) -> Result<String, Error> {
-    get_uploaded_chunks_hashes(req).await.or_else(|err| Err(error::ErrorBadRequest(err)))
+use crate::upload_policy::{configure_upload_routes, MergeTarget, UploadPolicy};
+
+// 分片上传, 秒传和 tus 上传完成后都创建分享, 结果为提取码
+struct TransferPolicy;
+
+// 一次合并对应的提取码
+struct ShareTarget {
+    fetch_code: i32,
 }
 
-#[post("/upload_chunk")]
-async fn upload_chunk(req: HttpRequest, payload: web::Payload) -> Result<String, Error> {
-    split_chunks_upload_handler(req, payload).await.or_else(|err| Err(error::ErrorBadRequest(err)))
+impl MergeTarget for ShareTarget {
+    // 转换路径，在后面加上提取码，防止重名覆盖
+    fn save_path(&self, base_path: &str, full_path: &str) -> String {
+        format!("{}{}{}", base_path, full_path, self.fetch_code)
+    }
+
+    fn after_merge(&self, full_path: String) -> BoxFuture<'static, Result<String, String>> {
+        let fetch_code = self.fetch_code;
+        Box::pin(async move {
+            // 存储信息，并激活过期删除
+            save_and_expiration_clear(full_path, fetch_code)
+                .await
+                .map_err(|err| err.to_string())?;
+            println!("file code: {}", fetch_code);
+            Ok(fetch_code.to_string())
+        })
+    }
 }
 
-#[post("/merge_chunks")]
-async fn file_chunks_merge(req: HttpRequest) -> Result<HttpResponse, Error> {
-    async fn handler(req: HttpRequest) -> Result<HttpResponse, Box<dyn std::error::Error>> {
-        let fetch_code = generate_fetch_code().await;
+impl UploadPolicy for TransferPolicy {
+    // 可选的 ownerKey 请求头, 创建会话时设置后, 中止上传需要提供相同的值
+    type Grant = Option<String>;
+    type Target = ShareTarget;
 
-        let full_path = file_chunks_merge_handler(
-            req,
-            // 转换路径，在后面加上提取码，防止重名覆盖
-            Some(Box::new(move | base_path, full_path | {
-                format!("{}{}{}", base_path, full_path, fetch_code)
-            })),
-        )
-        .await?;
+    const SERVICE: &'static str = SERVICE_NAME;
 
-        // 存储信息，并激活过期删除
-        save_and_expiration_clear(full_path, fetch_code).await?;
+    // 不需要鉴权
+    async fn authorize(&self, req: &HttpRequest) -> Result<Self::Grant, Box<dyn std::error::Error>> {
+        Ok(get_header(req, "ownerKey").map(String::from))
+    }
 
-        println!("file code: {}", fetch_code);
+    fn owner(&self, grant: &Self::Grant) -> Option<String> {
+        grant.clone()
+    }
 
-        // 响应
-        Ok(HttpResponse::Ok().body(format!("{}", fetch_code)))
+    async fn merge_target(&self, _grant: Self::Grant) -> Self::Target {
+        ShareTarget {
+            fetch_code: generate_fetch_code().await,
+        }
     }
-    handler(req).await.or_else(|err| Err(error::ErrorBadRequest(err)))
 }
 
 #[get("/fetch-file/{file_id}")]
@@ -192,7 +267,7 @@ async fn download(extract::Path(file_id): extract::Path<i32>) -> Result<NamedFil
         let mut filename: &str = "defaultName";
 
         if let Some(index) = &full_path.rfind("/") {
-            let i: usize = index.clone();
+            let i: usize = *index;
             // 截取 / 到倒数第7位， 最后6位是提取码
             filename = &full_path[i..(full_path.len() - 6)];
         };
@@ -202,14 +277,10 @@ async fn download(extract::Path(file_id): extract::Path<i32>) -> Result<NamedFil
         // 返回对应文件
         Ok(NamedFile::from_file(file, filename)?)
     }
-    handler(file_id).await.or_else(|err| Err(error::ErrorBadRequest(err)))
+    handler(file_id).await.map_err(error::ErrorBadRequest)
 }
 
 pub fn actix_configure(config: &mut web::ServiceConfig) {
-    config
-        .service(upload)
-        .service(upload_chunk)
-        .service(fetch_uploaded_chunks_hashes)
-        .service(file_chunks_merge)
-        .service(download);
+    config.service(upload).service(download);
+    configure_upload_routes(config, &TransferPolicy);
 }
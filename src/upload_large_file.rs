@@ -1,144 +1,95 @@
-use actix_web::{error, post, web, Error, HttpRequest, HttpResponse};
-use parking_lot::RwLock;
+use actix_web::{error, web, HttpRequest};
+use futures::future::BoxFuture;
 
-use crate::actix_utils::get_header;
+use crate::actix_utils::{get_header, get_required_header};
+use crate::archive_extract::extract_archive;
+use crate::jwt_verify::verify_token;
 
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use urlencoding::decode;
 
-pub struct Verify {
-  pub tokens: RwLock<Vec<String>>,
-}
+// chunk 会话所属服务
+pub(crate) const SERVICE_NAME: &str = "upload_large_file";
 
-use serde_json::Value;
-use base64::{ Engine as _, engine::general_purpose };
+// 大文件上传
+use crate::split_chunks_upload_operations_raw::{base_path, relative_path};
+use crate::upload_policy::{configure_upload_routes, MergeTarget, UploadPolicy};
 
-use std::str;
+// 所有接口都需要有效的 token, 文件保存在 token 中的用户目录下
+struct LargeFilePolicy;
 
-// 从 token 中获取 userDirectory 的方法
-fn get_user_directory(token: &str)-> Result<String, String> {
-  // 尝试截取为三个部分，验证有效性
-  let splits: Vec<&str> = token.split(".").collect();
-  // 长度不为3， 不是有效 jwt
-  if splits.len() != 3 {
-    return Err("invalid token".to_string());
-  }
-  // 截取 jwt 中间主体部分，并 base64 解码
-  let body_raw = match general_purpose::STANDARD_NO_PAD.decode(splits[1]) {
-    Ok(result)=> result,
-    Err(err)=> return Err(err.to_string()),
-  };
-  // 转换为 str
-  let body_json = match str::from_utf8(&body_raw) {
-    Ok(v) => v,
-    Err(err) => return Err(err.to_string()),
-  };
-  // 反序列化
-  let body: Value = match serde_json::from_str(body_json) {
-    Ok(v) => v,
-    Err(err) => return Err(err.to_string()),
-  };
-  // 尝试获取 userDirectory
-  match &body["data"]["userDirectory"] {
-    Value::String(user_directory)=> Ok(String::from(user_directory)),
-    _=> return Err("userDirectory is invalid".to_string()),
-  }
+// 一次合并的用户目录
+struct UserDirectoryTarget {
+  user_directory: String,
 }
 
-// 大文件上传
-use crate::actix_split_chunks_upload_handlers::{
-  // upload chunk handler
-  split_chunks_upload_handler,
-  // merge chunks handler
-  file_chunks_merge_handler,
-  get_uploaded_chunks_hashes,
-};
-
-use lazy_static::lazy_static;
-
-lazy_static! {
-  static ref VERIFY: Arc<Verify> = Arc::new(Verify {
-    tokens: RwLock::new(Vec::new()),
-  });
+// 用户目录, 保存和解压的路径都在其中
+fn user_root(base_path: &str, user_directory: &str) -> PathBuf {
+  Path::new(base_path).join(user_directory)
 }
 
-// 检测是否有效的token
-fn verify_token_valid(token: String) -> bool {
-  let tokens = VERIFY.tokens.read();
-  tokens.contains(&token)
+// 最终保存路径中用户目录之后的部分, 与请求中的 fullPath 对应, 按冲突策略改名后可能不同
+fn user_relative_path(user_directory: &str, file_path: &str) -> String {
+  let path = Path::new(file_path);
+  path.strip_prefix(user_root(base_path(), user_directory)).unwrap_or(path).to_string_lossy().into_owned()
 }
 
-#[post("/upload_chunk")]
-async fn upload_chunks(
-  req: HttpRequest,
-  payload: web::Payload,
-) -> Result<String, Error> {
-  async fn handler(req: HttpRequest, payload: web::Payload) -> Result<String, Box<dyn std::error::Error>> {
-    let token = get_header(&req, "token").ok_or_else(|| String::from("request header token is not found"))?;
-  
-    if verify_token_valid(String::from(token)) {
-      split_chunks_upload_handler(req, payload).await
-    } else {
-      Err("token is invalid".into())
-    }
+impl MergeTarget for UserDirectoryTarget {
+  // 转换路径加上用户目录路径
+  fn save_path(&self, base_path: &str, full_path: &str) -> String {
+    user_root(base_path, &self.user_directory).join(full_path).to_string_lossy().into_owned()
+  }
+
+  fn root_path(&self, base_path: &str) -> String {
+    user_root(base_path, &self.user_directory).to_string_lossy().into_owned()
   }
 
-  handler(req, payload).await.or_else(|err| Err(error::ErrorBadRequest(err)))
+  // 结果为最终保存路径
+  fn after_merge(&self, file_path: String) -> BoxFuture<'static, Result<String, String>> {
+    let full_path = user_relative_path(&self.user_directory, &file_path);
+    Box::pin(async move { Ok(full_path) })
+  }
 }
 
-#[post("/fetch_uploaded_chunks_hashes")]
-async fn fetch_uploaded_chunks_hashes(
-  req: HttpRequest,
-) -> Result<String, Error> {
-  async fn handler(req: HttpRequest) -> Result<String, Box<dyn std::error::Error>> {
-    let token = get_header(&req, "token").ok_or_else(|| String::from("request header token is not found"))?;
-    if verify_token_valid(String::from(token)) {
-      get_uploaded_chunks_hashes(req).await
-    } else {
-      Err("token is invalid".into())
-    }
+impl UploadPolicy for LargeFilePolicy {
+  // token 中的用户目录
+  type Grant = String;
+  type Target = UserDirectoryTarget;
+
+  const SERVICE: &'static str = SERVICE_NAME;
+
+  async fn authorize(&self, req: &HttpRequest) -> Result<Self::Grant, Box<dyn std::error::Error>> {
+    let token = get_header(req, "token").ok_or_else(|| String::from("request header token is not found"))?;
+    // 校验签名和有效期后取得用户目录
+    Ok(verify_token(token).await?)
+  }
+
+  // 会话的创建者为 token 中的用户目录, 只有同一用户可以中止上传
+  fn owner(&self, user_directory: &Self::Grant) -> Option<String> {
+    Some(user_directory.clone())
   }
 
-  handler(req).await.or_else(|err| Err(error::ErrorBadRequest(err)))
+  async fn merge_target(&self, user_directory: Self::Grant) -> Self::Target {
+    UserDirectoryTarget { user_directory }
+  }
 }
 
-#[post("/merge_chunks")]
-async fn file_chunks_merge(
-  req: HttpRequest,
-) -> Result<HttpResponse, Error> {
-  let token = match get_header(&req, "token") {
-    Some(token) => token,
-    None => return Err(error::ErrorBadRequest("request header token is not found")),
+// 解压用户目录中的压缩包, 请求头 archivePath 为压缩包路径, extractTo 为目标目录, 都相对于用户目录并经过编码
+// 响应解压出的文件列表
+async fn extract_archive_handler(req: HttpRequest) -> Result<String, Box<dyn std::error::Error>> {
+  let user_directory = LargeFilePolicy.authorize(&req).await?;
+  let user_path = |header_name: &str| -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = decode(get_required_header(&req, header_name)?)?;
+    Ok(user_root(base_path(), &user_directory).join(relative_path(Path::new(path.as_ref()))?))
   };
-
-  if verify_token_valid(String::from(token)) {
-
-    // 取得用户目录
-    let user_directory = match get_user_directory(token){
-      Ok(v)=> v,
-      Err(err) => return Err(error::ErrorBadRequest(err.to_string())),
-    };
-
-    file_chunks_merge_handler(
-      req,
-      // 转换路径加上用户目录路径
-      Some(Box::new(move | base_path, full_path | {
-        format!("{}{}{}", base_path, user_directory, full_path)
-      })),
-    ).await.map_or_else(|_| Err(error::ErrorBadRequest("failed to merge chunks")), |_| Ok(HttpResponse::Ok().body("true")))
-  } else {
-    Err(error::ErrorBadRequest("token is invalid"))
-  }
+  let (archive_path, target_dir) = (user_path("archivePath")?, user_path("extractTo")?);
+  let report = web::block(move || extract_archive(&archive_path, &target_dir)).await??;
+  Ok(serde_json::to_string(&report)?)
 }
 
 pub fn actix_configure(config: &mut web::ServiceConfig) {
-  config
-    .service(upload_chunks)
-    .service(fetch_uploaded_chunks_hashes)
-    .service(file_chunks_merge);
+  configure_upload_routes(config, &LargeFilePolicy);
+  config.route("/extract_archive", web::post().to(|req: HttpRequest| async move {
+    extract_archive_handler(req).await.map_err(error::ErrorBadRequest)
+  }));
 }
-
-// 在外部调用该方法更新 tokens
-pub fn update_tokens(new_tokens:Vec<String>) {
-  let mut tokens = VERIFY.tokens.write();
-  *tokens = new_tokens;
-}
\ No newline at end of file
@@ -0,0 +1,201 @@
+use std::future::Future;
+
+use actix_web::{error, http::Method, web, HttpRequest};
+
+use crate::actix_split_chunks_upload_handlers::{
+    abort_upload_session_handler,
+    create_instant_upload_challenge_handler,
+    create_upload_session_handler,
+    file_chunks_merge_handler,
+    file_chunks_merge_job_handler,
+    get_chunk_hash_algorithms,
+    get_merge_job_status_handler,
+    get_post_merge_status_handler,
+    get_upload_session_status_handler,
+    get_uploaded_chunks_hashes,
+    instant_upload_handler,
+    reuse_stored_chunks_handler,
+    split_chunks_batch_upload_handler,
+    split_chunks_upload_handler,
+};
+use crate::actix_ws_handlers::ws_upload_handler;
+use crate::actix_tus_handlers::{
+    tus_create_handler,
+    tus_delete_handler,
+    tus_head_handler,
+    tus_options_handler,
+    tus_patch_handler,
+};
+use crate::instant_upload::InstantUploadRequest;
+use crate::post_merge::PipelineTarget;
+use crate::split_chunks_upload_operations_raw::CreateUploadSession;
+
+pub use crate::split_chunks_upload_operations_raw::MergeTarget;
+
+// 上传服务的策略, 各服务通过它接入 chunk 上传的所有接口, 不需要重复编写处理函数
+pub trait UploadPolicy: Sync + 'static {
+    // 鉴权通过后得到的信息, 如 token 中的用户目录, WebSocket 连接中会多次使用
+    type Grant: Clone + 'static;
+    // 一次合并的目标, 决定保存路径和合并完成后的操作
+    type Target: MergeTarget + 'static;
+
+    // 服务名, 区分各服务的会话
+    const SERVICE: &'static str;
+
+    // 除 chunk_hash_algorithms 和 tus OPTIONS 外的请求都需要通过
+    fn authorize(&self, req: &HttpRequest) -> impl Future<Output = Result<Self::Grant, Box<dyn std::error::Error>>>;
+
+    // 会话创建者, 设置后只有创建者可以上传, 查询, 合并和中止
+    fn owner(&self, _grant: &Self::Grant) -> Option<String> {
+        None
+    }
+
+    // 单个文件的最大尺寸, 创建会话和合并时检查
+    fn max_file_size(&self, _grant: &Self::Grant) -> Option<u64> {
+        None
+    }
+
+    // 为一次合并准备目标
+    fn merge_target(&self, grant: Self::Grant) -> impl Future<Output = Self::Target>;
+}
+
+// 合并目标完成后启动服务的处理步骤, 进度通过 key 查询
+pub(crate) async fn prepare_merge_target<P: UploadPolicy>(
+    policy: &P,
+    grant: P::Grant,
+    key: String,
+) -> PipelineTarget<P::Target> {
+    let owner = policy.owner(&grant);
+    PipelineTarget::new(P::SERVICE, key, owner, policy.merge_target(grant).await)
+}
+
+// 注册 chunk 上传, 秒传和 tus 的所有接口
+pub fn configure_upload_routes<P: UploadPolicy>(config: &mut web::ServiceConfig, policy: &'static P) {
+    config
+        .route(
+            "/create_upload_session",
+            web::post().to(move |req: HttpRequest, request: web::Json<CreateUploadSession>| async move {
+                create_upload_session_handler(policy, &req, request.into_inner())
+                    .await
+                    .map_err(error::ErrorBadRequest)
+            }),
+        )
+        .route(
+            "/upload_chunk",
+            web::post().to(move |req: HttpRequest, payload: web::Payload| async move {
+                split_chunks_upload_handler(policy, &req, payload).await.map_err(error::ErrorBadRequest)
+            }),
+        )
+        // 一个请求上传多个 chunk, 响应每个 chunk 的结果
+        .route(
+            "/upload_chunks",
+            web::post().to(move |req: HttpRequest, payload: web::Payload| async move {
+                split_chunks_batch_upload_handler(policy, &req, payload).await.map_err(error::ErrorBadRequest)
+            }),
+        )
+        // 请求体为按顺序排列的所有 chunk hash
+        .route(
+            "/reuse_stored_chunks",
+            web::post().to(move |req: HttpRequest, chunks_hash: web::Json<Vec<String>>| async move {
+                reuse_stored_chunks_handler(policy, &req, chunks_hash.into_inner())
+                    .await
+                    .map_err(error::ErrorBadRequest)
+            }),
+        )
+        // 中止上传, 立即删除已上传的 chunk
+        .route(
+            "/abort_upload",
+            web::post().to(move |req: HttpRequest| async move {
+                abort_upload_session_handler(policy, &req).await.map_err(error::ErrorBadRequest)
+            }),
+        )
+        .route(
+            "/fetch_uploaded_chunks_hashes",
+            web::post().to(move |req: HttpRequest| async move {
+                get_uploaded_chunks_hashes(policy, &req).await.map_err(error::ErrorBadRequest)
+            }),
+        )
+        // 响应已上传的 chunk hash 列表和会话的过期时间
+        .route(
+            "/upload_session_status",
+            web::post().to(move |req: HttpRequest| async move {
+                get_upload_session_status_handler(policy, &req).await.map_err(error::ErrorBadRequest)
+            }),
+        )
+        .route(
+            "/chunk_hash_algorithms",
+            web::get().to(|| async { get_chunk_hash_algorithms().map_err(error::ErrorInternalServerError) }),
+        )
+        // 响应合并目标的结果
+        .route(
+            "/merge_chunks",
+            web::post().to(move |req: HttpRequest| async move {
+                file_chunks_merge_handler(policy, &req).await.map_err(error::ErrorBadRequest)
+            }),
+        )
+        // 后台合并, 响应任务 id, 任务成功后的结果为合并目标的结果
+        .route(
+            "/merge_chunks_async",
+            web::post().to(move |req: HttpRequest| async move {
+                file_chunks_merge_job_handler(policy, &req).await.map_err(error::ErrorBadRequest)
+            }),
+        )
+        .route(
+            "/merge_status",
+            web::post().to(move |req: HttpRequest| async move {
+                get_merge_job_status_handler(policy, &req).await.map_err(error::ErrorBadRequest)
+            }),
+        )
+        // 合并后处理步骤的进度, identify 为合并时的 identify, 秒传为 challengeId, tus 为上传 id
+        .route(
+            "/post_merge_status",
+            web::post().to(move |req: HttpRequest| async move {
+                get_post_merge_status_handler(policy, &req).await.map_err(error::ErrorBadRequest)
+            }),
+        )
+        .route(
+            "/instant_upload_challenge",
+            web::post().to(move |req: HttpRequest, request: web::Json<InstantUploadRequest>| async move {
+                create_instant_upload_challenge_handler(policy, &req, request.into_inner())
+                    .await
+                    .map_err(error::ErrorBadRequest)
+            }),
+        )
+        // 回答 challenge 后直接保存文件, 响应合并目标的结果
+        .route(
+            "/instant_upload",
+            web::post().to(move |req: HttpRequest, answer: web::Bytes| async move {
+                instant_upload_handler(policy, &req, answer).await.map_err(error::ErrorBadRequest)
+            }),
+        )
+        // WebSocket 上传, 在一个连接中发送多个 chunk
+        .route(
+            "/upload_ws",
+            web::get().to(move |req: HttpRequest, payload: web::Payload| async move {
+                ws_upload_handler(policy, &req, payload).await
+            }),
+        )
+        // tus 协议上传, 完成后 Upload-Result 响应头为编码后的合并目标结果
+        .service(
+            web::resource("/tus")
+                .route(web::method(Method::OPTIONS).to(|| async { tus_options_handler() }))
+                .route(web::post().to(move |req: HttpRequest| async move {
+                    tus_create_handler(policy, &req).await
+                })),
+        )
+        .service(
+            web::resource("/tus/{upload_id}")
+                .route(web::head().to(move |req: HttpRequest, upload_id: web::Path<String>| async move {
+                    tus_head_handler(policy, &req, &upload_id).await
+                }))
+                .route(web::patch().to(
+                    move |req: HttpRequest, upload_id: web::Path<String>, payload: web::Payload| async move {
+                        tus_patch_handler(policy, &req, &upload_id, payload).await
+                    },
+                ))
+                .route(web::delete().to(move |req: HttpRequest, upload_id: web::Path<String>| async move {
+                    tus_delete_handler(policy, &req, &upload_id).await
+                })),
+        );
+}
+
@@ -0,0 +1,167 @@
+#![cfg(feature = "upload-large-file")]
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+
+use actix_web::{http::StatusCode, test};
+use flate2::{write::GzEncoder, Compression};
+use serde_json::{json, Value};
+use zip::{write::FileOptions, ZipWriter};
+
+use web_server::archive_extract::{set_extract_limits, ExtractLimits};
+use web_server::upload_large_file;
+
+use common::{init_app, make_token, setup_jwt, upload_chunk_request};
+
+// 本测试进程中解压后最多 1024 字节, 5 项
+fn setup_tokens() -> String {
+    setup_jwt();
+    set_extract_limits(ExtractLimits {
+        max_total_size: 1024,
+        max_entries: 5,
+    });
+    make_token("dave/")
+}
+
+fn zip_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, content) in files {
+        if name.ends_with('/') {
+            writer.add_directory(*name, FileOptions::default()).unwrap();
+        } else {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+fn extract_request(token: &str, archive_path: &str, extract_to: &str) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/extract_archive")
+        .insert_header(("token", token))
+        .insert_header(("archivePath", archive_path))
+        .insert_header(("extractTo", extract_to))
+}
+
+// 不安全的压缩包放在单独的用户目录中, 不受其他测试影响
+fn user_directory_entries() -> Vec<String> {
+    let mut entries: Vec<String> = fs::read_dir("./files/erin")
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    entries.sort();
+    entries
+}
+
+#[actix_web::test]
+async fn merged_zip_is_extracted_with_report() {
+    let token = setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+
+    // 通过分片上传得到压缩包
+    let archive = zip_archive(&[
+        ("project/", b""),
+        ("project/README.md", b"# project"),
+        ("project/src/main.rs", b"fn main() {}"),
+    ]);
+    let req = upload_chunk_request("archive-upload", &archive, 0, 1)
+        .insert_header(("token", token.as_str()))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("token", token.as_str()))
+        .insert_header(("identify", "archive-upload"))
+        .insert_header(("fullPath", "uploads/project.zip"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "uploads/project.zip");
+
+    let req = extract_request(&token, "uploads/project.zip", "unpacked%20project").to_request();
+    let report: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        report,
+        json!({
+            "entries": [
+                { "path": "project", "size": 0, "directory": true },
+                { "path": "project/README.md", "size": 9, "directory": false },
+                { "path": "project/src/main.rs", "size": 12, "directory": false },
+            ],
+            "totalSize": 21,
+        })
+    );
+    assert_eq!(fs::read_to_string("./files/dave/unpacked project/project/src/main.rs").unwrap(), "fn main() {}");
+
+    // 目标目录已存在时不覆盖
+    let req = extract_request(&token, "uploads/project.zip", "unpacked%20project").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn tar_gz_is_extracted() {
+    let token = setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let mut header = tar::Header::new_gnu();
+    header.set_size(5);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, "docs/notes.txt", &b"notes"[..]).unwrap();
+    let archive = builder.into_inner().unwrap().finish().unwrap();
+    fs::create_dir_all("./files/dave").unwrap();
+    fs::write("./files/dave/docs.tar.gz", archive).unwrap();
+
+    let req = extract_request(&token, "docs.tar.gz", "docs").to_request();
+    let report: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(report["entries"], json!([{ "path": "docs/notes.txt", "size": 5, "directory": false }]));
+    assert_eq!(fs::read_to_string("./files/dave/docs/docs/notes.txt").unwrap(), "notes");
+}
+
+#[actix_web::test]
+async fn unsafe_archives_leave_nothing_behind() {
+    setup_tokens();
+    let token = make_token("erin/");
+    let app = init_app!(upload_large_file::actix_configure);
+    fs::create_dir_all("./files/erin").unwrap();
+
+    // 解压路径离开目标目录
+    fs::write("./files/erin/slip.zip", zip_archive(&[("ok.txt", b"ok"), ("../../escaped.txt", b"escaped")])).unwrap();
+    // 解压后超过总大小限制
+    fs::write("./files/erin/bomb.zip", zip_archive(&[("zeros", &[0; 2048])])).unwrap();
+    // 项数超过限制
+    let many: Vec<(String, &[u8])> = (0..6).map(|index| (format!("{}.txt", index), &b"x"[..])).collect();
+    let many: Vec<(&str, &[u8])> = many.iter().map(|(name, content)| (name.as_str(), *content)).collect();
+    fs::write("./files/erin/many.zip", zip_archive(&many)).unwrap();
+    // 符号链接
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    builder.append_link(&mut header, "link", "/etc/passwd").unwrap();
+    fs::write("./files/erin/link.tar", builder.into_inner().unwrap()).unwrap();
+    let archives = user_directory_entries();
+
+    for archive in ["slip.zip", "bomb.zip", "many.zip", "link.tar"] {
+        let req = extract_request(&token, archive, "unsafe").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST, "{}", archive);
+    }
+    assert_eq!(user_directory_entries(), archives);
+    assert!(fs::metadata("./files/escaped.txt").is_err());
+
+    // 请求中的路径同样不能离开用户目录
+    let req = extract_request(&token, "slip.zip", "../unsafe").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    let req = extract_request(&token, "../erin/slip.zip", "unsafe").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    // 没有 token 时不解压
+    let req = test::TestRequest::post()
+        .uri("/extract_archive")
+        .insert_header(("archivePath", "bomb.zip"))
+        .insert_header(("extractTo", "unsafe"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
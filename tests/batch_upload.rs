@@ -0,0 +1,101 @@
+#![cfg(feature = "transfer")]
+
+mod common;
+
+use actix_web::{http::StatusCode, test, App};
+use serde_json::{json, Value};
+
+use web_server::{set_max_chunk_size, transfer_serve};
+
+use common::{md5_hex, setup};
+
+// 本测试进程中单个 chunk 最大 16 字节
+const MAX_CHUNK_SIZE: usize = 16;
+
+// 4 字节大端序号, 1 字节 hash 长度, hash, 4 字节大端内容长度, 内容
+fn batch_entry(body: &mut Vec<u8>, index: u32, hash: &str, content: &[u8]) {
+    body.extend_from_slice(&index.to_be_bytes());
+    body.push(hash.len() as u8);
+    body.extend_from_slice(hash.as_bytes());
+    body.extend_from_slice(&(content.len() as u32).to_be_bytes());
+    body.extend_from_slice(content);
+}
+
+fn batch_request(identify: &str, chunks_number: usize, body: Vec<u8>) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/upload_chunks")
+        .insert_header(("identify", identify))
+        .insert_header(("chunksNumber", chunks_number.to_string()))
+        .set_payload(body)
+}
+
+#[actix_web::test]
+async fn each_chunk_in_batch_is_verified_individually() {
+    setup();
+    set_max_chunk_size(MAX_CHUNK_SIZE);
+    let app = test::init_service(App::new().configure(transfer_serve::actix_configure)).await;
+
+    let chunks: [&[u8]; 4] = [b"batch ", b"chunks ", b"in one ", b"request"];
+    let oversized = vec![b'x'; MAX_CHUNK_SIZE + 1];
+    let mut body = vec![];
+    batch_entry(&mut body, 0, &md5_hex(chunks[0]).to_uppercase(), chunks[0]);
+    batch_entry(&mut body, 1, &md5_hex(b"other"), chunks[1]);
+    batch_entry(&mut body, 2, &md5_hex(&oversized), &oversized);
+    batch_entry(&mut body, 3, &md5_hex(chunks[3]), chunks[3]);
+    let req = batch_request("batch", 4, body).to_request();
+    let results: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(results[0], json!({ "chunkIndex": 0, "chunkHash": md5_hex(chunks[0]), "error": null }));
+    assert_eq!(results[1]["chunkIndex"], 1);
+    assert_eq!(results[1]["error"], "chunk hash not match");
+    assert_eq!(results[2]["error"], format!("chunk is larger than {} bytes", MAX_CHUNK_SIZE));
+    assert_eq!(results[3], json!({ "chunkIndex": 3, "chunkHash": md5_hex(chunks[3]), "error": null }));
+
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", "batch"))
+        .to_request();
+    let hashes: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(hashes, vec![md5_hex(chunks[0]), "empty".into(), "empty".into(), md5_hex(chunks[3])]);
+
+    // 重新上传失败的 chunk 后合并
+    let mut body = vec![];
+    batch_entry(&mut body, 1, &md5_hex(chunks[1]), chunks[1]);
+    batch_entry(&mut body, 2, &md5_hex(chunks[2]), chunks[2]);
+    let req = batch_request("batch", 4, body).to_request();
+    let results: Value = test::call_and_read_body_json(&app, req).await;
+    assert!(results.as_array().unwrap().iter().all(|result| result["error"].is_null()));
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", "batch"))
+        .insert_header(("fullPath", "batch.txt"))
+        .to_request();
+    let fetch_code = test::call_and_read_body(&app, req).await;
+    let fetch_code = std::str::from_utf8(&fetch_code).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(format!("./files/batch.txt{}", fetch_code)).unwrap(),
+        "batch chunks in one request"
+    );
+}
+
+#[actix_web::test]
+async fn truncated_batch_is_rejected() {
+    setup();
+    set_max_chunk_size(MAX_CHUNK_SIZE);
+    let app = test::init_service(App::new().configure(transfer_serve::actix_configure)).await;
+
+    let mut body = vec![];
+    batch_entry(&mut body, 0, &md5_hex(b"complete"), b"complete");
+    batch_entry(&mut body, 1, &md5_hex(b"truncated"), b"truncated");
+    body.truncate(body.len() - 3);
+    let req = batch_request("truncated-batch", 2, body).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    // 截断前的 chunk 已经保存
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", "truncated-batch"))
+        .to_request();
+    let hashes: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(hashes, vec![md5_hex(b"complete"), "empty".into()]);
+}
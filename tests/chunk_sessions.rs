@@ -0,0 +1,223 @@
+#![cfg(feature = "transfer")]
+
+mod common;
+
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{test, App};
+use serde_json::{json, Value};
+
+use web_server::{restore_chunks_sessions, transfer_serve};
+
+use common::{md5_chunk_file, md5_hex, setup};
+
+// 与服务端的存储布局保持一致
+fn session_file(key: &str) -> String {
+    format!("./chunks/{}.session.json", md5_hex(key.as_bytes()))
+}
+
+fn session_log_file(key: &str) -> String {
+    format!("./chunks/{}.session.log", md5_hex(key.as_bytes()))
+}
+
+// 旧版本按会话保存的 chunk
+fn legacy_chunk_file(chunk_hash: &str, key: &str) -> String {
+    format!("./chunks/{}{}.chunk", chunk_hash, md5_hex(key.as_bytes()))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[actix_web::test]
+async fn uploaded_chunks_are_persisted() {
+    setup();
+    let app = test::init_service(App::new().configure(transfer_serve::actix_configure)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/upload_chunk")
+        .insert_header(("identify", "persisted"))
+        .insert_header(("chunkHash", md5_hex(b"persist me")))
+        .insert_header(("chunkIndex", "1"))
+        .insert_header(("chunksNumber", "2"))
+        .set_payload("persist me")
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let session: Value = serde_json::from_slice(&fs::read(session_file("transfer/persisted")).unwrap()).unwrap();
+    assert_eq!(session["identify"], "transfer/persisted");
+    // 只保存已上传的 chunk
+    assert_eq!(session["chunksHash"], json!({ "length": 2, "hashes": { "1": md5_hex(b"persist me") } }));
+    assert!(session["expiresAt"].as_u64().unwrap() > unix_now());
+
+    // 之后的 chunk 追加到记录中, 不重写会话文件
+    let req = test::TestRequest::post()
+        .uri("/upload_chunk")
+        .insert_header(("identify", "persisted"))
+        .insert_header(("chunkHash", md5_hex(b"then me")))
+        .insert_header(("chunkIndex", "0"))
+        .insert_header(("chunksNumber", "2"))
+        .set_payload("then me")
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    let session: Value = serde_json::from_slice(&fs::read(session_file("transfer/persisted")).unwrap()).unwrap();
+    assert_eq!(session["chunksHash"]["hashes"].as_object().unwrap().len(), 1);
+    let log: Value = serde_json::from_slice(&fs::read(session_log_file("transfer/persisted")).unwrap()).unwrap();
+    assert_eq!(log["index"], 0);
+    assert_eq!(log["hash"], md5_hex(b"then me"));
+    assert_eq!(log["generation"], session["logGeneration"]);
+}
+
+#[actix_web::test]
+async fn logged_chunks_are_replayed_on_restore() {
+    setup();
+    let key = "transfer/logged";
+    let (snapshot, logged, stale) = (md5_hex(b"in snapshot"), md5_hex(b"in log"), md5_hex(b"stale log"));
+    for (chunk_hash, content) in [(&snapshot, "in snapshot"), (&logged, "in log"), (&stale, "stale log")] {
+        fs::write(md5_chunk_file(chunk_hash, key), content).unwrap();
+    }
+    fs::write(
+        session_file(key),
+        json!({
+            "identify": key,
+            "chunksHash": [snapshot, "empty", "empty", "empty"],
+            "expiresAt": unix_now() + 3600,
+            "logGeneration": 2,
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let line = |generation: u64, index: usize, hash: &str| {
+        json!({ "generation": generation, "index": index, "hash": hash, "expiresAt": unix_now() + 3600 }).to_string()
+    };
+    // 重写前留下的记录和写到一半的行被忽略
+    let log = format!("{}\n{}\n{}", line(1, 2, &stale), line(2, 1, &logged), &line(2, 3, &logged)[..20]);
+    fs::write(session_log_file(key), log).unwrap();
+
+    assert!(restore_chunks_sessions().await.unwrap() >= 1);
+
+    let app = test::init_service(App::new().configure(transfer_serve::actix_configure)).await;
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", "logged"))
+        .to_request();
+    let hashes: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(hashes, vec![snapshot, logged, String::from("empty"), String::from("empty")]);
+    // 恢复后记录合并到会话文件中
+    assert!(fs::metadata(session_log_file(key)).is_err());
+}
+
+#[actix_web::test]
+async fn sessions_are_restored_and_chunks_reverified() {
+    setup();
+    let key = "transfer/restored";
+    let (good, corrupted) = (md5_hex(b"good chunk"), md5_hex(b"original chunk"));
+    fs::write(legacy_chunk_file(&good, key), "good chunk").unwrap();
+    fs::write(legacy_chunk_file(&corrupted, key), "tampered chunk").unwrap();
+    // 之前版本在会话之间共享的 md5 chunk
+    let shared = md5_hex(b"shared chunk");
+    fs::write(format!("./chunks/md5-{}.chunk", shared), "shared chunk").unwrap();
+    fs::write(
+        session_file(key),
+        json!({
+            "identify": key,
+            "chunksHash": [good, corrupted, shared, "empty"],
+            "expiresAt": unix_now() + 3600,
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    // 已过期的会话在恢复时被清除
+    let expired_key = "transfer/expired";
+    let expired_hash = md5_hex(b"stale");
+    fs::write(legacy_chunk_file(&expired_hash, expired_key), "stale").unwrap();
+    fs::write(
+        session_file(expired_key),
+        json!({ "identify": expired_key, "chunksHash": [expired_hash], "expiresAt": 1 }).to_string(),
+    )
+    .unwrap();
+
+    assert!(restore_chunks_sessions().await.unwrap() >= 1);
+
+    let app = test::init_service(App::new().configure(transfer_serve::actix_configure)).await;
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", "restored"))
+        .to_request();
+    let hashes: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        hashes,
+        vec![good.clone(), String::from("empty"), shared.clone(), String::from("empty")]
+    );
+    assert!(fs::metadata(legacy_chunk_file(&corrupted, key)).is_err());
+    // 旧的 chunk 移入存储
+    assert!(fs::metadata(legacy_chunk_file(&good, key)).is_err());
+    assert_eq!(fs::read(md5_chunk_file(&good, key)).unwrap(), b"good chunk");
+    assert!(fs::metadata(md5_chunk_file(&corrupted, key)).is_err());
+    assert_eq!(fs::read(md5_chunk_file(&shared, key)).unwrap(), b"shared chunk");
+
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", "expired"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "[]");
+    assert!(fs::metadata(session_file(expired_key)).is_err());
+    assert!(fs::metadata(legacy_chunk_file(&expired_hash, expired_key)).is_err());
+}
+
+#[actix_web::test]
+async fn preallocated_sessions_are_reverified_on_restore() {
+    setup();
+    let key = "transfer/restored-preallocated";
+    let (good, overwritten) = (md5_hex(b"good"), md5_hex(b"orig"));
+    fs::write(format!("./chunks/{}.assembling", md5_hex(key.as_bytes())), "goodXXXX\0\0").unwrap();
+    fs::write(
+        session_file(key),
+        json!({
+            "identify": key,
+            "chunksHash": [good, overwritten, "empty"],
+            "expiresAt": unix_now() + 3600,
+            "layout": { "fileName": "restored.txt", "fileSize": 10, "chunkSize": 4, "fileHash": null },
+            "assembly": "preallocated",
+            "written": [3],
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    assert!(restore_chunks_sessions().await.unwrap() >= 1);
+
+    let app = test::init_service(App::new().configure(transfer_serve::actix_configure)).await;
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", "restored-preallocated"))
+        .to_request();
+    let hashes: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(hashes, vec![good, String::from("empty"), String::from("empty")]);
+}
+
+#[actix_web::test]
+async fn oversized_session_file_is_not_restored() {
+    setup();
+    let key = "transfer/oversized";
+    fs::write(
+        session_file(key),
+        json!({
+            "identify": key,
+            "chunksHash": { "length": 1_000_000_000_000u64, "hashes": {} },
+            "expiresAt": unix_now() + 3600,
+        })
+        .to_string(),
+    )
+    .unwrap();
+    restore_chunks_sessions().await.unwrap();
+
+    let app = test::init_service(App::new().configure(transfer_serve::actix_configure)).await;
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", "oversized"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "[]");
+}
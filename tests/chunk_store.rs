@@ -0,0 +1,180 @@
+#![cfg(feature = "transfer")]
+
+mod common;
+
+use std::fs;
+
+use actix_web::{http::StatusCode, test};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use web_server::transfer_serve;
+
+use common::{init_app, md5_chunk_file, md5_hex, setup, upload_chunk_request};
+
+fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+// 只有 sha256 和 blake3 的 chunk 在会话之间共享
+fn stored_chunk_file(content: &[u8]) -> String {
+    format!("./chunks/sha256-{}.chunk", sha256_hex(content))
+}
+
+fn sha256_chunk_request(identify: &str, content: &[u8], index: usize, number: usize) -> test::TestRequest {
+    upload_chunk_request(identify, content, index, number)
+        .insert_header(("hashAlgorithm", "sha256"))
+        .insert_header(("chunkHash", sha256_hex(content)))
+}
+
+fn merge_request(identify: &str, full_path: &str) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", full_path))
+}
+
+fn reuse_request(identify: &str, chunks: &[&[u8]]) -> test::TestRequest {
+    let chunks_hash: Vec<String> = chunks.iter().map(|chunk| sha256_hex(chunk)).collect();
+    test::TestRequest::post()
+        .uri("/reuse_stored_chunks")
+        .insert_header(("identify", identify))
+        .insert_header(("chunksNumber", chunks.len().to_string()))
+        .insert_header(("hashAlgorithm", "sha256"))
+        .set_json(json!(chunks_hash))
+}
+
+#[actix_web::test]
+async fn identical_chunks_are_stored_once_and_reused() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let (shared, first_tail, second_tail) = (&b"shared chunk "[..], &b"first"[..], &b"second"[..]);
+
+    for (index, chunk) in [shared, first_tail].iter().enumerate() {
+        let req = sha256_chunk_request("store-first", chunk, index, 2)
+            .insert_header(("ownerKey", "alice"))
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    }
+    assert_eq!(fs::read(stored_chunk_file(shared)).unwrap(), shared);
+
+    // 同一创建者的第二个会话只需要上传服务端没有的 chunk
+    let req = reuse_request("store-second", &[shared, second_tail])
+        .insert_header(("ownerKey", "alice"))
+        .to_request();
+    let hashes: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(hashes, vec![sha256_hex(shared), String::from("empty")]);
+    let req = sha256_chunk_request("store-second", second_tail, 1, 2)
+        .insert_header(("ownerKey", "alice"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = merge_request("store-second", "second.txt")
+        .insert_header(("ownerKey", "alice"))
+        .to_request();
+    let fetch_code = test::call_and_read_body(&app, req).await;
+    let req = test::TestRequest::get()
+        .uri(&format!("/fetch-file/{}", String::from_utf8(fetch_code.to_vec()).unwrap()))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "shared chunk second");
+    // 仍被第一个会话引用
+    assert!(fs::metadata(stored_chunk_file(shared)).is_ok());
+    assert!(fs::metadata(stored_chunk_file(second_tail)).is_err());
+
+    let req = merge_request("store-first", "first.txt")
+        .insert_header(("ownerKey", "alice"))
+        .to_request();
+    test::call_and_read_body(&app, req).await;
+    assert!(fs::metadata(stored_chunk_file(shared)).is_err());
+    assert!(fs::metadata(stored_chunk_file(first_tail)).is_err());
+}
+
+#[actix_web::test]
+async fn chunks_of_other_owners_are_not_reused() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let private = &b"private chunk"[..];
+    let req = sha256_chunk_request("store-private", private, 0, 1)
+        .insert_header(("ownerKey", "alice"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    // 不知道内容的其他创建者不能引用, 也看不出 chunk 是否已存储
+    let req = reuse_request("store-stolen", &[private])
+        .insert_header(("ownerKey", "mallory"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, r#"["empty"]"#);
+
+    // 匿名会话不能引用
+    let req = reuse_request("store-anonymous", &[private]).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+}
+
+// 上传大文件服务的创建者为用户目录, transfer 客户端可以自行设置相同的 ownerKey
+#[cfg(feature = "upload-large-file")]
+#[actix_web::test]
+async fn chunks_of_other_services_are_not_reused() {
+    common::setup_jwt();
+    let app = test::init_service(
+        actix_web::App::new()
+            .service(actix_web::web::scope("/large").configure(web_server::upload_large_file::actix_configure))
+            .configure(transfer_serve::actix_configure),
+    )
+    .await;
+    let private = &b"private large file chunk"[..];
+    let req = sha256_chunk_request("store-large", private, 0, 1)
+        .uri("/large/upload_chunk")
+        .insert_header(("token", common::make_token("alice/")))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = reuse_request("store-impersonated", &[private])
+        .insert_header(("ownerKey", "alice/"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, r#"["empty"]"#);
+}
+
+#[actix_web::test]
+async fn md5_chunks_are_stored_per_session() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let content = b"md5 chunk";
+
+    // 相同 md5 的 chunk 各自保存, 不会用已存储的内容代替新上传的内容
+    for identify in ["store-md5-first", "store-md5-second"] {
+        let req = upload_chunk_request(identify, content, 0, 1).to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "true");
+        let chunk_file = md5_chunk_file(&md5_hex(content), &format!("transfer/{}", identify));
+        assert_eq!(fs::read(chunk_file).unwrap(), content);
+    }
+    assert!(fs::metadata(format!("./chunks/md5-{}.chunk", md5_hex(content))).is_err());
+}
+
+#[actix_web::test]
+async fn reuse_requires_deduplicable_algorithm_and_full_list() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    for (algorithm, chunk_hash) in [("crc32c", String::from("e3069283")), ("md5", md5_hex(b"md5 chunk"))] {
+        let req = test::TestRequest::post()
+            .uri("/reuse_stored_chunks")
+            .insert_header(("identify", "store-weak-hash"))
+            .insert_header(("chunksNumber", "1"))
+            .insert_header(("hashAlgorithm", algorithm))
+            .insert_header(("ownerKey", "alice"))
+            .set_json(json!([chunk_hash]))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST, "{}", algorithm);
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/reuse_stored_chunks")
+        .insert_header(("identify", "store-short"))
+        .insert_header(("chunksNumber", "2"))
+        .insert_header(("hashAlgorithm", "sha256"))
+        .insert_header(("ownerKey", "alice"))
+        .set_json(json!([sha256_hex(b"only one")]))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
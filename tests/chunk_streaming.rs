@@ -0,0 +1,55 @@
+#![cfg(feature = "transfer")]
+
+mod common;
+
+use std::fs;
+
+use actix_web::{http::StatusCode, test};
+
+use web_server::{set_max_chunk_size, transfer_serve};
+
+use common::{init_app, md5_chunk_file, md5_hex, setup, upload_chunk_request};
+
+// 本测试进程中单个 chunk 最大 16 字节
+const MAX_CHUNK_SIZE: usize = 16;
+
+// chunk 按内容保存, 包括未完成的临时文件
+fn chunk_files_of(chunk_hash: &str) -> Vec<String> {
+    fs::read_dir("./chunks")
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.contains(chunk_hash))
+        .collect()
+}
+
+#[actix_web::test]
+async fn oversized_chunk_is_rejected_without_leftovers() {
+    setup();
+    set_max_chunk_size(MAX_CHUNK_SIZE);
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let content = vec![b'x'; MAX_CHUNK_SIZE + 1];
+    let req = upload_chunk_request("oversized", &content, 0, 1).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    assert!(chunk_files_of(&md5_hex(&content)).is_empty());
+
+    let content = vec![b'x'; MAX_CHUNK_SIZE];
+    let req = upload_chunk_request("oversized", &content, 0, 1).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    let chunk_file = md5_chunk_file(&md5_hex(&content), "transfer/oversized");
+    assert_eq!(chunk_files_of(&md5_hex(&content)), vec![chunk_file.trim_start_matches("./chunks/").to_string()]);
+}
+
+#[actix_web::test]
+async fn mismatched_chunk_is_not_stored() {
+    setup();
+    set_max_chunk_size(MAX_CHUNK_SIZE);
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = upload_chunk_request("mismatched", b"content", 0, 1)
+        .insert_header(("chunkHash", md5_hex(b"other")))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    assert!(chunk_files_of(&md5_hex(b"content")).is_empty());
+    assert!(chunk_files_of(&md5_hex(b"other")).is_empty());
+}
@@ -0,0 +1,52 @@
+#![cfg(feature = "cloud-text")]
+
+mod common;
+
+use actix_web::{http::StatusCode, test};
+
+use web_server::cloud_text_serve;
+
+use common::{init_app, setup};
+
+#[actix_web::test]
+async fn write_then_read() {
+    setup();
+    let app = init_app!(cloud_text_serve::actix_configure);
+
+    let req = test::TestRequest::post()
+        .uri("/cloud_text/add/note-1")
+        .set_payload("some cloud text")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let req = test::TestRequest::get().uri("/cloud_text/get/note-1").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "some cloud text");
+}
+
+#[actix_web::test]
+async fn write_overwrites_previous_text() {
+    setup();
+    let app = init_app!(cloud_text_serve::actix_configure);
+
+    for text in ["a rather long first version", "short"] {
+        let req = test::TestRequest::post()
+            .uri("/cloud_text/add/note-2")
+            .set_payload(text)
+            .to_request();
+        test::call_service(&app, req).await;
+    }
+
+    let req = test::TestRequest::get().uri("/cloud_text/get/note-2").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "short");
+}
+
+#[actix_web::test]
+async fn read_missing_text_fails() {
+    setup();
+    let app = init_app!(cloud_text_serve::actix_configure);
+
+    let req = test::TestRequest::get().uri("/cloud_text/get/never-written").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
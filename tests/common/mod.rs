@@ -0,0 +1,115 @@
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+
+use actix_web::test::TestRequest;
+use lazy_static::lazy_static;
+use tempfile::TempDir;
+use tokio::time::{sleep, Duration};
+
+lazy_static! {
+    // 所有服务都使用相对于工作目录的 ./files/ ./chunks/ ./cloud_text/
+    // 因此每个测试进程切换到一个独立的临时目录中运行
+    static ref WORK_DIR: TempDir = {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        for sub_dir in ["files", "chunks", "cloud_text"] {
+            fs::create_dir_all(dir.path().join(sub_dir)).expect("failed to create sub dir");
+        }
+        env::set_current_dir(dir.path()).expect("failed to change working dir");
+        dir
+    };
+}
+
+pub fn setup() {
+    lazy_static::initialize(&WORK_DIR);
+}
+
+pub fn md5_hex(data: &[u8]) -> String {
+    format!("{:x}", md5::compute(data))
+}
+
+// md5 chunk 不在会话之间共享, 存储中按会话 key (服务名/identify) 区分
+pub fn md5_chunk_file(chunk_hash: &str, session_key: &str) -> String {
+    format!("./chunks/md5-{}-{}.chunk", chunk_hash, md5_hex(session_key.as_bytes()))
+}
+
+// 用服务的配置函数创建测试应用
+#[allow(unused_macros)]
+macro_rules! init_app {
+    ($configure:expr) => {
+        actix_web::test::init_service(actix_web::App::new().configure($configure)).await
+    };
+}
+#[allow(unused_imports)]
+pub(crate) use init_app;
+
+// 上传一个 chunk 的请求, chunk hash 为 md5
+pub fn upload_chunk_request(identify: &str, content: &[u8], index: usize, number: usize) -> TestRequest {
+    TestRequest::post()
+        .uri("/upload_chunk")
+        .insert_header(("identify", identify))
+        .insert_header(("chunkHash", md5_hex(content)))
+        .insert_header(("chunkIndex", index.to_string()))
+        .insert_header(("chunksNumber", number.to_string()))
+        .set_payload(content.to_vec())
+}
+
+// 让刚创建的后台任务先运行起来(注册各自的定时器)
+pub async fn settle() {
+    for _ in 0..10 {
+        tokio::task::yield_now().await;
+    }
+}
+
+// 轮询直到条件满足，用于等待后台清理任务
+pub async fn wait_until<F, Fut>(mut condition: F) -> bool
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    for _ in 0..100 {
+        if condition().await {
+            return true;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    false
+}
+
+// 测试中签发 token 使用的 HS256 密钥
+#[cfg(feature = "upload-large-file")]
+pub const JWT_SECRET: &str = "test-secret";
+
+// 只配置 HS256 密钥, 不要求 iss 和 aud
+#[cfg(feature = "upload-large-file")]
+pub fn setup_jwt() {
+    setup();
+    web_server::jwt_verify::set_jwt_config(&web_server::jwt_verify::JwtConfig {
+        secret: Some(String::from(JWT_SECRET)),
+        ..Default::default()
+    })
+    .unwrap();
+}
+
+// 签发指定用户目录的 token, 一小时后过期
+#[cfg(feature = "upload-large-file")]
+pub fn make_token(user_directory: &str) -> String {
+    sign_token(
+        &serde_json::json!({
+            "data": { "userDirectory": user_directory },
+            "exp": jsonwebtoken::get_current_timestamp() + 3600,
+        }),
+        JWT_SECRET,
+    )
+}
+
+#[cfg(feature = "upload-large-file")]
+pub fn sign_token(claims: &serde_json::Value, secret: &str) -> String {
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        claims,
+        &jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .unwrap()
+}
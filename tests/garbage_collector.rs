@@ -0,0 +1,132 @@
+#![cfg(feature = "transfer")]
+
+mod common;
+
+use actix_web::test;
+use std::fs;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use web_server::garbage_collector::{collect_garbage, GcConfig};
+use web_server::transfer_serve;
+
+use common::{init_app, md5_chunk_file, md5_hex, setup, upload_chunk_request};
+
+const GRACE_PERIOD: u64 = 3600;
+
+fn gc_config(dry_run: bool) -> GcConfig {
+    GcConfig {
+        grace_period: GRACE_PERIOD,
+        dry_run,
+        ..GcConfig::default()
+    }
+}
+
+// 把文件的修改时间改到宽限期之前
+fn age(path: &str) {
+    let modified = SystemTime::now() - Duration::from_secs(GRACE_PERIOD * 2);
+    fs::File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+}
+
+fn write_aged(path: &str, content: &str) {
+    fs::write(path, content).unwrap();
+    age(path);
+}
+
+fn exists(path: &str) -> bool {
+    fs::metadata(path).is_ok()
+}
+
+#[actix_web::test]
+async fn orphans_older_than_grace_period_are_collected() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    // 仍被引用的会话和分享
+    let identify = "gc-live-session";
+    let req = upload_chunk_request(identify, b"live chunk", 0, 2).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    let live_chunk = md5_chunk_file(&md5_hex(b"live chunk"), &format!("transfer/{}", identify));
+    let live_session = format!("./chunks/{}.session.json", md5_hex(format!("transfer/{}", identify).as_bytes()));
+    age(&live_chunk);
+    age(&live_session);
+
+    let req = test::TestRequest::post()
+        .uri("/upload")
+        .insert_header(("filename", "gc-shared.txt"))
+        .set_payload("shared")
+        .to_request();
+    let fetch_code = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    let live_share = format!("./files/gc-shared.txt{}", fetch_code);
+    age(&live_share);
+
+    // 其他服务保存的文件不是分享, 以数字结尾也不清理
+    fs::create_dir_all("./files/gc-user/").unwrap();
+    write_aged("./files/gc-user/notes123456", "user file");
+    write_aged("./files/gc-foreign.txt123456", "foreign file");
+
+    let orphans = [
+        "./chunks/md5-0123456789abcdef0123456789abcdef.chunk",
+        "./chunks/0123456789abcdef0123456789abcdef.session.json",
+        "./chunks/0123456789abcdef0123456789abcdef.assembling",
+        "./chunks/md5-0123456789abcdef0123456789abcdef.chunk.42.uploading",
+        "./files/gc-user/large.bin.42.merging",
+        "./files/gc-lost.txt654321",
+    ];
+    for orphan in orphans {
+        write_aged(orphan, "orphan");
+    }
+    // 重启前创建的分享, 提取码已丢失
+    let mut ledger = fs::OpenOptions::new().create(true).append(true).open("./files/.transfer_shares").unwrap();
+    writeln!(ledger, "{}", serde_json::to_string("./files/gc-lost.txt654321").unwrap()).unwrap();
+    // 宽限期内的文件可能正在写入
+    fs::write("./chunks/md5-fedcba9876543210fedcba9876543210.chunk", "recent").unwrap();
+
+    let report = collect_garbage(&gc_config(true), true).await;
+    assert!(report.dry_run);
+    for orphan in orphans {
+        assert!(report.contains(orphan), "{} is not reported", orphan);
+        assert!(exists(orphan));
+    }
+
+    let report = collect_garbage(&gc_config(false), true).await;
+    for orphan in orphans {
+        assert!(report.contains(orphan), "{} is not reported", orphan);
+        assert!(!exists(orphan));
+    }
+    assert!(report.removed_size() >= (orphans.len() * "orphan".len()) as u64);
+    for kept in [
+        live_chunk.as_str(),
+        live_session.as_str(),
+        live_share.as_str(),
+        "./files/gc-user/notes123456",
+        "./files/gc-foreign.txt123456",
+        "./chunks/md5-fedcba9876543210fedcba9876543210.chunk",
+    ] {
+        assert!(!report.contains(kept), "{} should be kept", kept);
+        assert!(exists(kept));
+    }
+
+    // 已删除的文件从记录中移除
+    let ledger = fs::read_to_string("./files/.transfer_shares").unwrap();
+    assert!(!ledger.contains("gc-lost.txt654321"));
+    assert!(ledger.contains(&format!("gc-shared.txt{}", fetch_code)));
+
+    // 未挂载 transfer 服务时不清理分享
+    write_aged("./files/gc-unmounted.txt123456", "orphan");
+    let mut ledger = fs::OpenOptions::new().append(true).open("./files/.transfer_shares").unwrap();
+    writeln!(ledger, "{}", serde_json::to_string("./files/gc-unmounted.txt123456").unwrap()).unwrap();
+    let report = collect_garbage(&gc_config(false), false).await;
+    assert!(!report.contains("./files/gc-unmounted.txt123456"));
+    assert!(exists("./files/gc-unmounted.txt123456"));
+
+    // 会话仍可继续上传
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", identify))
+        .to_request();
+    assert_eq!(
+        test::call_and_read_body(&app, req).await,
+        format!(r#"["{}","empty"]"#, md5_hex(b"live chunk"))
+    );
+}
@@ -0,0 +1,187 @@
+#![cfg(feature = "transfer")]
+
+mod common;
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use actix_web::{http::StatusCode, test};
+use serde_json::{json, Value};
+
+use web_server::{restore_stored_files, transfer_serve};
+
+use common::{init_app, md5_hex, setup, upload_chunk_request};
+
+// 比 challenge 最大长度更大的文件, 每个测试内容不同
+fn file_content(seed: u8) -> Vec<u8> {
+    (0..100_000u32).map(|index| (index % 251) as u8 ^ seed).collect()
+}
+
+fn challenge_request(content: &[u8]) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/instant_upload_challenge")
+        .set_json(json!({ "fileHash": md5_hex(content), "fileSize": content.len() }))
+}
+
+fn instant_upload_request(challenge_id: &str, answer: &[u8]) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/instant_upload")
+        .insert_header(("challengeId", challenge_id))
+        .insert_header(("fullPath", "instant.bin"))
+        .set_payload(answer.to_vec())
+}
+
+macro_rules! upload_and_merge {
+    ($app:expr, $identify:expr, $content:expr) => {{
+        let chunks: Vec<&[u8]> = $content.chunks(60_000).collect();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let req = upload_chunk_request($identify, chunk, index, chunks.len()).to_request();
+            assert_eq!(test::call_and_read_body(&$app, req).await, "true");
+        }
+        let req = test::TestRequest::post()
+            .uri("/merge_chunks")
+            .insert_header(("identify", $identify))
+            .insert_header(("fullPath", "original.bin"))
+            .to_request();
+        test::call_and_read_body(&$app, req).await;
+    }};
+}
+
+#[actix_web::test]
+async fn existing_file_is_uploaded_after_challenge() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let content = file_content(1);
+    upload_and_merge!(app, "instant-original", content);
+
+    let challenge: Value = test::call_and_read_body_json(&app, challenge_request(&content).to_request()).await;
+    assert_eq!(challenge["exists"], true);
+    let challenge_id = challenge["challengeId"].as_str().unwrap();
+    let (offset, length) = (
+        challenge["offset"].as_u64().unwrap() as usize,
+        challenge["length"].as_u64().unwrap() as usize,
+    );
+    assert_eq!(length, 65536);
+
+    let req = instant_upload_request(challenge_id, &content[offset..offset + length]).to_request();
+    let fetch_code = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    let req = test::TestRequest::get().uri(&format!("/fetch-file/{}", fetch_code)).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, content);
+}
+
+#[actix_web::test]
+async fn wrong_answer_consumes_challenge() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let content = file_content(2);
+    upload_and_merge!(app, "instant-guarded", content);
+
+    let challenge: Value = test::call_and_read_body_json(&app, challenge_request(&content).to_request()).await;
+    let challenge_id = challenge["challengeId"].as_str().unwrap();
+    let (offset, length) = (
+        challenge["offset"].as_u64().unwrap() as usize,
+        challenge["length"].as_u64().unwrap() as usize,
+    );
+
+    let req = instant_upload_request(challenge_id, &vec![0; length]).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    // 同一个 challenge 不能再次回答
+    let req = instant_upload_request(challenge_id, &content[offset..offset + length]).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn unknown_file_has_no_challenge() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let challenge: Value = test::call_and_read_body_json(&app, challenge_request(&file_content(3)).to_request()).await;
+    assert_eq!(challenge["exists"], false);
+    assert_eq!(challenge["challengeId"], Value::Null);
+
+    let req = instant_upload_request("0123456789abcdef0123456789abcdef", b"guess").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn stored_files_are_only_visible_to_their_owner() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let content = file_content(6);
+    upload_and_merge!(app, "instant-scoped", content);
+
+    let req = challenge_request(&content).insert_header(("ownerKey", "someone-else")).to_request();
+    let challenge: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(challenge["exists"], false);
+
+    // challenge 也不能由其他创建者回答
+    let challenge: Value = test::call_and_read_body_json(&app, challenge_request(&content).to_request()).await;
+    let challenge_id = challenge["challengeId"].as_str().unwrap();
+    let (offset, length) = (
+        challenge["offset"].as_u64().unwrap() as usize,
+        challenge["length"].as_u64().unwrap() as usize,
+    );
+    let req = instant_upload_request(challenge_id, &content[offset..offset + length])
+        .insert_header(("ownerKey", "someone-else"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn tus_upload_without_checksum_is_recorded() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let content = file_content(4);
+
+    let req = test::TestRequest::post()
+        .uri("/tus")
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", content.len().to_string()))
+        .insert_header(("Upload-Metadata", "filename dHVzLmJpbg=="))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers().get("Location").unwrap().to_str().unwrap().to_string();
+    let req = test::TestRequest::patch()
+        .uri(&location)
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Content-Type", "application/offset+octet-stream"))
+        .insert_header(("Upload-Offset", "0"))
+        .set_payload(content.clone())
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NO_CONTENT);
+
+    let challenge: Value = test::call_and_read_body_json(&app, challenge_request(&content).to_request()).await;
+    assert_eq!(challenge["exists"], true);
+}
+
+fn indexed_paths(content: &[u8]) -> Vec<String> {
+    let index: HashMap<String, Vec<Value>> =
+        serde_json::from_slice(&fs::read("./chunks/stored_files.json").unwrap()).unwrap();
+    index
+        .get(&format!("transfer::md5-{}-{}", md5_hex(content), content.len()))
+        .map(|files| files.iter().map(|file| file["path"].as_str().unwrap().to_string()).collect())
+        .unwrap_or_default()
+}
+
+#[actix_web::test]
+async fn stored_files_are_persisted_and_pruned_on_restore() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let content = file_content(5);
+    upload_and_merge!(app, "instant-persisted", content);
+
+    let paths = indexed_paths(&content);
+    assert_eq!(paths.len(), 1);
+
+    // 重启时重新校验记录, 已修改的文件不再使用
+    restore_stored_files().await.unwrap();
+    assert_eq!(indexed_paths(&content), paths);
+    OpenOptions::new().append(true).open(&paths[0]).unwrap().write_all(b"changed").unwrap();
+    restore_stored_files().await.unwrap();
+    assert!(indexed_paths(&content).is_empty());
+
+    let challenge: Value = test::call_and_read_body_json(&app, challenge_request(&content).to_request()).await;
+    assert_eq!(challenge["exists"], false);
+}
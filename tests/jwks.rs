@@ -0,0 +1,266 @@
+#![cfg(feature = "upload-large-file")]
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use actix_web::{dev::ServerHandle, http::StatusCode, test, web, App, HttpResponse, HttpServer};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, get_current_timestamp, Algorithm, EncodingKey, Header};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde_json::{json, Value};
+
+use web_server::jwt_verify::{refresh_jwks_url, reload_jwks, set_jwt_config, watch_jwks, JwtConfig};
+use web_server::upload_large_file;
+
+use common::setup;
+
+lazy_static! {
+    // 校验配置是全局的, 本文件中的测试依次运行
+    static ref CONFIG_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn claims() -> Value {
+    json!({
+        "data": { "userDirectory": "alice/" },
+        "exp": get_current_timestamp() + 3600,
+    })
+}
+
+fn sign(algorithm: Algorithm, kid: Option<&str>, key: &EncodingKey) -> String {
+    let header = Header {
+        kid: kid.map(String::from),
+        ..Header::new(algorithm)
+    };
+    encode(&header, &claims(), key).unwrap()
+}
+
+fn rsa_token(kid: Option<&str>) -> String {
+    let key = EncodingKey::from_rsa_pem(&fs::read(fixture("jwt_rsa.key")).unwrap()).unwrap();
+    sign(Algorithm::RS256, kid, &key)
+}
+
+fn ec_token(kid: Option<&str>) -> String {
+    let key = EncodingKey::from_ec_pem(&fs::read(fixture("jwt_ec.key")).unwrap()).unwrap();
+    sign(Algorithm::ES256, kid, &key)
+}
+
+fn hs_token(kid: &str, secret: &str) -> String {
+    sign(Algorithm::HS256, Some(kid), &EncodingKey::from_secret(secret.as_bytes()))
+}
+
+// 只含一个对称密钥的 JWKS
+fn oct_jwks(kid: &str, secret: &str) -> String {
+    json!({ "keys": [{ "kty": "oct", "kid": kid, "k": URL_SAFE_NO_PAD.encode(secret) }] }).to_string()
+}
+
+async fn is_accepted(token: &str) -> bool {
+    let app = test::init_service(App::new().configure(upload_large_file::actix_configure)).await;
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("token", token))
+        .insert_header(("identify", "jwks-check"))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+    match response.status() {
+        StatusCode::OK => true,
+        StatusCode::BAD_REQUEST => false,
+        status => panic!("unexpected status {}", status),
+    }
+}
+
+#[actix_web::test]
+async fn jwks_file_keys_are_selected_by_kid() {
+    let _lock = CONFIG_LOCK.lock().await;
+    setup();
+    fs::copy(fixture("jwks.json"), "./jwks_kid.json").unwrap();
+    set_jwt_config(&JwtConfig {
+        jwks_path: Some(String::from("./jwks_kid.json")),
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert!(is_accepted(&rsa_token(Some("rsa-1"))).await);
+    assert!(is_accepted(&ec_token(Some("ec-1"))).await);
+    // 没有 kid 时尝试所有同算法的密钥
+    assert!(is_accepted(&rsa_token(None)).await);
+    // kid 对应的密钥算法不同, 或没有对应的密钥
+    assert!(!is_accepted(&rsa_token(Some("ec-1"))).await);
+    assert!(!is_accepted(&rsa_token(Some("rsa-2"))).await);
+}
+
+#[actix_web::test]
+async fn failed_jwks_reload_keeps_previous_keys() {
+    let _lock = CONFIG_LOCK.lock().await;
+    setup();
+    let path = Path::new("./jwks_reload.json");
+    fs::copy(fixture("jwks.json"), path).unwrap();
+    set_jwt_config(&JwtConfig {
+        jwks_path: Some(path.to_string_lossy().to_string()),
+        ..Default::default()
+    })
+    .unwrap();
+
+    // 轮换为新的密钥
+    fs::write(path, oct_jwks("hs-1", "rotated-secret")).unwrap();
+    assert_eq!(reload_jwks(path), Ok(1));
+    assert!(is_accepted(&hs_token("hs-1", "rotated-secret")).await);
+    assert!(!is_accepted(&rsa_token(Some("rsa-1"))).await);
+
+    // 无法解析或没有签名密钥时保留之前的密钥
+    for content in ["{ \"keys\": [", r#"{ "keys": [] }"#, r#"{ "keys": [{ "kty": "RSA", "n": "!", "e": "AQAB" }] }"#] {
+        fs::write(path, content).unwrap();
+        assert!(reload_jwks(path).is_err(), "{}", content);
+        assert!(is_accepted(&hs_token("hs-1", "rotated-secret")).await);
+    }
+
+    // 启动时文件无效则配置失败
+    assert!(set_jwt_config(&JwtConfig {
+        jwks_path: Some(path.to_string_lossy().to_string()),
+        ..Default::default()
+    })
+    .is_err());
+    assert!(is_accepted(&hs_token("hs-1", "rotated-secret")).await);
+}
+
+// 文件变化后需要等待 Hotwatch 的去抖延迟
+async fn wait_until_accepted(token: &str) -> bool {
+    for _ in 0..100 {
+        if is_accepted(token).await {
+            return true;
+        }
+        actix_web::rt::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    false
+}
+
+#[actix_web::test]
+async fn watched_jwks_file_is_reloaded_when_replaced() {
+    let _lock = CONFIG_LOCK.lock().await;
+    setup();
+    let path = Path::new("./jwks_watch.json");
+    fs::copy(fixture("jwks.json"), path).unwrap();
+    set_jwt_config(&JwtConfig {
+        jwks_path: Some(path.to_string_lossy().to_string()),
+        ..Default::default()
+    })
+    .unwrap();
+    let _watch = watch_jwks(&path.to_string_lossy()).unwrap();
+
+    // 直接写入
+    fs::write(path, oct_jwks("hs-w", "written-secret")).unwrap();
+    assert!(wait_until_accepted(&hs_token("hs-w", "written-secret")).await);
+
+    // 写入临时文件后替换
+    fs::write("./jwks_watch.json.tmp", oct_jwks("hs-r", "renamed-secret")).unwrap();
+    fs::rename("./jwks_watch.json.tmp", path).unwrap();
+    assert!(wait_until_accepted(&hs_token("hs-r", "renamed-secret")).await);
+    assert!(!is_accepted(&hs_token("hs-w", "written-secret")).await);
+}
+
+// 在本地提供 JWKS, 返回地址, 内容可以随时替换, 同时记录请求次数
+fn serve_jwks(served: Arc<RwLock<String>>, hits: Arc<AtomicUsize>) -> (String, ServerHandle) {
+    let server = HttpServer::new(move || {
+        let (content, hits) = (served.clone(), hits.clone());
+        App::new().route(
+            "/jwks.json",
+            web::get().to(move || {
+                hits.fetch_add(1, Ordering::SeqCst);
+                let body = content.read().clone();
+                async move { HttpResponse::Ok().content_type("application/json").body(body) }
+            }),
+        )
+    })
+    .workers(1)
+    .bind("127.0.0.1:0")
+    .unwrap();
+    let url = format!("http://{}/jwks.json", server.addrs()[0]);
+    let server = server.run();
+    let server_handle = server.handle();
+    actix_web::rt::spawn(server);
+    (url, server_handle)
+}
+
+#[actix_web::test]
+async fn jwks_url_keys_are_cached() {
+    let _lock = CONFIG_LOCK.lock().await;
+    setup();
+    let served = Arc::new(RwLock::new(fs::read_to_string(fixture("jwks.json")).unwrap()));
+    let (url, server_handle) = serve_jwks(served.clone(), Arc::new(AtomicUsize::new(0)));
+
+    set_jwt_config(&JwtConfig {
+        jwks_url: Some(url.clone()),
+        ..Default::default()
+    })
+    .unwrap();
+    // 获取之前没有可用的密钥
+    assert!(!is_accepted(&ec_token(None)).await);
+    assert_eq!(refresh_jwks_url(&url).await, Ok(2));
+    assert!(is_accepted(&ec_token(Some("ec-1"))).await);
+
+    // 获取失败时继续使用缓存的密钥
+    *served.write() = String::from("not json");
+    assert!(refresh_jwks_url(&url).await.is_err());
+    assert!(refresh_jwks_url(&format!("{}.missing", url)).await.is_err());
+    assert!(is_accepted(&ec_token(Some("ec-1"))).await);
+
+    *served.write() = oct_jwks("hs-2", "remote-secret");
+    assert_eq!(refresh_jwks_url(&url).await, Ok(1));
+    assert!(is_accepted(&hs_token("hs-2", "remote-secret")).await);
+    assert!(!is_accepted(&ec_token(Some("ec-1"))).await);
+
+    // 缓存的密钥只对配置的地址有效
+    set_jwt_config(&JwtConfig {
+        secret: Some(String::from("local-secret")),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(!is_accepted(&hs_token("hs-2", "remote-secret")).await);
+    // 配置的密钥没有 kid, token 中的 kid 无对应密钥时使用
+    assert!(is_accepted(&hs_token("unknown", "local-secret")).await);
+
+    server_handle.stop(false).await;
+}
+
+#[actix_web::test]
+async fn unknown_kid_triggers_one_jwks_fetch() {
+    let _lock = CONFIG_LOCK.lock().await;
+    setup();
+    let served = Arc::new(RwLock::new(fs::read_to_string(fixture("jwks.json")).unwrap()));
+    let hits = Arc::new(AtomicUsize::new(0));
+    let (url, server_handle) = serve_jwks(served.clone(), hits.clone());
+
+    set_jwt_config(&JwtConfig {
+        jwks_url: Some(url.clone()),
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(refresh_jwks_url(&url).await, Ok(2));
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+    // 已知的 kid 不会重新获取
+    assert!(is_accepted(&rsa_token(Some("rsa-1"))).await);
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+    // 密钥轮换后, 新的 kid 在拒绝前重新获取
+    *served.write() = oct_jwks("hs-3", "rotated-remote-secret");
+    assert!(is_accepted(&hs_token("hs-3", "rotated-remote-secret")).await);
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+    // 间隔内不会再次获取
+    *served.write() = oct_jwks("hs-4", "next-remote-secret");
+    assert!(!is_accepted(&hs_token("hs-4", "next-remote-secret")).await);
+    assert!(!is_accepted(&hs_token("forged", "next-remote-secret")).await);
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+    assert!(is_accepted(&hs_token("hs-3", "rotated-remote-secret")).await);
+
+    server_handle.stop(false).await;
+}
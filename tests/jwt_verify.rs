@@ -0,0 +1,124 @@
+#![cfg(feature = "upload-large-file")]
+
+mod common;
+
+use actix_web::{http::StatusCode, test, App};
+use jsonwebtoken::{encode, get_current_timestamp, Algorithm, EncodingKey, Header};
+use serde_json::{json, Value};
+
+use web_server::jwt_verify::{set_jwt_config, JwtConfig};
+use web_server::upload_large_file;
+
+use common::{setup, sign_token, JWT_SECRET};
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+// 本测试进程要求 iss 和 aud, 同时接受 HS256, RS256 和 ES256
+fn setup_verification() {
+    setup();
+    set_jwt_config(&JwtConfig {
+        secret: Some(String::from(JWT_SECRET)),
+        public_key_paths: vec![fixture("jwt_rsa.pub"), fixture("jwt_ec.pub")],
+        issuer: Some(String::from("https://auth.example.com")),
+        audience: Some(String::from("uploads")),
+        leeway: 0,
+        ..Default::default()
+    })
+    .unwrap();
+}
+
+fn claims(user_directory: &str) -> Value {
+    json!({
+        "data": { "userDirectory": user_directory },
+        "iss": "https://auth.example.com",
+        "aud": "uploads",
+        "exp": get_current_timestamp() + 3600,
+    })
+}
+
+fn sign_with_pem(algorithm: Algorithm, key_file: &str, claims: &Value) -> String {
+    let pem = std::fs::read(fixture(key_file)).unwrap();
+    let key = match algorithm {
+        Algorithm::RS256 => EncodingKey::from_rsa_pem(&pem).unwrap(),
+        _ => EncodingKey::from_ec_pem(&pem).unwrap(),
+    };
+    encode(&Header::new(algorithm), claims, &key).unwrap()
+}
+
+// 需要鉴权的接口是否接受该 token
+async fn is_accepted(token: &str) -> bool {
+    let app = test::init_service(App::new().configure(upload_large_file::actix_configure)).await;
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("token", token))
+        .insert_header(("identify", "jwt-check"))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+    match response.status() {
+        StatusCode::OK => true,
+        StatusCode::BAD_REQUEST => false,
+        status => panic!("unexpected status {}", status),
+    }
+}
+
+#[actix_web::test]
+async fn signed_tokens_are_accepted() {
+    setup_verification();
+    assert!(is_accepted(&sign_token(&claims("alice/"), JWT_SECRET)).await);
+    assert!(is_accepted(&sign_with_pem(Algorithm::RS256, "jwt_rsa.key", &claims("alice/"))).await);
+    assert!(is_accepted(&sign_with_pem(Algorithm::ES256, "jwt_ec.key", &claims("alice/"))).await);
+}
+
+#[actix_web::test]
+async fn invalid_signatures_are_rejected() {
+    setup_verification();
+    assert!(!is_accepted(&sign_token(&claims("alice/"), "other-secret")).await);
+    // 用公钥作为 HS256 密钥签名
+    let public_key = std::fs::read_to_string(fixture("jwt_rsa.pub")).unwrap();
+    assert!(!is_accepted(&sign_token(&claims("alice/"), &public_key)).await);
+    // 未签名
+    let token = sign_token(&claims("alice/"), JWT_SECRET);
+    let unsigned = format!("{}.", &token[..token.rfind('.').unwrap()]);
+    assert!(!is_accepted(&unsigned).await);
+    assert!(!is_accepted("not-a-token").await);
+}
+
+#[actix_web::test]
+async fn claims_are_checked() {
+    setup_verification();
+    let now = get_current_timestamp();
+    let with = |key: &str, value: Value| {
+        let mut claims = claims("alice/");
+        claims[key] = value;
+        sign_token(&claims, JWT_SECRET)
+    };
+    assert!(!is_accepted(&with("exp", json!(now - 10))).await);
+    assert!(!is_accepted(&with("nbf", json!(now + 3600))).await);
+    assert!(is_accepted(&with("nbf", json!(now - 10))).await);
+    assert!(!is_accepted(&with("iss", json!("https://other.example.com"))).await);
+    assert!(!is_accepted(&with("aud", json!("downloads"))).await);
+    assert!(is_accepted(&with("aud", json!(["downloads", "uploads"]))).await);
+
+    // exp 必须存在
+    let mut claims_without_exp = claims("alice/");
+    claims_without_exp.as_object_mut().unwrap().remove("exp");
+    assert!(!is_accepted(&sign_token(&claims_without_exp, JWT_SECRET)).await);
+
+    // 用户目录不能离开基本路径
+    assert!(!is_accepted(&sign_token(&claims("../alice/"), JWT_SECRET)).await);
+    assert!(!is_accepted(&sign_token(&claims("/etc/"), JWT_SECRET)).await);
+}
+
+#[actix_web::test]
+async fn invalid_config_keeps_previous_keys() {
+    setup_verification();
+    assert!(set_jwt_config(&JwtConfig::default()).is_err());
+    let config = JwtConfig {
+        public_key_paths: vec![fixture("missing.pub")],
+        ..Default::default()
+    };
+    assert!(set_jwt_config(&config).is_err());
+    assert!(is_accepted(&sign_token(&claims("alice/"), JWT_SECRET)).await);
+}
@@ -0,0 +1,153 @@
+#![cfg(feature = "transfer")]
+
+mod common;
+
+use actix_web::{http::StatusCode, test};
+use serde_json::Value;
+use tokio::time::{sleep, Duration};
+
+use web_server::transfer_serve;
+
+use common::{init_app, setup, upload_chunk_request};
+
+// 轮询合并任务直到结束, 返回最终状态
+macro_rules! wait_for_job {
+    ($app:expr, $job_id:expr) => {{
+        let mut status = Value::Null;
+        for _ in 0..100 {
+            let req = test::TestRequest::post()
+                .uri("/merge_status")
+                .insert_header(("jobId", $job_id))
+                .to_request();
+            status = test::call_and_read_body_json(&$app, req).await;
+            if status["state"] != "running" {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        status
+    }};
+}
+
+#[actix_web::test]
+async fn async_merge_reports_progress_and_fetch_code() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "job-merge";
+    let chunks: [&[u8]; 3] = [b"merged ", b"in the ", b"background"];
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let req = upload_chunk_request(identify, chunk, index, chunks.len()).to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks_async")
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "job.txt"))
+        .to_request();
+    let job_id = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    assert_eq!(job_id.len(), 32);
+
+    let status = wait_for_job!(app, job_id.as_str());
+    assert_eq!(status["state"], "succeeded");
+    assert_eq!(status["mergedChunks"], 3);
+    assert_eq!(status["totalChunks"], 3);
+
+    // 结果为提取码
+    let fetch_code = status["result"].as_str().unwrap();
+    let req = test::TestRequest::get().uri(&format!("/fetch-file/{}", fetch_code)).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "merged in the background");
+
+    // 会话已删除
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", identify))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "[]");
+}
+
+#[actix_web::test]
+async fn async_merge_with_missing_chunks_fails_and_keeps_session() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "job-incomplete";
+
+    let req = upload_chunk_request(identify, b"first", 0, 2).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks_async")
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "incomplete-job.txt"))
+        .to_request();
+    let job_id = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+
+    let status = wait_for_job!(app, job_id.as_str());
+    assert_eq!(status["state"], "failed");
+    assert_eq!(status["error"], "missing chunks: [1]");
+    assert_eq!(status["result"], Value::Null);
+
+    // 补传后可以再次合并
+    let req = upload_chunk_request(identify, b"second", 1, 2).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks_async")
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "incomplete-job.txt"))
+        .to_request();
+    let job_id = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    assert_eq!(wait_for_job!(app, job_id.as_str())["state"], "succeeded");
+}
+
+#[actix_web::test]
+async fn unknown_session_and_job_are_rejected() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks_async")
+        .insert_header(("identify", "job-never-uploaded"))
+        .insert_header(("fullPath", "never.txt"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = test::TestRequest::post()
+        .uri("/merge_status")
+        .insert_header(("jobId", "0123456789abcdef0123456789abcdef"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn merge_status_is_only_visible_to_owner() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "job-owned";
+
+    let req = upload_chunk_request(identify, b"owned job", 0, 1)
+        .insert_header(("ownerKey", "job-owner"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks_async")
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "owned-job.txt"))
+        .insert_header(("ownerKey", "job-owner"))
+        .to_request();
+    let job_id = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+
+    for owner_key in [None, Some("someone-else")] {
+        let mut req = test::TestRequest::post().uri("/merge_status").insert_header(("jobId", job_id.as_str()));
+        if let Some(owner_key) = owner_key {
+            req = req.insert_header(("ownerKey", owner_key));
+        }
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::BAD_REQUEST);
+    }
+    let req = test::TestRequest::post()
+        .uri("/merge_status")
+        .insert_header(("jobId", job_id.as_str()))
+        .insert_header(("ownerKey", "job-owner"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+}
@@ -0,0 +1,239 @@
+#![cfg(any(feature = "transfer", feature = "upload-large-file"))]
+
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use actix_web::{rt::time::sleep, test, web, App, HttpRequest};
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use web_server::post_merge::{set_post_merge_steps, PostMergeStep, PostMergeStepConfig};
+use web_server::upload_policy::{configure_upload_routes, MergeTarget, UploadPolicy};
+
+use common::{md5_hex, setup};
+
+// 各服务使用不同的处理步骤, 文件保存在 ./files/ 下
+struct ScannedPolicy;
+struct FailingPolicy;
+struct UnpackedPolicy;
+
+struct PlainTarget;
+
+impl MergeTarget for PlainTarget {}
+
+impl UploadPolicy for ScannedPolicy {
+    type Grant = ();
+    type Target = PlainTarget;
+
+    const SERVICE: &'static str = "scanned";
+
+    async fn authorize(&self, _req: &HttpRequest) -> Result<Self::Grant, Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    async fn merge_target(&self, _grant: Self::Grant) -> Self::Target {
+        PlainTarget
+    }
+}
+
+impl UploadPolicy for FailingPolicy {
+    type Grant = ();
+    type Target = PlainTarget;
+
+    const SERVICE: &'static str = "failing";
+
+    async fn authorize(&self, _req: &HttpRequest) -> Result<Self::Grant, Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    async fn merge_target(&self, _grant: Self::Grant) -> Self::Target {
+        PlainTarget
+    }
+}
+
+impl UploadPolicy for UnpackedPolicy {
+    type Grant = ();
+    type Target = PlainTarget;
+
+    const SERVICE: &'static str = "unpacked";
+
+    async fn authorize(&self, _req: &HttpRequest) -> Result<Self::Grant, Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    async fn merge_target(&self, _grant: Self::Grant) -> Self::Target {
+        PlainTarget
+    }
+}
+
+struct PanickingStep;
+
+impl PostMergeStep for PanickingStep {
+    fn name(&self) -> String {
+        String::from("panicking")
+    }
+
+    fn run(&self, _file_path: String) -> BoxFuture<'static, Result<String, String>> {
+        Box::pin(async { panic!("step crashed") })
+    }
+}
+
+fn build_steps(configs: Value) -> Vec<Arc<dyn PostMergeStep>> {
+    let configs: Vec<PostMergeStepConfig> = serde_json::from_value(configs).unwrap();
+    configs.iter().map(|config| config.build()).collect()
+}
+
+// 上传一个 chunk 并合并, 返回合并的响应
+async fn upload_and_merge(
+    configure: fn(&mut web::ServiceConfig),
+    identify: &str,
+    full_path: &str,
+    content: &[u8],
+) -> String {
+    let app = test::init_service(App::new().configure(configure)).await;
+    let req = test::TestRequest::post()
+        .uri("/upload_chunk")
+        .insert_header(("identify", identify))
+        .insert_header(("chunkHash", md5_hex(content)))
+        .insert_header(("chunkIndex", "0"))
+        .insert_header(("chunksNumber", "1"))
+        .set_payload(content.to_vec())
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", full_path))
+        .to_request();
+    String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap()
+}
+
+// 等待处理结束, 返回最终状态
+async fn finished_status(configure: fn(&mut web::ServiceConfig), identify: &str) -> Value {
+    let app = test::init_service(App::new().configure(configure)).await;
+    for _ in 0..100 {
+        let req = test::TestRequest::post()
+            .uri("/post_merge_status")
+            .insert_header(("identify", identify))
+            .to_request();
+        let status: Value = test::call_and_read_body_json(&app, req).await;
+        if status["state"] != "running" {
+            return status;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    panic!("post-merge pipeline did not finish");
+}
+
+#[actix_web::test]
+async fn steps_run_in_order_after_merge() {
+    setup();
+    set_post_merge_steps(
+        ScannedPolicy::SERVICE,
+        build_steps(json!([
+            { "type": "checksum", "algorithm": "sha256" },
+            { "type": "contentType" },
+            { "type": "command", "program": "sh", "args": ["-c", "test -s \"$0\" && echo clean"] },
+        ])),
+    );
+    let configure = |config: &mut web::ServiceConfig| configure_upload_routes(config, &ScannedPolicy);
+
+    let content = b"scan me after merge";
+    let file_path = upload_and_merge(configure, "scanned-upload", "scanned.txt", content).await;
+    assert_eq!(file_path, "./files/scanned.txt");
+
+    let status = finished_status(configure, "scanned-upload").await;
+    assert_eq!(status["state"], "succeeded");
+    let steps = status["steps"].as_array().unwrap();
+    let names: Vec<&str> = steps.iter().map(|step| step["name"].as_str().unwrap()).collect();
+    assert_eq!(names, vec!["checksum-sha256", "contentType", "sh"]);
+    assert!(steps.iter().all(|step| step["state"] == "succeeded"));
+    assert_eq!(steps[0]["result"], format!("{:x}", Sha256::digest(content)));
+    assert_eq!(steps[1]["result"], "text/plain");
+    assert_eq!(steps[2]["result"], "clean");
+}
+
+#[actix_web::test]
+async fn failed_step_skips_the_rest_and_keeps_file() {
+    setup();
+    let mut steps = build_steps(json!([{ "type": "contentType" }]));
+    steps.push(Arc::new(PanickingStep));
+    steps.extend(build_steps(json!([{ "type": "command", "program": "sh", "args": ["-c", "exit 0"] }])));
+    set_post_merge_steps(FailingPolicy::SERVICE, steps);
+    let configure = |config: &mut web::ServiceConfig| configure_upload_routes(config, &FailingPolicy);
+
+    let file_path = upload_and_merge(configure, "failing-upload", "failing.bin", b"kept").await;
+    let status = finished_status(configure, "failing-upload").await;
+    assert_eq!(status["state"], "failed");
+    let states: Vec<&str> = status["steps"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|step| step["state"].as_str().unwrap())
+        .collect();
+    assert_eq!(states, vec!["succeeded", "failed", "skipped"]);
+    assert_eq!(status["steps"][0]["result"], "application/octet-stream");
+    assert!(status["steps"][1]["error"].as_str().unwrap().contains("panic"));
+    assert_eq!(std::fs::read_to_string(file_path).unwrap(), "kept");
+
+    // 没有合并过的会话没有处理进度
+    let app = test::init_service(App::new().configure(configure)).await;
+    let req = test::TestRequest::post()
+        .uri("/post_merge_status")
+        .insert_header(("identify", "unknown-upload"))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_client_error());
+}
+
+#[actix_web::test]
+async fn content_type_is_sniffed_and_archives_are_extracted() {
+    setup();
+    #[allow(unused_mut)]
+    let mut configs = vec![json!({ "type": "contentType" })];
+    #[cfg(feature = "upload-large-file")]
+    configs.push(json!({ "type": "extract" }));
+    set_post_merge_steps(UnpackedPolicy::SERVICE, build_steps(Value::Array(configs)));
+    let configure = |config: &mut web::ServiceConfig| configure_upload_routes(config, &UnpackedPolicy);
+
+    // 按文件头识别, 不相信扩展名
+    let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+    upload_and_merge(configure, "unpacked-image", "disguised.txt", png).await;
+    let status = finished_status(configure, "unpacked-image").await;
+    assert_eq!(status["state"], "succeeded");
+    assert_eq!(status["steps"][0]["result"], "image/png");
+    #[cfg(feature = "upload-large-file")]
+    assert_eq!(status["steps"][1]["result"], "not an archive");
+
+    #[cfg(feature = "upload-large-file")]
+    {
+        use std::io::Write;
+        use zip::{write::FileOptions, ZipWriter};
+
+        // 不限制解压后的大小时也能解压
+        web_server::archive_extract::set_extract_limits(web_server::archive_extract::ExtractLimits {
+            max_total_size: u64::MAX,
+            max_entries: 100,
+        });
+        let mut writer = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        writer.start_file("docs/readme.txt", FileOptions::default()).unwrap();
+        writer.write_all(b"extracted after merge").unwrap();
+        let archive = writer.finish().unwrap().into_inner();
+
+        upload_and_merge(configure, "unpacked-archive", "bundle.zip", &archive).await;
+        let status = finished_status(configure, "unpacked-archive").await;
+        assert_eq!(status["state"], "succeeded");
+        assert_eq!(status["steps"][0]["result"], "application/zip");
+        let report: Value = serde_json::from_str(status["steps"][1]["result"].as_str().unwrap()).unwrap();
+        assert!(report["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|entry| entry["path"] == "docs/readme.txt"));
+        assert_eq!(
+            std::fs::read_to_string("./files/bundle/docs/readme.txt").unwrap(),
+            "extracted after merge"
+        );
+    }
+}
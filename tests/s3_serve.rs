@@ -0,0 +1,364 @@
+#![cfg(feature = "s3")]
+
+mod common;
+
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{
+    body::{BodySize, MessageBody},
+    dev::ServiceResponse,
+    http::StatusCode,
+    test,
+};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use web_server::s3_serve::{self, update_credentials, S3Credential};
+
+use common::{init_app, setup};
+
+const ACCESS_KEY_ID: &str = "AKIDEXAMPLE";
+const SECRET_ACCESS_KEY: &str = "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY";
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+fn setup_credentials() {
+    setup();
+    update_credentials(vec![S3Credential {
+        access_key_id: String::from(ACCESS_KEY_ID),
+        secret_access_key: String::from(SECRET_ACCESS_KEY),
+    }]);
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+fn hmac_sha256(key: &[u8], content: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(content.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// 客户端一侧的 SigV4 签名, path 和 query 需已按 AWS 规则编码并排序
+fn signature(secret: &str, method: &str, path: &str, query: &str, headers: &[(&str, &str)], amz_date: &str) -> String {
+    let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect();
+    let signed_headers: Vec<&str> = headers.iter().map(|(name, _)| *name).collect();
+    let payload_hash = headers
+        .iter()
+        .find(|(name, _)| *name == "x-amz-content-sha256")
+        .map_or(UNSIGNED_PAYLOAD, |(_, value)| *value);
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        path,
+        query,
+        canonical_headers,
+        signed_headers.join(";"),
+        payload_hash
+    );
+    let scope = format!("{}/us-east-1/s3/aws4_request", &amz_date[..8]);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        sha256_hex(canonical_request.as_bytes())
+    );
+    let mut key = format!("AWS4{}", secret).into_bytes();
+    for part in scope.split('/') {
+        key = hmac_sha256(&key, part);
+    }
+    hmac_sha256(&key, &string_to_sign).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn amz_date_now() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let (days, seconds) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // 1970-01-01 起的天数转为公历日期
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+// 带 Authorization 头的请求, query 需按名称排序
+fn signed_request(method: &str, path: &str, query: &str, body: &[u8]) -> test::TestRequest {
+    signed_request_with(method, path, query, body, SECRET_ACCESS_KEY)
+}
+
+fn signed_request_with(method: &str, path: &str, query: &str, body: &[u8], secret: &str) -> test::TestRequest {
+    let amz_date = amz_date_now();
+    let payload_hash = sha256_hex(body);
+    let headers = [
+        ("host", "localhost"),
+        ("x-amz-content-sha256", payload_hash.as_str()),
+        ("x-amz-date", amz_date.as_str()),
+    ];
+    let signature = signature(secret, method, path, query, &headers, &amz_date);
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}/us-east-1/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+        ACCESS_KEY_ID,
+        &amz_date[..8],
+        signature
+    );
+    let uri = if query.is_empty() { String::from(path) } else { format!("{}?{}", path, query) };
+    test::TestRequest::default()
+        .method(method.parse().unwrap())
+        .uri(&uri)
+        .insert_header(("host", "localhost"))
+        .insert_header(("x-amz-content-sha256", payload_hash))
+        .insert_header(("x-amz-date", amz_date))
+        .insert_header(("Authorization", authorization))
+        .set_payload(body.to_vec())
+}
+
+fn xml_value(body: &str, tag: &str) -> String {
+    let start = body.find(&format!("<{}>", tag)).unwrap() + tag.len() + 2;
+    let end = body[start..].find(&format!("</{}>", tag)).unwrap() + start;
+    String::from(&body[start..end])
+}
+
+fn etag_of(response: &ServiceResponse) -> String {
+    response.headers().get("ETag").unwrap().to_str().unwrap().to_string()
+}
+
+fn complete_body(parts: &[(usize, &str)]) -> Vec<u8> {
+    let parts: String = parts
+        .iter()
+        .map(|(number, etag)| format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", number, etag))
+        .collect();
+    format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts).into_bytes()
+}
+
+macro_rules! create_upload {
+    ($app:expr, $path:expr) => {{
+        let req = signed_request("POST", $path, "uploads=", b"").to_request();
+        let response = test::call_service(&$app, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        xml_value(&body, "UploadId")
+    }};
+}
+
+macro_rules! upload_part {
+    ($app:expr, $path:expr, $upload_id:expr, $number:expr, $content:expr) => {{
+        let query = format!("partNumber={}&uploadId={}", $number, $upload_id);
+        let response = test::call_service(&$app, signed_request("PUT", $path, &query, $content).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        etag_of(&response)
+    }};
+}
+
+#[actix_web::test]
+async fn signer_matches_aws_example() {
+    // AWS 文档中 GET Object 的示例
+    let headers = [
+        ("host", "examplebucket.s3.amazonaws.com"),
+        ("range", "bytes=0-9"),
+        (
+            "x-amz-content-sha256",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        ),
+        ("x-amz-date", "20130524T000000Z"),
+    ];
+    assert_eq!(
+        signature(SECRET_ACCESS_KEY, "GET", "/test.txt", "", &headers, "20130524T000000Z"),
+        "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+    );
+}
+
+#[actix_web::test]
+async fn multipart_upload_creates_object() {
+    setup_credentials();
+    let app = init_app!(s3_serve::actix_configure);
+    let path = "/bucket/dir/multi%20part.txt";
+    let upload_id = create_upload!(app, path);
+
+    // 分段可以乱序上传
+    let second = upload_part!(app, path, upload_id, 2, b"second part");
+    let first = upload_part!(app, path, upload_id, 1, b"first part, ");
+    assert_eq!(first, format!("\"{:x}\"", md5::compute(b"first part, ")));
+
+    let req = signed_request("GET", path, &format!("uploadId={}", upload_id), b"").to_request();
+    let body = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    assert!(body.contains("<PartNumber>1</PartNumber>"));
+    assert!(body.contains("<Size>11</Size>"));
+
+    let body = complete_body(&[(1, &first), (2, &second)]);
+    let req = signed_request("POST", path, &format!("uploadId={}", upload_id), &body).to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    assert!(xml_value(&body, "ETag").ends_with("-2&quot;"));
+
+    let req = signed_request("GET", path, "", b"").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "first part, second part");
+    let req = signed_request("HEAD", path, "", b"").to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.response().body().size(), BodySize::Sized(23));
+
+    // 上传已完成
+    let req = signed_request("GET", path, &format!("uploadId={}", upload_id), b"").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn complete_validates_parts() {
+    setup_credentials();
+    let app = init_app!(s3_serve::actix_configure);
+    let path = "/bucket/selected.txt";
+    let upload_id = create_upload!(app, path);
+    let first = upload_part!(app, path, upload_id, 1, b"one ");
+    upload_part!(app, path, upload_id, 2, b"two ");
+    let third = upload_part!(app, path, upload_id, 3, b"three");
+    let query = format!("uploadId={}", upload_id);
+
+    let body = complete_body(&[(3, &third), (1, &first)]);
+    let response = test::call_service(&app, signed_request("POST", path, &query, &body).to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    assert_eq!(xml_value(&body, "Code"), "InvalidPartOrder");
+
+    let body = complete_body(&[(1, &first), (3, "\"0123456789abcdef0123456789abcdef\"")]);
+    let response = test::call_service(&app, signed_request("POST", path, &query, &body).to_request()).await;
+    let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    assert_eq!(xml_value(&body, "Code"), "InvalidPart");
+
+    // 未列出的分段不写入对象
+    let body = complete_body(&[(1, &first), (3, &third)]);
+    let response = test::call_service(&app, signed_request("POST", path, &query, &body).to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let req = signed_request("GET", path, "", b"").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "one three");
+}
+
+#[actix_web::test]
+async fn large_complete_request_and_sparse_parts() {
+    setup_credentials();
+    let app = init_app!(s3_serve::actix_configure);
+    let path = "/bucket/sparse.txt";
+    let upload_id = create_upload!(app, path);
+    let first = upload_part!(app, path, upload_id, 1, b"first ");
+    let last = upload_part!(app, path, upload_id, 10000, b"last");
+
+    // 会话只保存已上传的分段
+    let session_sizes: Vec<u64> = fs::read_dir("./chunks/")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(".session.json"))
+        .filter(|path| fs::read_to_string(path).unwrap().contains(&upload_id))
+        .map(|path| fs::metadata(path).unwrap().len())
+        .collect();
+    assert_eq!(session_sizes.len(), 1);
+    assert!(session_sizes[0] < 4096, "{}", session_sizes[0]);
+
+    // 超过默认 256KB 的请求体
+    let mut body = complete_body(&[(1, &first), (10000, &last)]);
+    let padding = " ".repeat(300_000);
+    body.splice(25..25, padding.into_bytes());
+    let req = signed_request("POST", path, &format!("uploadId={}", upload_id), &body).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let req = signed_request("GET", path, "", b"").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "first last");
+
+    // 超出分段数的序号
+    let upload_id = create_upload!(app, path);
+    let query = format!("partNumber=10001&uploadId={}", upload_id);
+    let response = test::call_service(&app, signed_request("PUT", path, &query, b"x").to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn abort_removes_upload() {
+    setup_credentials();
+    let app = init_app!(s3_serve::actix_configure);
+    let path = "/bucket/aborted.txt";
+    let upload_id = create_upload!(app, path);
+    upload_part!(app, path, upload_id, 1, b"never completed");
+    let query = format!("uploadId={}", upload_id);
+
+    let req = signed_request("DELETE", path, &query, b"").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NO_CONTENT);
+
+    let response = test::call_service(&app, signed_request("GET", path, &query, b"").to_request()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    assert_eq!(xml_value(&body, "Code"), "NoSuchUpload");
+
+    // 上传 id 只属于创建它的对象
+    let other_id = create_upload!(app, path);
+    let query = format!("partNumber=1&uploadId={}", other_id);
+    let response = test::call_service(&app, signed_request("PUT", "/bucket/other.txt", &query, b"x").to_request()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn invalid_signatures_are_rejected() {
+    setup_credentials();
+    let app = init_app!(s3_serve::actix_configure);
+    let path = "/bucket/denied.txt";
+
+    let req = test::TestRequest::post().uri(&format!("{}?uploads", path)).to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    assert_eq!(xml_value(&body, "Code"), "AccessDenied");
+
+    let req = signed_request_with("POST", path, "uploads=", b"", "wrong secret").to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    assert_eq!(xml_value(&body, "Code"), "SignatureDoesNotMatch");
+
+    // 签名的 hash 与请求体不一致
+    let upload_id = create_upload!(app, path);
+    let query = format!("partNumber=1&uploadId={}", upload_id);
+    let req = signed_request("PUT", path, &query, b"signed content")
+        .set_payload("tampered content")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    let req = signed_request("GET", path, &format!("uploadId={}", upload_id), b"").to_request();
+    let body = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    assert!(!body.contains("<Part>"));
+}
+
+#[actix_web::test]
+async fn presigned_url_downloads_object() {
+    setup_credentials();
+    let app = init_app!(s3_serve::actix_configure);
+    let path = "/bucket/presigned.txt";
+    let upload_id = create_upload!(app, path);
+    let etag = upload_part!(app, path, upload_id, 1, b"presigned content");
+    let body = complete_body(&[(1, &etag)]);
+    let req = signed_request("POST", path, &format!("uploadId={}", upload_id), &body).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let amz_date = amz_date_now();
+    let query = format!(
+        "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Credential={}%2F{}%2Fus-east-1%2Fs3%2Faws4_request&X-Amz-Date={}&X-Amz-Expires=60&X-Amz-SignedHeaders=host",
+        ACCESS_KEY_ID,
+        &amz_date[..8],
+        amz_date
+    );
+    let signature = signature(SECRET_ACCESS_KEY, "GET", path, &query, &[("host", "localhost")], &amz_date);
+    let req = test::TestRequest::get()
+        .uri(&format!("{}?{}&X-Amz-Signature={}", path, query, signature))
+        .insert_header(("host", "localhost"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "presigned content");
+}
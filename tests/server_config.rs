@@ -0,0 +1,197 @@
+#![cfg(all(feature = "transfer", feature = "cloud-text", feature = "upload-large-file"))]
+
+mod common;
+
+use actix_web::{http::StatusCode, test, App};
+
+use web_server::server_config::{self, MountConfig, ServerConfig, ServiceKind};
+
+use common::{make_token, md5_hex, setup, setup_jwt, JWT_SECRET};
+
+fn mount(service: ServiceKind, prefix: &str) -> MountConfig {
+    MountConfig {
+        service,
+        prefix: String::from(prefix),
+    }
+}
+
+#[actix_web::test]
+async fn default_config_keeps_original_listeners() {
+    let mut config = ServerConfig::default();
+    let binds: Vec<&str> = config.listeners.iter().map(|listener| listener.bind.as_str()).collect();
+    assert_eq!(binds, vec!["0.0.0.0:16383", "0.0.0.0:16382"]);
+    assert!(config.mounts_service(ServiceKind::UploadLargeFile));
+    // 挂载上传大文件服务时必须配置校验密钥
+    assert!(config.validate().unwrap_err().contains("token verification key"));
+    config.token_verification.secret = Some(String::from(JWT_SECRET));
+    assert!(config.validate().is_ok());
+}
+
+#[actix_web::test]
+async fn default_config_starts_without_token_keys() {
+    setup();
+    // 没有配置文件时使用默认配置
+    let mut config = server_config::load_server_config("./missing-server-config.json").unwrap();
+    let _watches = server_config::prepare_services(&mut config).unwrap();
+    // 没有校验密钥时只跳过上传大文件服务
+    assert!(!config.mounts_service(ServiceKind::UploadLargeFile));
+    let binds: Vec<&str> = config.listeners.iter().map(|listener| listener.bind.as_str()).collect();
+    assert_eq!(binds, vec!["0.0.0.0:16383"]);
+
+    let mounts = config.listeners[0].services.clone();
+    let app = test::init_service(App::new().configure(|config| server_config::mount_services(config, &mounts))).await;
+    let req = test::TestRequest::post()
+        .uri("/cloud_text/add/default-config")
+        .set_payload("default config")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    // 配置密钥后照常挂载
+    let mut config = ServerConfig::default();
+    config.token_verification.secret = Some(String::from(JWT_SECRET));
+    let _watches = server_config::prepare_services(&mut config).unwrap();
+    assert!(config.mounts_service(ServiceKind::UploadLargeFile));
+
+    // 挂载了上传大文件服务却没有密钥时无法启动
+    let mut config = ServerConfig::default();
+    assert!(server_config::prepare_services(&mut config).is_err());
+    let path = std::env::temp_dir().join(format!("server-config-{}.json", md5_hex(b"no token keys")));
+    std::fs::write(&path, r#"{ "listeners": [{ "bind": "127.0.0.1:8081", "services": [{ "service": "uploadLargeFile" }] }] }"#)
+        .unwrap();
+    assert!(server_config::load_server_config(path.to_str().unwrap()).is_err());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[actix_web::test]
+async fn parses_listeners_from_json() {
+    let config: ServerConfig = serde_json::from_str(
+        r#"{
+            "listeners": [
+                {
+                    "bind": "127.0.0.1:8443",
+                    "tls": { "certPath": "cert.pem", "keyPath": "key.pem", "redirectBind": "127.0.0.1:8080" },
+                    "services": [{ "service": "uploadLargeFile", "prefix": "/large" }]
+                },
+                { "bind": "127.0.0.1:8081", "services": [{ "service": "cloudText" }] }
+            ],
+            "tokenVerification": { "secret": "secret" }
+        }"#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.listeners[0].services[0].prefix, "/large");
+    assert_eq!(config.listeners[1].services[0].prefix, "");
+    assert!(!config.mounts_service(ServiceKind::Transfer));
+}
+
+#[actix_web::test]
+async fn post_merge_steps_are_limited_to_upload_services() {
+    let config: ServerConfig = serde_json::from_str(
+        r#"{
+            "listeners": [{ "bind": "127.0.0.1:8081", "services": [{ "service": "uploadLargeFile" }] }],
+            "tokenVerification": { "secret": "secret" },
+            "postMerge": {
+                "uploadLargeFile": [{ "type": "checksum", "algorithm": "sha256" }, { "type": "contentType" }]
+            }
+        }"#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.post_merge[&ServiceKind::UploadLargeFile].len(), 2);
+
+    let config: ServerConfig = serde_json::from_str(
+        r#"{
+            "listeners": [{ "bind": "127.0.0.1:8081", "services": [{ "service": "cloudText" }] }],
+            "postMerge": { "cloudText": [{ "type": "contentType" }] }
+        }"#,
+    )
+    .unwrap();
+    assert!(config.validate().is_err());
+}
+
+fn listener_config(mounts: Vec<MountConfig>) -> ServerConfig {
+    let mut config = ServerConfig::default();
+    config.listeners.truncate(1);
+    config.listeners[0].services = mounts;
+    config.token_verification.secret = Some(String::from(JWT_SECRET));
+    config
+}
+
+#[actix_web::test]
+async fn overlapping_routes_are_rejected() {
+    // 上传服务注册相同的路由, 不能挂载在同一前缀下
+    let config = listener_config(vec![mount(ServiceKind::Transfer, ""), mount(ServiceKind::UploadLargeFile, "")]);
+    assert!(config.validate().unwrap_err().contains("overlap"));
+    let config = listener_config(vec![mount(ServiceKind::CloudText, "/a"), mount(ServiceKind::CloudText, "/a")]);
+    assert!(config.validate().is_err());
+    let config = listener_config(vec![
+        mount(ServiceKind::Transfer, ""),
+        mount(ServiceKind::UploadLargeFile, "/large"),
+        mount(ServiceKind::CloudText, ""),
+    ]);
+    assert!(config.validate().is_ok());
+
+    // S3 的路由匹配其前缀下的所有路径
+    #[cfg(feature = "s3")]
+    {
+        let config = listener_config(vec![mount(ServiceKind::S3, ""), mount(ServiceKind::Transfer, "/transfer")]);
+        assert!(config.validate().unwrap_err().contains("overlap"));
+        let config = listener_config(vec![mount(ServiceKind::Transfer, "/s3"), mount(ServiceKind::S3, "/s3")]);
+        assert!(config.validate().is_err());
+        let config = listener_config(vec![mount(ServiceKind::S3, "/s3"), mount(ServiceKind::Transfer, "/s3x")]);
+        assert!(config.validate().is_ok());
+    }
+}
+
+#[actix_web::test]
+async fn services_mount_under_prefixes() {
+    setup();
+    let mounts = vec![
+        mount(ServiceKind::Transfer, "/transfer"),
+        mount(ServiceKind::CloudText, "/transfer"),
+        mount(ServiceKind::UploadLargeFile, ""),
+    ];
+    let app = test::init_service(App::new().configure(|config| server_config::mount_services(config, &mounts))).await;
+
+    let req = test::TestRequest::post()
+        .uri("/transfer/cloud_text/add/prefixed")
+        .set_payload("prefixed text")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let req = test::TestRequest::get().uri("/transfer/cloud_text/get/prefixed").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "prefixed text");
+
+    // 未加前缀的路径不再可用
+    let req = test::TestRequest::get().uri("/cloud_text/get/prefixed").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn chunk_sessions_are_isolated_between_services() {
+    setup_jwt();
+    let token = make_token("bob/");
+    let mounts = vec![
+        mount(ServiceKind::Transfer, "/transfer"),
+        mount(ServiceKind::UploadLargeFile, "/large"),
+    ];
+    let app = test::init_service(App::new().configure(|config| server_config::mount_services(config, &mounts))).await;
+
+    let req = test::TestRequest::post()
+        .uri("/large/upload_chunk")
+        .insert_header(("token", token.as_str()))
+        .insert_header(("identify", "shared-identify"))
+        .insert_header(("chunkHash", md5_hex(b"private")))
+        .insert_header(("chunkIndex", "0"))
+        .insert_header(("chunksNumber", "1"))
+        .set_payload("private")
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    // 另一个服务使用相同 identify 看不到该会话
+    let req = test::TestRequest::post()
+        .uri("/transfer/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", "shared-identify"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "[]");
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "transfer")]
+
+mod common;
+
+use actix_web::test;
+use serde_json::Value;
+use tokio::time::{self, Duration};
+
+use web_server::{set_session_idle_time, set_session_max_lifetime, transfer_serve};
+
+use common::{init_app, md5_hex, settle, setup, upload_chunk_request, wait_until};
+
+const IDLE_TIME: u64 = 100;
+const MAX_LIFETIME: u64 = 250;
+
+// 同一进程中的测试使用相同的配置
+fn setup_expiry() {
+    setup();
+    set_session_idle_time(IDLE_TIME);
+    set_session_max_lifetime(MAX_LIFETIME);
+}
+
+fn status_request(identify: &str) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/upload_session_status")
+        .insert_header(("identify", identify))
+}
+
+#[actix_web::test]
+async fn status_reports_chunks_and_deadlines() {
+    setup_expiry();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "expiry-status";
+
+    let req = status_request(identify).to_request();
+    assert!(test::call_service(&app, req).await.status().is_client_error());
+
+    let req = upload_chunk_request(identify, b"status chunk", 1, 2).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = status_request(identify).to_request();
+    let status: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(status["chunksHash"][0], "empty");
+    assert_eq!(status["chunksHash"][1], md5_hex(b"status chunk"));
+    let expires_at = status["expiresAt"].as_u64().unwrap();
+    let max_expires_at = status["maxExpiresAt"].as_u64().unwrap();
+    assert!(expires_at < max_expires_at);
+    assert!(max_expires_at - expires_at >= MAX_LIFETIME - IDLE_TIME - 1);
+}
+
+#[actix_web::test]
+async fn each_chunk_postpones_expiry() {
+    setup_expiry();
+    time::pause();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "expiry-sliding";
+    let chunks: [&[u8]; 3] = [b"slow chunk 0", b"slow chunk 1", b"slow chunk 2"];
+
+    // 每个 chunk 的间隔小于闲置时间, 总时长超过闲置时间
+    for (index, chunk) in chunks.iter().take(2).enumerate() {
+        let req = upload_chunk_request(identify, chunk, index, chunks.len()).to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "true");
+        settle().await;
+        time::advance(Duration::from_secs(IDLE_TIME - 20)).await;
+        settle().await;
+    }
+    let req = status_request(identify).to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // 闲置超过配置的时间后清理
+    time::advance(Duration::from_secs(21)).await;
+    assert!(
+        wait_until(|| async {
+            let req = status_request(identify).to_request();
+            test::call_service(&app, req).await.status().is_client_error()
+        })
+        .await
+    );
+}
+
+#[actix_web::test]
+async fn active_session_expires_after_max_lifetime() {
+    setup_expiry();
+    time::pause();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "expiry-max-lifetime";
+
+    let mut elapsed = 0;
+    let mut index = 0;
+    while elapsed + 80 < MAX_LIFETIME {
+        let content = format!("lifetime chunk {}", index);
+        let req = upload_chunk_request(identify, content.as_bytes(), index, 10).to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "true");
+        settle().await;
+        time::advance(Duration::from_secs(80)).await;
+        settle().await;
+        elapsed += 80;
+        index += 1;
+    }
+    // 最后一个 chunk 之后未到闲置时间, 但已超过最长存活时间
+    time::advance(Duration::from_secs(MAX_LIFETIME - elapsed + 1)).await;
+    assert!(
+        wait_until(|| async {
+            let req = status_request(identify).to_request();
+            test::call_service(&app, req).await.status().is_client_error()
+        })
+        .await
+    );
+}
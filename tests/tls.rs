@@ -0,0 +1,102 @@
+use std::fs;
+use std::time::Duration;
+
+use actix_web::{http::header, http::StatusCode, test, web, App};
+
+use web_server::tls::{self, HttpsPort, ReloadableCertResolver, TlsConfig};
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn fixture_config() -> TlsConfig {
+    TlsConfig {
+        cert_path: fixture("localhost.crt"),
+        key_path: fixture("localhost.key"),
+        redirect_bind: None,
+    }
+}
+
+// PEM 文件中第一个证书的 DER 内容
+fn certificate_der(path: &str) -> Vec<u8> {
+    let mut reader = std::io::BufReader::new(fs::File::open(path).unwrap());
+    rustls_pemfile::certs(&mut reader).unwrap().remove(0)
+}
+
+#[actix_web::test]
+async fn loads_pem_certificate_and_key() {
+    assert!(tls::rustls_server_config(&fixture_config()).is_ok());
+}
+
+#[actix_web::test]
+async fn invalid_key_file_is_rejected() {
+    let mut config = fixture_config();
+    // 证书文件中没有私钥
+    config.key_path = config.cert_path.clone();
+    assert!(tls::rustls_server_config(&config).is_err());
+}
+
+#[actix_web::test]
+async fn http_requests_redirect_to_https() {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(HttpsPort(8443)))
+            .default_service(web::to(tls::redirect_to_https)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/fetch-file/123456?download=1")
+        .insert_header((header::HOST, "example.com:8080"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        resp.headers().get(header::LOCATION).unwrap(),
+        "https://example.com:8443/fetch-file/123456?download=1"
+    );
+}
+
+#[actix_web::test]
+async fn replaced_certificate_is_served() {
+    let dir = tempfile::tempdir().unwrap();
+    let cert_path = dir.path().join("server.crt").to_string_lossy().to_string();
+    let key_path = dir.path().join("server.key").to_string_lossy().to_string();
+    fs::copy(fixture("localhost.crt"), &cert_path).unwrap();
+    fs::copy(fixture("localhost.key"), &key_path).unwrap();
+
+    let (resolver, _watch) = ReloadableCertResolver::watch(&cert_path, &key_path).unwrap();
+    assert_eq!(resolver.certified_key().cert[0].0, certificate_der(&cert_path));
+
+    // 续期工具通常写入临时文件后替换
+    for (name, path) in [("localhost_renewed.key", &key_path), ("localhost_renewed.crt", &cert_path)] {
+        let temp_path = format!("{}.tmp", path);
+        fs::copy(fixture(name), &temp_path).unwrap();
+        fs::rename(&temp_path, path).unwrap();
+    }
+    let renewed = certificate_der(&fixture("localhost_renewed.crt"));
+    let mut reloaded = false;
+    // 需要等待 Hotwatch 的去抖延迟
+    for _ in 0..100 {
+        if resolver.certified_key().cert[0].0 == renewed {
+            reloaded = true;
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(reloaded);
+
+    // 无效的文件不会替换当前证书
+    fs::write(&cert_path, "not a certificate").unwrap();
+    actix_web::rt::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(resolver.certified_key().cert[0].0, renewed);
+}
+
+#[actix_web::test]
+async fn redirect_server_accepts_host_names() {
+    let server = tls::redirect_server("127.0.0.1:0", "localhost:8443").unwrap();
+    let server_handle = server.handle();
+    actix_web::rt::spawn(server);
+    server_handle.stop(false).await;
+    assert!(tls::redirect_server("127.0.0.1:0", "not an address").is_err());
+}
@@ -0,0 +1,352 @@
+#![cfg(feature = "transfer")]
+
+mod common;
+
+use actix_web::{body::to_bytes, http::StatusCode, test};
+use sha2::{Digest, Sha256};
+use tokio::time::{self, Duration};
+
+use web_server::transfer_serve;
+
+use common::{init_app, md5_chunk_file, md5_hex, settle, setup, upload_chunk_request, wait_until};
+
+const SURVIVAL_TIME: u64 = 7 * 86400;
+const CHUNK_SURVIVAL_TIME: u64 = 86400;
+
+#[actix_web::test]
+async fn upload_then_download_round_trip() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = test::TestRequest::post()
+        .uri("/upload")
+        .insert_header(("filename", "hello%20world.txt"))
+        .set_payload("hello transfer")
+        .to_request();
+    let fetch_code = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    assert_eq!(fetch_code.len(), 6);
+
+    let req = test::TestRequest::get().uri(&format!("/fetch-file/{}", fetch_code)).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(to_bytes(resp.into_body()).await.unwrap(), "hello transfer");
+}
+
+#[actix_web::test]
+async fn upload_without_filename_is_rejected() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = test::TestRequest::post().uri("/upload").set_payload("content").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn download_unknown_code_fails() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = test::TestRequest::get().uri("/fetch-file/1").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn chunked_upload_out_of_order_with_duplicates_then_merge() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "transfer-out-of-order";
+    let chunks: [&[u8]; 3] = [b"first chunk|", b"second chunk|", b"third chunk"];
+
+    // 乱序上传，并重复上传其中一个 chunk
+    for index in [2, 0, 2, 1] {
+        let req = upload_chunk_request(identify, chunks[index], index, chunks.len()).to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "true");
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", identify))
+        .to_request();
+    let hashes: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(hashes, chunks.iter().map(|chunk| md5_hex(chunk)).collect::<Vec<_>>());
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "merged.txt"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let fetch_code = String::from_utf8(to_bytes(resp.into_body()).await.unwrap().to_vec()).unwrap();
+
+    let req = test::TestRequest::get().uri(&format!("/fetch-file/{}", fetch_code)).to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert_eq!(body, "first chunk|second chunk|third chunk");
+
+    // 合并之后会话被清除
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", identify))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "[]");
+}
+
+#[actix_web::test]
+async fn chunk_hash_mismatch_is_rejected() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "transfer-hash-mismatch";
+
+    let req = upload_chunk_request(identify, b"real content", 0, 1)
+        .insert_header(("chunkHash", md5_hex(b"other content")))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", identify))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "[]");
+}
+
+#[actix_web::test]
+async fn chunk_upload_with_missing_headers_is_rejected() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = test::TestRequest::post()
+        .uri("/upload_chunk")
+        .insert_header(("identify", "transfer-missing-headers"))
+        .set_payload("content")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn merge_unknown_identify_fails() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", "transfer-never-uploaded"))
+        .insert_header(("fullPath", "never.txt"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn shared_file_expires_after_survival_time() {
+    setup();
+    time::pause();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = test::TestRequest::post()
+        .uri("/upload")
+        .insert_header(("filename", "expiring.txt"))
+        .set_payload("short lived")
+        .to_request();
+    let fetch_code = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    let uri = format!("/fetch-file/{}", fetch_code);
+    settle().await;
+
+    time::advance(Duration::from_secs(SURVIVAL_TIME - 1)).await;
+    let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    time::advance(Duration::from_secs(2)).await;
+    assert!(
+        wait_until(|| async {
+            let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+            resp.status() == StatusCode::BAD_REQUEST
+        })
+        .await
+    );
+}
+
+#[actix_web::test]
+async fn unmerged_chunks_expire_after_survival_time() {
+    setup();
+    time::pause();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "transfer-expiring-chunks";
+
+    let req = upload_chunk_request(identify, b"left behind", 0, 2).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    settle().await;
+
+    time::advance(Duration::from_secs(CHUNK_SURVIVAL_TIME + 1)).await;
+    assert!(
+        wait_until(|| async {
+            let req = test::TestRequest::post()
+                .uri("/fetch_uploaded_chunks_hashes")
+                .insert_header(("identify", identify))
+                .to_request();
+            test::call_and_read_body(&app, req).await == "[]"
+        })
+        .await
+    );
+}
+
+#[actix_web::test]
+async fn advertises_chunk_hash_algorithms() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = test::TestRequest::get().uri("/chunk_hash_algorithms").to_request();
+    let algorithms: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(algorithms, vec!["md5", "sha256", "blake3", "crc32c", "sha1"]);
+}
+
+#[actix_web::test]
+async fn chunked_upload_with_declared_hash_algorithms() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    type HashFn = fn(&[u8]) -> String;
+    let sha256_hex = |data: &[u8]| format!("{:x}", Sha256::digest(data));
+    let hashes: [(&str, HashFn); 3] = [
+        ("sha256", |data| format!("{:x}", Sha256::digest(data))),
+        ("blake3", |data| blake3::hash(data).to_hex().to_string()),
+        ("crc32c", |data| format!("{:08x}", crc32c::crc32c(data))),
+    ];
+    for (algorithm, hash) in hashes {
+        let identify = format!("transfer-{}", algorithm);
+        let chunks: [&[u8]; 2] = [b"hashed ", b"content"];
+        for (index, chunk) in chunks.iter().enumerate() {
+            // 大写 hex 同样接受
+            let req = upload_chunk_request(&identify, chunk, index, chunks.len())
+                .insert_header(("chunkHash", hash(chunk).to_uppercase()))
+                .insert_header(("hashAlgorithm", algorithm))
+                .to_request();
+            assert_eq!(test::call_and_read_body(&app, req).await, "true");
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/merge_chunks")
+            .insert_header(("identify", identify.as_str()))
+            .insert_header(("fullPath", "hashed.txt"))
+            .to_request();
+        let fetch_code = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+        let req = test::TestRequest::get().uri(&format!("/fetch-file/{}", fetch_code)).to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "hashed content");
+    }
+
+    // sha256 的 hash 不能当作 md5 提交
+    let req = upload_chunk_request("transfer-wrong-algorithm", b"content", 0, 1)
+        .insert_header(("chunkHash", sha256_hex(b"content")))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn session_hash_algorithm_cannot_change() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "transfer-mixed-algorithms";
+
+    let req = upload_chunk_request(identify, b"md5 chunk", 0, 2).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = upload_chunk_request(identify, b"sha chunk", 1, 2)
+        .insert_header(("chunkHash", format!("{:x}", Sha256::digest(b"sha chunk"))))
+        .insert_header(("hashAlgorithm", "sha256"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = upload_chunk_request(identify, b"other chunk", 1, 2)
+        .insert_header(("hashAlgorithm", "sha512"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+fn fetch_hashes_request(identify: &str) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", identify))
+}
+
+#[actix_web::test]
+async fn abort_requires_owner_key_and_removes_chunks() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "transfer-aborted";
+    let content = b"aborted chunk content";
+
+    let req = upload_chunk_request(identify, content, 0, 2)
+        .insert_header(("ownerKey", "secret"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    let chunk_file = md5_chunk_file(&md5_hex(content), &format!("transfer/{}", identify));
+    assert!(std::fs::metadata(&chunk_file).is_ok());
+
+    for owner_key in [None, Some("guess")] {
+        let mut req = test::TestRequest::post().uri("/abort_upload").insert_header(("identify", identify));
+        if let Some(owner_key) = owner_key {
+            req = req.insert_header(("ownerKey", owner_key));
+        }
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::BAD_REQUEST);
+    }
+    // 其他人看不到已上传的 chunk
+    let req = fetch_hashes_request(identify).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "[]");
+    let req = fetch_hashes_request(identify).insert_header(("ownerKey", "secret")).to_request();
+    assert_ne!(test::call_and_read_body(&app, req).await, "[]");
+
+    let req = test::TestRequest::post()
+        .uri("/abort_upload")
+        .insert_header(("identify", identify))
+        .insert_header(("ownerKey", "secret"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    let req = fetch_hashes_request(identify).insert_header(("ownerKey", "secret")).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "[]");
+    assert!(std::fs::metadata(&chunk_file).is_err());
+
+    // 已中止的会话不能再中止
+    let req = test::TestRequest::post()
+        .uri("/abort_upload")
+        .insert_header(("identify", identify))
+        .insert_header(("ownerKey", "secret"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn abort_cancels_expiration_of_the_session() {
+    setup();
+    time::pause();
+    let app = init_app!(transfer_serve::actix_configure);
+    let identify = "transfer-aborted-then-restarted";
+
+    let req = upload_chunk_request(identify, b"first attempt", 0, 2).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    settle().await;
+    time::advance(Duration::from_secs(CHUNK_SURVIVAL_TIME / 2)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/abort_upload")
+        .insert_header(("identify", identify))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    // 同一 identify 重新开始上传
+    let req = upload_chunk_request(identify, b"second attempt", 0, 2).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    settle().await;
+
+    // 旧会话的定时清理已取消, 不会删除新会话
+    time::advance(Duration::from_secs(CHUNK_SURVIVAL_TIME / 2 + 1)).await;
+    settle().await;
+    let req = fetch_hashes_request(identify).to_request();
+    assert_eq!(
+        test::call_and_read_body(&app, req).await,
+        format!(r#"["{}","empty"]"#, md5_hex(b"second attempt"))
+    );
+}
@@ -0,0 +1,205 @@
+#![cfg(feature = "transfer")]
+
+mod common;
+
+use actix_web::{dev::ServiceResponse, http::StatusCode, test};
+use base64::{engine::general_purpose, Engine as _};
+
+use web_server::transfer_serve;
+
+use common::{init_app, setup};
+
+fn header(response: &ServiceResponse, name: &str) -> Option<String> {
+    response.headers().get(name).map(|value| value.to_str().unwrap().to_string())
+}
+
+fn create_request(length: usize, file_name: &str) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/tus")
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", length.to_string()))
+        .insert_header((
+            "Upload-Metadata",
+            format!("filename {},filetype dGV4dC9wbGFpbg==", general_purpose::STANDARD.encode(file_name)),
+        ))
+}
+
+fn patch_request(location: &str, offset: usize, content: &[u8]) -> test::TestRequest {
+    test::TestRequest::patch()
+        .uri(location)
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Content-Type", "application/offset+octet-stream"))
+        .insert_header(("Upload-Offset", offset.to_string()))
+        .set_payload(content.to_vec())
+}
+
+fn head_request(location: &str) -> test::TestRequest {
+    test::TestRequest::default()
+        .method(actix_web::http::Method::HEAD)
+        .uri(location)
+        .insert_header(("Tus-Resumable", "1.0.0"))
+}
+
+fn sha2_digest(data: &[u8]) -> Vec<u8> {
+    use sha2::Digest;
+    sha2::Sha256::digest(data).to_vec()
+}
+
+fn sha1_digest(data: &[u8]) -> Vec<u8> {
+    use sha1::Digest;
+    sha1::Sha1::digest(data).to_vec()
+}
+
+macro_rules! create_upload {
+    ($app:expr, $length:expr, $file_name:expr) => {{
+        let response = test::call_service(&$app, create_request($length, $file_name).to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(header(&response, "Upload-Expires").is_some());
+        header(&response, "Location").unwrap()
+    }};
+}
+
+#[actix_web::test]
+async fn options_advertises_extensions() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = test::TestRequest::default()
+        .method(actix_web::http::Method::OPTIONS)
+        .uri("/tus")
+        .to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&response, "Tus-Version").unwrap(), "1.0.0");
+    assert_eq!(
+        header(&response, "Tus-Extension").unwrap(),
+        "creation,termination,checksum,expiration"
+    );
+    let algorithms = header(&response, "Tus-Checksum-Algorithm").unwrap();
+    assert!(algorithms.split(',').any(|algorithm| algorithm == "sha1"));
+    assert!(algorithms.split(',').any(|algorithm| algorithm == "sha256"));
+}
+
+#[actix_web::test]
+async fn upload_resumes_from_offset_and_creates_share() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let content = b"uploaded by a tus client";
+    let location = create_upload!(app, content.len(), "tus.txt");
+    assert!(location.starts_with("/tus/"));
+
+    let response = test::call_service(&app, patch_request(&location, 0, &content[..10]).to_request()).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&response, "Upload-Offset").unwrap(), "10");
+    assert!(header(&response, "Upload-Result").is_none());
+
+    // 客户端断开后通过 HEAD 查询偏移继续上传
+    let response = test::call_service(&app, head_request(&location).to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "Upload-Offset").unwrap(), "10");
+    assert_eq!(header(&response, "Upload-Length").unwrap(), content.len().to_string());
+    assert_eq!(header(&response, "Cache-Control").unwrap(), "no-store");
+
+    let response = test::call_service(&app, patch_request(&location, 10, &content[10..]).to_request()).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&response, "Upload-Offset").unwrap(), content.len().to_string());
+    let fetch_code = header(&response, "Upload-Result").unwrap();
+
+    let req = test::TestRequest::get().uri(&format!("/fetch-file/{}", fetch_code)).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, content.to_vec());
+
+    // 完成后 HEAD 仍能拿到结果
+    let response = test::call_service(&app, head_request(&location).to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "Upload-Offset").unwrap(), content.len().to_string());
+    assert_eq!(header(&response, "Upload-Result").unwrap(), fetch_code);
+}
+
+#[actix_web::test]
+async fn mismatched_offset_is_a_conflict() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let location = create_upload!(app, 8, "conflict.txt");
+
+    let response = test::call_service(&app, patch_request(&location, 4, b"late").to_request()).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(header(&response, "Upload-Offset").unwrap(), "0");
+
+    // 超出声明的长度
+    let response = test::call_service(&app, patch_request(&location, 0, b"too long content").to_request()).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let response = test::call_service(&app, head_request(&location).to_request()).await;
+    assert_eq!(header(&response, "Upload-Offset").unwrap(), "0");
+}
+
+#[actix_web::test]
+async fn checksum_mismatch_discards_patch() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let location = create_upload!(app, 11, "checksum.txt");
+
+    let wrong_checksum = format!("md5 {}", general_purpose::STANDARD.encode(md5::compute(b"other").0));
+    let req = patch_request(&location, 0, b"hello")
+        .insert_header(("Upload-Checksum", wrong_checksum))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status().as_u16(), 460);
+    let response = test::call_service(&app, head_request(&location).to_request()).await;
+    assert_eq!(header(&response, "Upload-Offset").unwrap(), "0");
+
+    let checksum = format!("sha256 {}", general_purpose::STANDARD.encode(sha2_digest(b"hello")));
+    let req = patch_request(&location, 0, b"hello")
+        .insert_header(("Upload-Checksum", checksum))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&response, "Upload-Offset").unwrap(), "5");
+
+    // 协议要求支持 sha1
+    let checksum = format!("sha1 {}", general_purpose::STANDARD.encode(sha1_digest(b" world")));
+    let req = patch_request(&location, 5, b" world")
+        .insert_header(("Upload-Checksum", checksum))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&response, "Upload-Offset").unwrap(), "11");
+}
+
+#[actix_web::test]
+async fn termination_removes_upload() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let location = create_upload!(app, 10, "terminated.txt");
+    let response = test::call_service(&app, patch_request(&location, 0, b"part").to_request()).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let req = test::TestRequest::delete()
+        .uri(&location)
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NO_CONTENT);
+
+    let response = test::call_service(&app, head_request(&location).to_request()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = test::call_service(&app, patch_request(&location, 4, b"more!!").to_request()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn requests_without_tus_version_are_rejected() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = test::TestRequest::post()
+        .uri("/tus")
+        .insert_header(("Upload-Length", "5"))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(header(&response, "Tus-Version").unwrap(), "1.0.0");
+
+    let location = create_upload!(app, 5, "content-type.txt");
+    let req = patch_request(&location, 0, b"hello")
+        .insert_header(("Content-Type", "text/plain"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
@@ -0,0 +1,454 @@
+#![cfg(feature = "upload-large-file")]
+
+mod common;
+
+use actix_web::{http::StatusCode, test};
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::json;
+
+use web_server::upload_large_file;
+
+use common::{init_app, make_token, md5_hex, setup_jwt, sign_token, upload_chunk_request};
+
+fn setup_tokens() -> String {
+    setup_jwt();
+    make_token("alice/")
+}
+
+#[actix_web::test]
+async fn requests_without_token_are_rejected() {
+    setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+
+    let req = upload_chunk_request("large-no-token", b"content", 0, 1).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", "large-no-token"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", "large-no-token"))
+        .insert_header(("fullPath", "a.txt"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn unknown_token_is_rejected() {
+    setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+    // 使用其他密钥签名
+    let unknown_token = sign_token(
+        &json!({ "data": { "userDirectory": "alice/" }, "exp": jsonwebtoken::get_current_timestamp() + 3600 }),
+        "other-secret",
+    );
+
+    let req = upload_chunk_request("large-unknown-token", b"content", 0, 1)
+        .insert_header(("token", unknown_token.as_str()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("token", unknown_token.as_str()))
+        .insert_header(("identify", "large-unknown-token"))
+        .insert_header(("fullPath", "a.txt"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn upload_and_merge_into_user_directory() {
+    let token = setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+    let identify = "large-merge";
+    let chunks: [&[u8]; 2] = [b"large ", b"file"];
+
+    for index in [1, 0] {
+        let req = upload_chunk_request(identify, chunks[index], index, chunks.len())
+            .insert_header(("token", token.as_str()))
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("token", token.as_str()))
+        .insert_header(("identify", identify))
+        .to_request();
+    let hashes: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(hashes, vec![md5_hex(chunks[0]), md5_hex(chunks[1])]);
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("token", token.as_str()))
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "docs/large%20file.txt"))
+        .to_request();
+    // 响应用户目录下的保存路径
+    assert_eq!(test::call_and_read_body(&app, req).await, "docs/large file.txt");
+
+    let merged = std::fs::read_to_string("./files/alice/docs/large file.txt").unwrap();
+    assert_eq!(merged, "large file");
+}
+
+#[actix_web::test]
+async fn sessions_of_other_users_are_not_accessible() {
+    let token = setup_tokens();
+    let other_token = make_token("mallory/");
+    let app = init_app!(upload_large_file::actix_configure);
+    let identify = "large-owned";
+    let chunks: [&[u8]; 2] = [b"owned ", b"file"];
+
+    let req = upload_chunk_request(identify, chunks[0], 0, chunks.len())
+        .insert_header(("token", token.as_str()))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    // 其他用户不能上传, 查询或合并
+    let req = upload_chunk_request(identify, chunks[1], 1, chunks.len())
+        .insert_header(("token", other_token.as_str()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("token", other_token.as_str()))
+        .insert_header(("identify", identify))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "[]");
+
+    let req = test::TestRequest::post()
+        .uri("/upload_session_status")
+        .insert_header(("token", other_token.as_str()))
+        .insert_header(("identify", identify))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = upload_chunk_request(identify, chunks[1], 1, chunks.len())
+        .insert_header(("token", token.as_str()))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("token", other_token.as_str()))
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "owned.txt"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    assert!(!std::path::Path::new("./files/mallory/owned.txt").exists());
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("token", token.as_str()))
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "owned.txt"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "owned.txt");
+    assert_eq!(std::fs::read_to_string("./files/alice/owned.txt").unwrap(), "owned file");
+}
+
+#[actix_web::test]
+async fn full_path_cannot_leave_user_directory() {
+    let token = setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+    let identify = "large-traversal";
+    let content = b"escaped";
+
+    let req = upload_chunk_request(identify, content, 0, 1)
+        .insert_header(("token", token.as_str()))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    for full_path in ["../mallory/escaped.txt", "docs/../../mallory/escaped.txt", "%2Fescaped.txt", ".."] {
+        let req = test::TestRequest::post()
+            .uri("/merge_chunks")
+            .insert_header(("token", token.as_str()))
+            .insert_header(("identify", identify))
+            .insert_header(("fullPath", full_path))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST, "{}", full_path);
+    }
+    assert!(!std::path::Path::new("./files/mallory/escaped.txt").exists());
+
+    // 会话保留, 可以使用正常的路径合并
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("token", token.as_str()))
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "./docs/escaped.txt"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "docs/escaped.txt");
+}
+
+#[actix_web::test]
+async fn merge_with_missing_chunk_fails() {
+    let token = setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+    let identify = "large-incomplete";
+
+    let req = upload_chunk_request(identify, b"only one", 0, 2)
+        .insert_header(("token", token.as_str()))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("token", token.as_str()))
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "incomplete.txt"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn async_merge_into_user_directory() {
+    let token = setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+    let identify = "large-async";
+
+    let req = upload_chunk_request(identify, b"async large", 0, 1)
+        .insert_header(("token", token.as_str()))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks_async")
+        .insert_header(("token", token.as_str()))
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "async.txt"))
+        .to_request();
+    let job_id = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+
+    // 查询状态同样需要 token
+    let req = test::TestRequest::post()
+        .uri("/merge_status")
+        .insert_header(("jobId", job_id.as_str()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let finished = common::wait_until(|| {
+        let req = test::TestRequest::post()
+            .uri("/merge_status")
+            .insert_header(("token", token.as_str()))
+            .insert_header(("jobId", job_id.as_str()))
+            .to_request();
+        let app = &app;
+        async move {
+            let status: serde_json::Value = test::call_and_read_body_json(app, req).await;
+            status["state"] == "succeeded" && status["result"] == "async.txt"
+        }
+    })
+    .await;
+    assert!(finished);
+    assert_eq!(std::fs::read_to_string("./files/alice/async.txt").unwrap(), "async large");
+}
+
+#[actix_web::test]
+async fn tus_upload_into_user_directory() {
+    let token = setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+    let metadata = format!("filename {}", general_purpose::STANDARD.encode("tus.txt"));
+
+    let req = test::TestRequest::post()
+        .uri("/tus")
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", "9"))
+        .insert_header(("Upload-Metadata", metadata.as_str()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = test::TestRequest::post()
+        .uri("/tus")
+        .insert_header(("token", token.as_str()))
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", "9"))
+        .insert_header(("Upload-Metadata", metadata.as_str()))
+        .to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers().get("Location").unwrap().to_str().unwrap().to_string();
+
+    let req = test::TestRequest::patch()
+        .uri(&location)
+        .insert_header(("token", token.as_str()))
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Content-Type", "application/offset+octet-stream"))
+        .insert_header(("Upload-Offset", "0"))
+        .set_payload("tus large")
+        .to_request();
+    let response = test::call_service(&app, req).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers().get("Upload-Result").unwrap(), "tus.txt");
+    assert_eq!(std::fs::read_to_string("./files/alice/tus.txt").unwrap(), "tus large");
+}
+
+#[actix_web::test]
+async fn only_the_uploading_user_can_abort() {
+    let token = setup_tokens();
+    let other_token = make_token("bob/");
+    let app = init_app!(upload_large_file::actix_configure);
+    let identify = "large-aborted";
+
+    let req = upload_chunk_request(identify, b"large aborted chunk", 0, 2)
+        .insert_header(("token", token.as_str()))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let abort_request = |token: Option<&str>| {
+        let req = test::TestRequest::post().uri("/abort_upload").insert_header(("identify", identify));
+        match token {
+            Some(token) => req.insert_header(("token", token)).to_request(),
+            None => req.to_request(),
+        }
+    };
+    assert_eq!(test::call_service(&app, abort_request(None)).await.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        test::call_service(&app, abort_request(Some(&other_token))).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(test::call_and_read_body(&app, abort_request(Some(&token))).await, "true");
+
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", identify))
+        .insert_header(("token", token.as_str()))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "[]");
+}
+
+// 上传单个分片并合并到指定路径, 返回响应状态和内容
+macro_rules! upload_and_merge {
+    ($app:expr, $token:expr, $identify:expr, $content:expr, $full_path:expr, $policy:expr) => {{
+        let req = upload_chunk_request($identify, $content, 0, 1)
+            .insert_header(("token", $token))
+            .to_request();
+        assert_eq!(test::call_and_read_body(&$app, req).await, "true");
+        let mut req = test::TestRequest::post()
+            .uri("/merge_chunks")
+            .insert_header(("token", $token))
+            .insert_header(("identify", $identify))
+            .insert_header(("fullPath", $full_path));
+        if let Some(policy) = $policy {
+            req = req.insert_header(("conflictPolicy", policy));
+        }
+        let response = test::call_service(&$app, req.to_request()).await;
+        let status = response.status();
+        (status, String::from_utf8(test::read_body(response).await.to_vec()).unwrap())
+    }};
+}
+
+#[actix_web::test]
+async fn existing_file_is_renamed_by_default() {
+    let token = setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+
+    let (_, path) = upload_and_merge!(app, token.as_str(), "conflict-rename-1", b"first", "rename.txt", None::<&str>);
+    assert_eq!(path, "rename.txt");
+    let (_, path) = upload_and_merge!(app, token.as_str(), "conflict-rename-2", b"second", "rename.txt", None::<&str>);
+    assert_eq!(path, "rename (1).txt");
+    let (_, path) = upload_and_merge!(app, token.as_str(), "conflict-rename-3", b"third", "rename.txt", Some("rename"));
+    assert_eq!(path, "rename (2).txt");
+
+    assert_eq!(std::fs::read_to_string("./files/alice/rename.txt").unwrap(), "first");
+    assert_eq!(std::fs::read_to_string("./files/alice/rename (1).txt").unwrap(), "second");
+    assert_eq!(std::fs::read_to_string("./files/alice/rename (2).txt").unwrap(), "third");
+}
+
+#[actix_web::test]
+async fn fail_policy_keeps_existing_file_and_session() {
+    let token = setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+
+    upload_and_merge!(app, token.as_str(), "conflict-fail-1", b"kept", "fail.txt", None::<&str>);
+    let (status, _) = upload_and_merge!(app, token.as_str(), "conflict-fail-2", b"rejected", "fail.txt", Some("fail"));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(std::fs::read_to_string("./files/alice/fail.txt").unwrap(), "kept");
+
+    // 会话保留, 可以换一个路径重新合并
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("token", token.as_str()))
+        .insert_header(("identify", "conflict-fail-2"))
+        .insert_header(("fullPath", "fail-other.txt"))
+        .insert_header(("conflictPolicy", "fail"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "fail-other.txt");
+    assert_eq!(std::fs::read_to_string("./files/alice/fail-other.txt").unwrap(), "rejected");
+}
+
+#[actix_web::test]
+async fn overwrite_and_version_policies() {
+    let token = setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+
+    upload_and_merge!(app, token.as_str(), "conflict-overwrite-1", b"old", "overwrite.txt", None::<&str>);
+    let (_, path) =
+        upload_and_merge!(app, token.as_str(), "conflict-overwrite-2", b"new", "overwrite.txt", Some("overwrite"));
+    assert_eq!(path, "overwrite.txt");
+    assert_eq!(std::fs::read_to_string("./files/alice/overwrite.txt").unwrap(), "new");
+
+    upload_and_merge!(app, token.as_str(), "conflict-version-1", b"v0", "docs/version.txt", None::<&str>);
+    let (_, path) =
+        upload_and_merge!(app, token.as_str(), "conflict-version-2", b"v1", "docs/version.txt", Some("version"));
+    // 新内容保存到原路径, 旧内容保留为版本文件
+    assert_eq!(path, "docs/version.txt");
+    assert_eq!(std::fs::read_to_string("./files/alice/docs/version.txt").unwrap(), "v1");
+    assert_eq!(std::fs::read_to_string("./files/alice/docs/version.v1.txt").unwrap(), "v0");
+
+    let (status, _) =
+        upload_and_merge!(app, token.as_str(), "conflict-invalid", b"invalid", "invalid.txt", Some("replace"));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn conflict_policies_never_overwrite_when_names_run_out() {
+    let token = setup_tokens();
+    let app = init_app!(upload_large_file::actix_configure);
+    std::fs::create_dir_all("./files/alice/full").unwrap();
+    std::fs::write("./files/alice/full/rename.txt", "kept").unwrap();
+    std::fs::write("./files/alice/full/version.txt", "kept").unwrap();
+    // 占用所有可用的序号和版本
+    for index in 1..10000 {
+        std::fs::hard_link("./files/alice/full/rename.txt", format!("./files/alice/full/rename ({}).txt", index)).unwrap();
+        std::fs::hard_link("./files/alice/full/version.txt", format!("./files/alice/full/version.v{}.txt", index)).unwrap();
+    }
+
+    let (status, _) =
+        upload_and_merge!(app, token.as_str(), "conflict-full-rename", b"new", "full/rename.txt", Some("rename"));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) =
+        upload_and_merge!(app, token.as_str(), "conflict-full-version", b"new", "full/version.txt", Some("version"));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    assert_eq!(std::fs::read_to_string("./files/alice/full/rename.txt").unwrap(), "kept");
+    assert_eq!(std::fs::read_to_string("./files/alice/full/version.txt").unwrap(), "kept");
+}
+
+#[actix_web::test]
+async fn user_directory_prefix_does_not_reach_other_users() {
+    setup_tokens();
+    let token = make_token("al");
+    let app = init_app!(upload_large_file::actix_configure);
+    let identify = "large-prefix";
+
+    let req = upload_chunk_request(identify, b"prefix", 0, 1)
+        .insert_header(("token", token.as_str()))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("token", token.as_str()))
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", "ice/prefix.txt"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "ice/prefix.txt");
+    assert_eq!(std::fs::read_to_string("./files/al/ice/prefix.txt").unwrap(), "prefix");
+    assert!(!std::path::Path::new("./files/alice/prefix.txt").exists());
+}
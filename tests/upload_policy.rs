@@ -0,0 +1,123 @@
+#![cfg(any(feature = "transfer", feature = "upload-large-file"))]
+
+mod common;
+
+use actix_web::{http::StatusCode, test, web, HttpRequest};
+use futures::future::BoxFuture;
+use serde_json::json;
+
+use web_server::upload_policy::{configure_upload_routes, MergeTarget, UploadPolicy};
+
+use common::{init_app, md5_hex, setup, upload_chunk_request};
+
+// 只有 apiKey 为 secret 的请求可以上传, 文件保存在 ./files/archive/ 下, 最大 16 字节
+struct ArchivePolicy;
+
+struct ArchiveTarget;
+
+impl MergeTarget for ArchiveTarget {
+    fn save_path(&self, base_path: &str, full_path: &str) -> String {
+        format!("{}archive/{}", base_path, full_path)
+    }
+
+    fn after_merge(&self, file_path: String) -> BoxFuture<'static, Result<String, String>> {
+        Box::pin(async move { Ok(format!("archived {}", file_path)) })
+    }
+}
+
+impl UploadPolicy for ArchivePolicy {
+    type Grant = ();
+    type Target = ArchiveTarget;
+
+    const SERVICE: &'static str = "archive";
+
+    async fn authorize(&self, req: &HttpRequest) -> Result<Self::Grant, Box<dyn std::error::Error>> {
+        match req.headers().get("apiKey").and_then(|key| key.to_str().ok()) {
+            Some("secret") => Ok(()),
+            _ => Err("apiKey is invalid".into()),
+        }
+    }
+
+    fn max_file_size(&self, _grant: &Self::Grant) -> Option<u64> {
+        Some(16)
+    }
+
+    async fn merge_target(&self, _grant: Self::Grant) -> Self::Target {
+        ArchiveTarget
+    }
+}
+
+fn configure(config: &mut web::ServiceConfig) {
+    configure_upload_routes(config, &ArchivePolicy);
+}
+
+fn merge_request(identify: &str, full_path: &str) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("apiKey", "secret"))
+        .insert_header(("identify", identify))
+        .insert_header(("fullPath", full_path))
+}
+
+#[actix_web::test]
+async fn custom_policy_controls_authorization_and_target() {
+    setup();
+    let app = init_app!(configure);
+
+    let req = upload_chunk_request("policy-upload", b"archived", 0, 1)
+        .insert_header(("apiKey", "wrong"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = upload_chunk_request("policy-upload", b"archived", 0, 1)
+        .insert_header(("apiKey", "secret"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = merge_request("policy-upload", "report.txt").to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "archived ./files/archive/report.txt");
+    assert_eq!(std::fs::read_to_string("./files/archive/report.txt").unwrap(), "archived");
+}
+
+#[actix_web::test]
+async fn custom_policy_limits_file_size() {
+    setup();
+    let app = init_app!(configure);
+
+    // 声明的大小超过限制时不创建会话
+    let req = test::TestRequest::post()
+        .uri("/create_upload_session")
+        .insert_header(("apiKey", "secret"))
+        .set_json(json!({ "fileName": "large.txt", "fileSize": 17, "chunkSize": 17, "chunksNumber": 1 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    // 未声明大小的会话在合并时检查, 失败后会话保留
+    let chunks: [&[u8]; 2] = [b"0123456789", b"abcdefghij"];
+    for (index, chunk) in chunks.iter().enumerate() {
+        let req = upload_chunk_request("policy-large", chunk, index, 2)
+            .insert_header(("apiKey", "secret"))
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    }
+    let req = merge_request("policy-large", "large.txt").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    assert!(std::fs::metadata("./files/archive/large.txt").is_err());
+
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("apiKey", "secret"))
+        .insert_header(("identify", "policy-large"))
+        .to_request();
+    let hashes: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(hashes, vec![md5_hex(chunks[0]), md5_hex(chunks[1])]);
+
+    // tus 上传的长度同样受限
+    let req = test::TestRequest::post()
+        .uri("/tus")
+        .insert_header(("apiKey", "secret"))
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", "17"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
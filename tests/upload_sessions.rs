@@ -0,0 +1,293 @@
+#![cfg(feature = "transfer")]
+
+mod common;
+
+use actix_web::{http::StatusCode, test};
+use serde_json::{json, Value};
+
+use web_server::{set_min_chunk_size, transfer_serve};
+
+use common::{init_app, md5_hex, upload_chunk_request};
+
+// 本测试进程中使用很小的 chunk
+fn setup() {
+    common::setup();
+    set_min_chunk_size(4);
+}
+
+fn create_session_request(body: Value) -> test::TestRequest {
+    test::TestRequest::post().uri("/create_upload_session").set_json(body)
+}
+
+#[actix_web::test]
+async fn created_session_uploads_and_merges_with_declared_name() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = create_session_request(json!({
+        "fileName": "declared.txt",
+        "fileSize": 10,
+        "chunkSize": 4,
+        "chunksNumber": 3,
+    }))
+    .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let session_id = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert_eq!(session_id.len(), 32);
+
+    for (index, chunk) in [&b"0123"[..], b"4567", b"89"].iter().enumerate() {
+        let req = upload_chunk_request(&session_id, chunk, index, 3).to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    }
+
+    // 未提供 fullPath, 使用声明的文件名
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", session_id.as_str()))
+        .to_request();
+    let fetch_code = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    let req = test::TestRequest::get().uri(&format!("/fetch-file/{}", fetch_code)).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp
+        .headers()
+        .get("content-disposition")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .contains("declared.txt"));
+    assert_eq!(test::read_body(resp).await, "0123456789");
+}
+
+#[actix_web::test]
+async fn inconsistent_layout_is_rejected() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    for body in [
+        json!({ "fileName": "a.txt", "fileSize": 10, "chunkSize": 4, "chunksNumber": 2 }),
+        json!({ "fileName": "a.txt", "fileSize": 10, "chunkSize": 0, "chunksNumber": 1 }),
+        json!({ "fileName": "", "fileSize": 10, "chunkSize": 10, "chunksNumber": 1 }),
+        json!({ "fileName": "a.txt", "fileSize": 10, "chunkSize": 10, "chunksNumber": 1, "hashAlgorithm": "sha512" }),
+        json!({ "fileName": "a.txt", "fileSize": 1u64 << 40, "chunkSize": 1 << 20, "chunksNumber": 1 << 20 }),
+        // chunk 小于最小尺寸, 或数量过多
+        json!({ "fileName": "a.txt", "fileSize": 10, "chunkSize": 2, "chunksNumber": 5 }),
+        json!({ "fileName": "a.txt", "fileSize": 1 << 20, "chunkSize": 4, "chunksNumber": 1 << 18 }),
+    ] {
+        let resp = test::call_service(&app, create_session_request(body).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[actix_web::test]
+async fn chunks_are_validated_against_session_layout() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = create_session_request(json!({
+        "fileName": "validated.txt",
+        "fileSize": 6,
+        "chunkSize": 4,
+        "chunksNumber": 2,
+    }))
+    .to_request();
+    let session_id = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+
+    // 超出范围的 chunkIndex
+    let req = upload_chunk_request(&session_id, b"ab", 2, 2).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    // 与会话不一致的 chunksNumber
+    let req = upload_chunk_request(&session_id, b"ab", 1, 3).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    // 大小不符合布局
+    let req = upload_chunk_request(&session_id, b"abc", 0, 2).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    let req = upload_chunk_request(&session_id, b"abc", 1, 2).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = upload_chunk_request(&session_id, b"ab", 1, 2).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", session_id.as_str()))
+        .to_request();
+    let hashes: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(hashes, vec![String::from("empty"), md5_hex(b"ab")]);
+}
+
+#[actix_web::test]
+async fn implicit_session_rejects_out_of_range_chunks() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = upload_chunk_request("implicit-out-of-range", b"chunk", 5, 2).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let req = upload_chunk_request("implicit-out-of-range", b"chunk", 0, 2).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    let req = upload_chunk_request("implicit-out-of-range", b"other", 1, 4).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn oversized_chunks_number_is_rejected() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    // 在分配会话前拒绝
+    let req = upload_chunk_request("implicit-oversized", b"chunk", 0, 1_000_000_000_000).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    let req = test::TestRequest::post()
+        .uri("/fetch_uploaded_chunks_hashes")
+        .insert_header(("identify", "implicit-oversized"))
+        .to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "[]");
+}
+
+fn merging_leftovers() -> usize {
+    std::fs::read_dir("./files")
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().ends_with(".merging"))
+        .count()
+}
+
+#[actix_web::test]
+async fn merge_reports_missing_chunks() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = upload_chunk_request("merge-missing", b"middle", 1, 3).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "true");
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", "merge-missing"))
+        .insert_header(("fullPath", "missing.txt"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(test::read_body(resp).await, "missing chunks: [0,2]");
+    assert!(!std::fs::read_dir("./files")
+        .unwrap()
+        .any(|entry| entry.unwrap().file_name().to_string_lossy().starts_with("missing.txt")));
+}
+
+#[actix_web::test]
+async fn merge_verifies_whole_file_hash() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = create_session_request(json!({
+        "fileName": "verified.txt",
+        "fileSize": 8,
+        "chunkSize": 4,
+        "chunksNumber": 2,
+        "fileHash": md5_hex(b"not the content"),
+    }))
+    .to_request();
+    let session_id = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    for (index, chunk) in [&b"veri"[..], b"fied"].iter().enumerate() {
+        let req = upload_chunk_request(&session_id, chunk, index, 2).to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", session_id.as_str()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(test::read_body(resp).await, "file hash not match");
+    assert_eq!(merging_leftovers(), 0);
+
+    // 会话保留, 可以使用正确的 hash 重新合并
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", session_id.as_str()))
+        .insert_header(("fileHash", md5_hex(b"verified")))
+        .to_request();
+    let fetch_code = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    let req = test::TestRequest::get().uri(&format!("/fetch-file/{}", fetch_code)).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "verified");
+}
+
+#[actix_web::test]
+async fn preallocated_session_writes_chunks_in_place() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+
+    let req = create_session_request(json!({
+        "fileName": "preallocated.txt",
+        "fileSize": 10,
+        "chunkSize": 4,
+        "chunksNumber": 3,
+        "fileHash": md5_hex(b"0123456789"),
+        "assembly": "preallocated",
+    }))
+    .to_request();
+    let session_id = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    let assembling = format!("./chunks/{}.assembling", md5_hex(format!("transfer/{}", session_id).as_bytes()));
+    assert_eq!(std::fs::metadata(&assembling).unwrap().len(), 10);
+
+    // hash 不一致的 chunk 不会被标记为已写入
+    let req = upload_chunk_request(&session_id, b"4567", 1, 3)
+        .insert_header(("chunkHash", md5_hex(b"other")))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    for (index, chunk) in [(2, &b"89"[..]), (0, b"0123"), (1, b"4567")] {
+        let req = upload_chunk_request(&session_id, chunk, index, 3).to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    }
+    assert_eq!(std::fs::read(&assembling).unwrap(), b"0123456789");
+    assert!(!std::fs::read_dir("./chunks").unwrap().any(|entry| {
+        let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+        name.starts_with(&md5_hex(b"0123")) && name.ends_with(".chunk")
+    }));
+
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", session_id.as_str()))
+        .to_request();
+    let fetch_code = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    let req = test::TestRequest::get().uri(&format!("/fetch-file/{}", fetch_code)).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, "0123456789");
+    assert!(std::fs::metadata(&assembling).is_err());
+}
+
+#[actix_web::test]
+async fn preallocated_session_without_hash_is_not_reread() {
+    setup();
+    let app = init_app!(transfer_serve::actix_configure);
+    let content = b"preallocated unhashed";
+
+    let req = create_session_request(json!({
+        "fileName": "unhashed.txt",
+        "fileSize": content.len(),
+        "chunkSize": 8,
+        "chunksNumber": 3,
+        "assembly": "preallocated",
+    }))
+    .to_request();
+    let session_id = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    for (index, chunk) in content.chunks(8).enumerate() {
+        let req = upload_chunk_request(&session_id, chunk, index, 3).to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "true");
+    }
+    let req = test::TestRequest::post()
+        .uri("/merge_chunks")
+        .insert_header(("identify", session_id.as_str()))
+        .to_request();
+    let fetch_code = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    let req = test::TestRequest::get().uri(&format!("/fetch-file/{}", fetch_code)).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, &content[..]);
+
+    // 未计算整个文件的 hash, 不记录用于秒传
+    let req = test::TestRequest::post()
+        .uri("/instant_upload_challenge")
+        .set_json(json!({ "fileHash": md5_hex(content), "fileSize": content.len() }))
+        .to_request();
+    let challenge: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(challenge["exists"], false);
+}
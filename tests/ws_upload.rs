@@ -0,0 +1,120 @@
+#![cfg(all(feature = "transfer", feature = "upload-large-file"))]
+
+mod common;
+
+use std::net::SocketAddr;
+
+use actix_web::{rt, App, HttpServer};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, Message},
+    MaybeTlsStream,
+    WebSocketStream,
+};
+
+use web_server::{transfer_serve, upload_large_file};
+
+use common::{make_token, md5_hex, setup, setup_jwt};
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// WebSocket 需要真实的连接, 在随机端口上启动服务
+fn start_server(configure: fn(&mut actix_web::web::ServiceConfig)) -> SocketAddr {
+    let server = HttpServer::new(move || App::new().configure(configure))
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+    let addr = server.addrs()[0];
+    rt::spawn(server.run());
+    addr
+}
+
+// 4 字节大端序号, 1 字节 hash 长度, hash, 内容
+fn chunk_frame(index: u32, hash: &str, content: &[u8]) -> Message {
+    let mut frame = index.to_be_bytes().to_vec();
+    frame.push(hash.len() as u8);
+    frame.extend_from_slice(hash.as_bytes());
+    frame.extend_from_slice(content);
+    Message::Binary(frame)
+}
+
+async fn send_json(client: &mut Client, message: Value) {
+    client.send(Message::Text(message.to_string())).await.unwrap();
+}
+
+async fn receive_json(client: &mut Client) -> Value {
+    loop {
+        match client.next().await.unwrap().unwrap() {
+            Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+            Message::Ping(_) | Message::Pong(_) => continue,
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+}
+
+#[actix_web::test]
+async fn chunks_are_acknowledged_and_merged() {
+    setup();
+    let addr = start_server(transfer_serve::actix_configure);
+    let (mut client, _) = connect_async(format!("ws://{}/upload_ws", addr)).await.unwrap();
+
+    // 打开会话前的 chunk 被拒绝
+    client.send(chunk_frame(0, &md5_hex(b"early"), b"early")).await.unwrap();
+    assert_eq!(receive_json(&mut client).await["type"], "error");
+
+    send_json(&mut client, json!({ "type": "open", "identify": "ws-upload", "chunksNumber": 2 })).await;
+    let opened = receive_json(&mut client).await;
+    assert_eq!(opened, json!({ "type": "opened", "identify": "ws-upload", "chunksHash": [] }));
+
+    let chunks: [&[u8]; 2] = [b"over ", b"websocket"];
+    for (index, chunk) in chunks.iter().enumerate() {
+        let hash = md5_hex(chunk).to_uppercase();
+        client.send(chunk_frame(index as u32, &hash, chunk)).await.unwrap();
+        let ack = receive_json(&mut client).await;
+        assert_eq!(ack, json!({ "type": "ack", "chunkIndex": index, "chunkHash": md5_hex(chunk) }));
+    }
+
+    // hash 不一致时返回错误, 连接仍可继续使用
+    client.send(chunk_frame(1, &md5_hex(b"other"), b"websocket")).await.unwrap();
+    let error = receive_json(&mut client).await;
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["chunkIndex"], 1);
+
+    send_json(&mut client, json!({ "type": "merge", "fullPath": "ws.txt" })).await;
+    let merged = receive_json(&mut client).await;
+    assert_eq!(merged["type"], "merged");
+    let fetch_code = merged["result"].as_str().unwrap();
+    assert_eq!(std::fs::read_to_string(format!("./files/ws.txt{}", fetch_code)).unwrap(), "over websocket");
+
+    // 合并后需要重新打开会话
+    send_json(&mut client, json!({ "type": "merge", "fullPath": "ws.txt" })).await;
+    assert_eq!(receive_json(&mut client).await["type"], "error");
+}
+
+#[actix_web::test]
+async fn upgrade_requires_authorization() {
+    setup_jwt();
+    let token = make_token("carol/");
+    let addr = start_server(upload_large_file::actix_configure);
+
+    assert!(connect_async(format!("ws://{}/upload_ws", addr)).await.is_err());
+
+    let mut request = format!("ws://{}/upload_ws", addr).into_client_request().unwrap();
+    request.headers_mut().insert("token", token.parse().unwrap());
+    let (mut client, _) = connect_async(request).await.unwrap();
+
+    send_json(&mut client, json!({ "type": "open", "identify": "ws-large", "chunksNumber": 1 })).await;
+    assert_eq!(receive_json(&mut client).await["type"], "opened");
+    client.send(chunk_frame(0, &md5_hex(b"in user directory"), b"in user directory")).await.unwrap();
+    assert_eq!(receive_json(&mut client).await["type"], "ack");
+
+    send_json(&mut client, json!({ "type": "merge", "fullPath": "docs/ws file.txt" })).await;
+    assert_eq!(receive_json(&mut client).await, json!({ "type": "merged", "result": "docs/ws file.txt" }));
+    assert_eq!(
+        std::fs::read_to_string("./files/carol/docs/ws file.txt").unwrap(),
+        "in user directory"
+    );
+}